@@ -0,0 +1,258 @@
+use crate::fs::{collect_note_paths, get_app_data_dir, read_note};
+use crate::ollama::chat_json;
+use crate::related::{note_vector, Pooling};
+use crate::vector_store::{dot, normalize};
+use chrono::{DateTime, Utc};
+use ollama_rs::generation::chat::ChatMessage;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::fs;
+use std::path::PathBuf;
+
+const MAX_ITERATIONS: usize = 50;
+// Titles closest to a cluster's centre shown to the model when naming it
+const NAMING_TITLES: usize = 12;
+
+const NAMING_PROMPT: &str = "You name groups of notes. Given the titles of notes in one group, \
+reply with a JSON object {\"name\": \"...\"} holding a short topic name of one to four words \
+that describes the group.";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClusterMember {
+  pub id: String,
+  pub path: String,
+  pub title: String,
+  // Cosine similarity to the cluster centre
+  pub similarity: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopicCluster {
+  pub id: usize,
+  pub name: String,
+  // Directory name the cluster's notes could be moved into
+  pub folder: String,
+  pub notes: Vec<ClusterMember>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VaultClusters {
+  pub clusters: Vec<TopicCluster>,
+  // Notes that have no embeddings yet
+  pub unclustered: Vec<String>,
+  pub clustered_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ClusterName {
+  name: String,
+}
+
+fn clusters_path() -> Result<PathBuf, String> {
+  Ok(get_app_data_dir()?.join("clusters.json"))
+}
+
+// Default cluster count for `count` notes
+fn default_k(count: usize) -> usize {
+  ((count as f32 / 2.0).sqrt().round() as usize).clamp(1, 12)
+}
+
+// Spherical k-means over unit vectors. Centres are seeded farthest-first so results are
+// deterministic. Returns the cluster index of every vector and the final centres.
+pub fn kmeans(vectors: &[Vec<f32>], k: usize) -> (Vec<usize>, Vec<Vec<f32>>) {
+  // At least one centre, so every vector is assigned somewhere
+  let k = k.max(1).min(vectors.len());
+  if k == 0 {
+    return (Vec::new(), Vec::new());
+  }
+
+  let mut centres = vec![vectors[0].clone()];
+  while centres.len() < k {
+    let farthest = vectors
+      .iter()
+      .max_by(|a, b| {
+        let closest = |v: &Vec<f32>| {
+          centres
+            .iter()
+            .map(|centre| dot(v, centre))
+            .fold(f32::MIN, f32::max)
+        };
+        closest(b).total_cmp(&closest(a))
+      })
+      .unwrap();
+    centres.push(farthest.clone());
+  }
+
+  let mut assignments = vec![usize::MAX; vectors.len()];
+  for _ in 0..MAX_ITERATIONS {
+    let mut changed = false;
+    for (vector, assignment) in vectors.iter().zip(assignments.iter_mut()) {
+      let best = centres
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| dot(vector, a).total_cmp(&dot(vector, b)))
+        .map(|(index, _)| index)
+        .unwrap();
+      if *assignment != best {
+        *assignment = best;
+        changed = true;
+      }
+    }
+    if !changed {
+      break;
+    }
+
+    for (index, centre) in centres.iter_mut().enumerate() {
+      let mut sum = vec![0.0; centre.len()];
+      let mut members = 0;
+      for (vector, _) in vectors
+        .iter()
+        .zip(&assignments)
+        .filter(|(_, assignment)| **assignment == index)
+      {
+        sum
+          .iter_mut()
+          .zip(vector)
+          .for_each(|(acc, value)| *acc += value);
+        members += 1;
+      }
+      // An empty cluster keeps its previous centre
+      if members > 0 {
+        *centre = normalize(sum);
+      }
+    }
+  }
+  (assignments, centres)
+}
+
+// Folder name of the `number`th cluster; names without letters or digits get a generated one
+fn folder_name(name: &str, number: usize) -> String {
+  let slug: Vec<String> = name
+    .split(|c: char| !c.is_alphanumeric())
+    .filter(|word| !word.is_empty())
+    .map(str::to_lowercase)
+    .collect();
+  if slug.is_empty() {
+    return format!("topic-{}", number);
+  }
+  slug.join("-")
+}
+
+async fn name_cluster(members: &[ClusterMember]) -> Result<String, String> {
+  let titles: Vec<&str> = members
+    .iter()
+    .take(NAMING_TITLES)
+    .map(|member| member.title.as_str())
+    .collect();
+  let messages = vec![
+    ChatMessage::system(NAMING_PROMPT.to_string()),
+    ChatMessage::user(format!("Titles:\n{}", titles.join("\n"))),
+  ];
+  let reply: ClusterName = chat_json(messages).await?;
+  Ok(reply.name.trim().to_string())
+}
+
+// Cluster the vault's notes by topic from their embeddings and name every cluster with the chat
+// model. `k` defaults to a count based on the vault size. The result is saved so the UI can show
+// it as smart folders without clustering again.
+#[tauri::command]
+pub async fn cluster_notes(k: Option<usize>, name: Option<bool>) -> Result<VaultClusters, String> {
+  if k == Some(0) {
+    return Err("The number of clusters must be at least 1".to_string());
+  }
+  let mut members = Vec::new();
+  let mut vectors = Vec::new();
+  let mut unclustered = Vec::new();
+  for path in collect_note_paths()? {
+    let Some(path_str) = path.to_str() else {
+      continue;
+    };
+    let Ok(note) = read_note(path_str) else {
+      continue;
+    };
+    // Notes without an id have no embeddings yet
+    let Some(id) = note["id"].as_str().map(str::to_string) else {
+      unclustered.push(path_str.to_string());
+      continue;
+    };
+    match note_vector(&id, None, Pooling::Mean).await? {
+      Some(vector) => {
+        let title = note["title"].as_str().unwrap_or("").to_string();
+        members.push((id, path_str.to_string(), title));
+        vectors.push(normalize(vector));
+      }
+      None => unclustered.push(path_str.to_string()),
+    }
+  }
+
+  let k = k.unwrap_or_else(|| default_k(vectors.len()));
+  let (assignments, centres) = kmeans(&vectors, k);
+
+  let mut clusters = Vec::new();
+  for (index, centre) in centres.iter().enumerate() {
+    let mut notes: Vec<ClusterMember> = members
+      .iter()
+      .zip(&vectors)
+      .zip(&assignments)
+      .filter(|(_, assignment)| **assignment == index)
+      .map(|(((id, path, title), vector), _)| ClusterMember {
+        id: id.clone(),
+        path: path.clone(),
+        title: title.clone(),
+        similarity: dot(vector, centre),
+      })
+      .collect();
+    if notes.is_empty() {
+      continue;
+    }
+    notes.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+
+    let fallback = format!("Topic {}", clusters.len() + 1);
+    let cluster_name = if name.unwrap_or(true) {
+      match name_cluster(&notes).await {
+        Ok(cluster_name) if !cluster_name.is_empty() => cluster_name,
+        Ok(_) => fallback,
+        Err(e) => {
+          eprintln!("Failed to name cluster: {}", e);
+          fallback
+        }
+      }
+    } else {
+      fallback
+    };
+    clusters.push(TopicCluster {
+      id: clusters.len(),
+      folder: folder_name(&cluster_name, clusters.len() + 1),
+      name: cluster_name,
+      notes,
+    });
+  }
+  // Largest clusters first
+  clusters.sort_by_key(|cluster| Reverse(cluster.notes.len()));
+  for (id, cluster) in clusters.iter_mut().enumerate() {
+    cluster.id = id;
+  }
+
+  let result = VaultClusters {
+    clusters,
+    unclustered,
+    clustered_at: Utc::now(),
+  };
+  let content = serde_json::to_string_pretty(&result)
+    .map_err(|e| format!("Failed to serialize clusters: {}", e))?;
+  fs::write(clusters_path()?, content).map_err(|e| format!("Failed to save clusters: {}", e))?;
+  Ok(result)
+}
+
+// The clusters from the last `cluster_notes` run, or None if the vault was never clustered
+#[tauri::command]
+pub fn get_smart_folders() -> Result<Option<VaultClusters>, String> {
+  let path = clusters_path()?;
+  if !path.exists() {
+    return Ok(None);
+  }
+  let content = fs::read_to_string(&path).map_err(|e| format!("Failed to read clusters: {}", e))?;
+  serde_json::from_str(&content)
+    .map(Some)
+    .map_err(|e| format!("Failed to parse clusters: {}", e))
+}
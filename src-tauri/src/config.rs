@@ -1,19 +1,200 @@
+use crate::embedder::reset_embedder;
+use crate::fs::{get_app_data_dir, read_file, write_file};
+use crate::mongo::reset_mongo_client;
+use crate::ollama::reset_ollama_client;
+use crate::qdrant::reset_qdrant_client;
+use crate::vector_store::reset_vector_store;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
-#[derive(Debug, Serialize, Deserialize)]
+// Which backend stores note embeddings
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorStoreKind {
+  // Qdrant server, either a container run by the app or an external instance
+  #[default]
+  Qdrant,
+  // Brute-force index kept in a file under the app data directory
+  Local,
+}
+
+// How the services the app depends on are run
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ContainerRuntimeKind {
+  // Use Docker, or Podman if Docker isn't available
+  #[default]
+  Auto,
+  Docker,
+  Podman,
+  // Start no containers and use the services at `qdrant_url` and `ollama_url`
+  External,
+}
+
+// Which API produces note embeddings
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbeddingProvider {
+  #[default]
+  Ollama,
+  // Any server exposing the OpenAI `/embeddings` API, e.g. llama.cpp or vLLM
+  #[serde(rename = "openai")]
+  OpenAi,
+}
+
+// How vectors in a collection are compared
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorDistance {
+  Cosine,
+  #[default]
+  Dot,
+  Euclid,
+}
+
+// Where notes are synced to
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncRemoteKind {
+  // Sync is off
+  #[default]
+  None,
+  // A plain directory, e.g. on a USB drive or network share
+  Folder,
+  Mongo,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SyncConfig {
+  pub remote: SyncRemoteKind,
+  // Directory used by the folder remote
+  pub folder: String,
+  pub mongo_uri: String,
+  pub mongo_database: String,
+  // Encrypt notes with a key derived from a passphrase before they reach the remote
+  pub encrypt: bool,
+}
+
+impl Default for SyncConfig {
+  fn default() -> Self {
+    SyncConfig {
+      remote: SyncRemoteKind::default(),
+      folder: String::new(),
+      mongo_uri: "mongodb://localhost:27017".to_string(),
+      mongo_database: "elab".to_string(),
+      encrypt: false,
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct EmbeddingConfig {
+  pub provider: EmbeddingProvider,
+  // Base URL of the OpenAI-compatible server; the Ollama provider uses `ollama_url`
+  pub endpoint: String,
+  pub api_key: Option<String>,
+  pub model: String,
+  pub dimension: u64,
+  pub distance: VectorDistance,
+}
+
+impl Default for EmbeddingConfig {
+  fn default() -> Self {
+    EmbeddingConfig {
+      provider: EmbeddingProvider::default(),
+      endpoint: "http://localhost:8080/v1".to_string(),
+      api_key: None,
+      model: "granite-embedding:30m".to_string(),
+      dimension: 384,
+      distance: VectorDistance::default(),
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct UserConfig {
-  theme: String,
-  default_save_location: String,
-  autosave_interval: u32,
-  // Add fields as needed
+  pub theme: String,
+  pub default_save_location: String,
+  pub autosave_interval: u32,
+  pub vector_store: VectorStoreKind,
+  pub container_runtime: ContainerRuntimeKind,
+  // gRPC URL of the Qdrant server
+  pub qdrant_url: String,
+  pub ollama_url: String,
+  // Run Ollama in a container instead of using a server installed separately
+  pub manage_ollama: bool,
+  pub embedding: EmbeddingConfig,
+  // Ollama model used for answering questions and writing assistance
+  pub chat_model: String,
+  // Ollama model that can read images, used to transcribe handwriting
+  pub vision_model: String,
+  // Generate summaries and title suggestions for notes in the background
+  pub auto_summary: bool,
+  // Seconds a note must go unedited before it is summarized
+  pub summary_idle_secs: u64,
+  pub sync: SyncConfig,
+}
+
+impl Default for UserConfig {
+  fn default() -> Self {
+    UserConfig {
+      theme: "system".to_string(),
+      default_save_location: String::new(),
+      autosave_interval: 30,
+      vector_store: VectorStoreKind::default(),
+      container_runtime: ContainerRuntimeKind::default(),
+      qdrant_url: "http://localhost:6334".to_string(),
+      ollama_url: "http://localhost:11434".to_string(),
+      manage_ollama: false,
+      embedding: EmbeddingConfig::default(),
+      chat_model: "llama3.2".to_string(),
+      vision_model: "llama3.2-vision".to_string(),
+      auto_summary: false,
+      summary_idle_secs: 300,
+      sync: SyncConfig::default(),
+    }
+  }
+}
+
+fn config_path() -> Result<PathBuf, String> {
+  Ok(get_app_data_dir()?.join("config.json"))
+}
+
+// Current configuration, falling back to defaults when no config file has been saved yet
+pub fn get_config() -> Result<UserConfig, String> {
+  let path = config_path()?;
+  if !path.exists() {
+    return Ok(UserConfig::default());
+  }
+  let path_str = path
+    .to_str()
+    .ok_or_else(|| "Invalid path encoding".to_string())?;
+  let content = read_file(path_str)?;
+  serde_json::from_str(&content).map_err(|e| format!("Failed to parse config: {}", e))
 }
 
 #[tauri::command]
-pub fn load_config(app_handle: tauri::AppHandle) -> Result<UserConfig, String> {
-  // Load config from app data directory
+pub fn load_config() -> Result<UserConfig, String> {
+  get_config()
 }
 
 #[tauri::command]
-pub fn save_config(app_handle: tauri::AppHandle, config: UserConfig) -> Result<(), String> {
-  // Save config to app data directory
+pub fn save_config(config: UserConfig) -> Result<(), String> {
+  let path = config_path()?;
+  let path_str = path
+    .to_str()
+    .ok_or_else(|| "Invalid path encoding".to_string())?;
+  let content = serde_json::to_string_pretty(&config)
+    .map_err(|e| format!("Failed to serialize config: {}", e))?;
+  write_file(path_str, &content)?;
+  // Pick up changed backends and endpoints on their next use
+  reset_vector_store();
+  reset_embedder();
+  reset_ollama_client();
+  reset_qdrant_client();
+  reset_mongo_client();
+  Ok(())
 }
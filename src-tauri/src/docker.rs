@@ -1,129 +1,304 @@
 // src-tauri/src/docker.rs
-use std::process::Command;
+use crate::config::ContainerRuntimeKind;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::Arc;
 
-// --- Helper functions ---
+// --- Container runtimes ---
 
-pub fn start_container(container_name: &str) -> Result<(), String> {
-  let output = Command::new("docker")
-    .args(["start", container_name])
-    .output()
-    .map_err(|e| format!("Failed to execute docker command: {}", e))?;
+// A container engine driven through its command line. Docker and Podman accept the same
+// commands, so every operation has a shared default built on `command`.
+pub trait ContainerRuntime: Send + Sync {
+  // Name of the executable, also used in messages
+  fn binary(&self) -> &'static str;
 
-  if output.status.success() {
-    Ok(())
-  } else {
-    let error = String::from_utf8_lossy(&output.stderr);
-    Err(format!("Docker start failed: {}", error))
+  fn command(&self) -> Command {
+    Command::new(self.binary())
   }
-}
 
-pub fn stop_container(container_name: &str) -> Result<(), String> {
-  println!("Attempting to stop container: {}", container_name); // For debugging
-  let output = Command::new("docker")
-    .args(["stop", container_name])
-    .output()
-    .map_err(|e| format!("Failed to execute docker stop command: {}", e))?;
+  // Run a command to completion, turning a missing executable into a readable error
+  fn run(&self, args: &[&str]) -> Result<Output, String> {
+    self.command().args(args).output().map_err(|e| {
+      format!(
+        "Failed to execute {} command (is {} installed?): {}",
+        self.binary(),
+        self.binary(),
+        e
+      )
+    })
+  }
 
-  if output.status.success() {
-    println!("Successfully stopped container: {}", container_name); // For debugging
-    Ok(())
-  } else {
-    // It's possible the container was already stopped, which might show in stderr.
-    // You could check stderr for specific messages if needed, but often just logging is okay.
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    // Don't necessarily return an error if it was already stopped.
-    // Depending on requirements, you might ignore certain errors.
-    eprintln!(
-      "Docker stop command for '{}' finished with status: {:?}. Stderr: {}",
-      container_name,
-      output.status.code(),
-      stderr
-    );
-    // For now, let's return Ok even if it failed, as it might already be stopped.
-    // Adjust error handling based on whether you *need* it to be running before stopping.
+  // Whether the executable exists and can reach its engine
+  fn available(&self) -> bool {
+    self
+      .run(&["version"])
+      .map(|output| output.status.success())
+      .unwrap_or(false)
+  }
+
+  // `-v` argument mounting `host` at `container`
+  fn volume_arg(&self, host: &Path, container: &str) -> String {
+    format!("{}:{}", host.display(), container)
+  }
+
+  fn start_container(&self, container_name: &str) -> Result<(), String> {
+    let output = self.run(&["start", container_name])?;
+
+    if output.status.success() {
+      Ok(())
+    } else {
+      let error = String::from_utf8_lossy(&output.stderr);
+      Err(format!("{} start failed: {}", self.binary(), error))
+    }
+  }
+
+  fn stop_container(&self, container_name: &str) -> Result<(), String> {
+    let output = self.run(&["stop", container_name])?;
+    if !output.status.success() {
+      // It's possible the container was already stopped, which might show in stderr,
+      // so this is only logged
+      let stderr = String::from_utf8_lossy(&output.stderr);
+      eprintln!(
+        "{} stop command for '{}' finished with status: {:?}. Stderr: {}",
+        self.binary(),
+        container_name,
+        output.status.code(),
+        stderr
+      );
+    }
     Ok(())
-    // Or, if you want to propagate the error strictly:
-    // Err(format!("Docker stop failed: {}", stderr))
   }
-}
 
-pub fn check_container_exists(container_name: &str) -> bool {
-  let output = Command::new("docker")
-    .args([
-      "ps",
-      "-a",
-      "--filter",
-      &format!("name={}", container_name),
-      "--format",
-      "{{.Names}}",
-    ])
-    .output();
-
-  match output {
-    Ok(output) => {
-      let stdout = String::from_utf8_lossy(&output.stdout);
-      !stdout.trim().is_empty()
+  // Whether the container exists and is currently running
+  fn container_running(&self, container_name: &str) -> bool {
+    match self.run(&["inspect", "-f", "{{.State.Running}}", container_name]) {
+      Ok(output) => {
+        output.status.success() && String::from_utf8_lossy(&output.stdout).trim() == "true"
+      }
+      Err(_) => false,
+    }
+  }
+
+  fn check_container_exists(&self, container_name: &str) -> bool {
+    let filter = format!("name={}", container_name);
+    match self.run(&["ps", "-a", "--filter", &filter, "--format", "{{.Names}}"]) {
+      Ok(output) => {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        !stdout.trim().is_empty()
+      }
+      Err(_) => false,
     }
-    Err(_) => false,
   }
-}
 
-pub fn create_container_if_needed(
-  image: &str,
-  container_name: &str,
-  ports: &[(&str, &str)],
-) -> Result<(), String> {
-  if !check_container_exists(container_name) {
-    let port_mappings: Vec<String> = ports
-      .iter()
-      .map(|(host, container)| format!("{}:{}", host, container))
-      .collect();
-    let mut args = vec!["run", "-d", "--name", container_name];
-
-    // Add port mappings
-    for mapping in &port_mappings {
-      args.push("-p");
-      args.push(mapping.as_str());
+  fn container_label(&self, container_name: &str, label: &str) -> Option<String> {
+    let format = format!("{{{{index .Config.Labels \"{}\"}}}}", label);
+    let output = self.run(&["inspect", "-f", &format, container_name]).ok()?;
+    if !output.status.success() {
+      return None;
     }
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    // Missing labels print as "<no value>"
+    (!value.is_empty() && value != "<no value>").then_some(value)
+  }
+
+  // Whether the container was created from exactly this spec
+  fn container_current(&self, spec: &ContainerSpec) -> bool {
+    self.container_label(&spec.name, SPEC_LABEL).as_deref() == Some(spec.hash().as_str())
+  }
 
-    args.push(image);
+  fn remove_container(&self, container_name: &str) -> Result<(), String> {
+    let output = self.run(&["rm", "-f", container_name])?;
+    if output.status.success() {
+      Ok(())
+    } else {
+      let error = String::from_utf8_lossy(&output.stderr);
+      Err(format!("{} rm failed: {}", self.binary(), error))
+    }
+  }
 
-    let output = Command::new("docker")
-      .args(&args)
-      .output()
-      .map_err(|e| format!("Failed to create container: {}", e))?;
+  // Copy data out of a container into each empty volume directory, so data an older container
+  // kept in its writable layer survives the container being recreated
+  fn copy_data_to_volumes(&self, spec: &ContainerSpec) -> Result<(), String> {
+    for (host, container) in &spec.volumes {
+      let empty = fs::read_dir(host)
+        .map(|mut entries| entries.next().is_none())
+        .unwrap_or(true);
+      if !empty {
+        continue;
+      }
+      fs::create_dir_all(host)
+        .map_err(|e| format!("Failed to create {}: {}", host.display(), e))?;
+      let source = format!("{}:{}/.", spec.name, container);
+      let host_str = host
+        .to_str()
+        .ok_or_else(|| format!("Invalid volume path {}", host.display()))?;
+      let output = self.run(&["cp", &source, host_str])?;
+      if !output.status.success() {
+        return Err(format!(
+          "Could not copy {} out of {}: {}",
+          container,
+          spec.name,
+          String::from_utf8_lossy(&output.stderr)
+        ));
+      }
+    }
+    Ok(())
+  }
 
+  fn create_container(&self, spec: &ContainerSpec) -> Result<(), String> {
+    let mut args = vec![
+      "run".to_string(),
+      "-d".to_string(),
+      "--name".to_string(),
+      spec.name.clone(),
+      "--label".to_string(),
+      format!("{}={}", SPEC_LABEL, spec.hash()),
+    ];
+    for (host, container) in &spec.ports {
+      args.push("-p".to_string());
+      args.push(format!("{}:{}", host, container));
+    }
+    for (host, container) in &spec.volumes {
+      fs::create_dir_all(host).map_err(|e| {
+        format!(
+          "Failed to create volume directory {}: {}",
+          host.display(),
+          e
+        )
+      })?;
+      args.push("-v".to_string());
+      args.push(self.volume_arg(host, container));
+    }
+    for (key, value) in &spec.env {
+      args.push("-e".to_string());
+      args.push(format!("{}={}", key, value));
+    }
+    args.push(spec.image.clone());
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let output = self.run(&args)?;
     if !output.status.success() {
       let error = String::from_utf8_lossy(&output.stderr);
       return Err(format!("Container creation failed: {}", error));
     }
-  } else {
-    // If container exists, try to start it
-    start_container(container_name)?;
+    Ok(())
   }
 
-  Ok(())
+  // Create the container described by `spec` if it doesn't exist, then make sure it is running.
+  // A container created from a different spec is replaced, after copying its data into the
+  // spec's volumes.
+  fn ensure_container(&self, spec: &ContainerSpec) -> Result<(), String> {
+    if !self.check_container_exists(&spec.name) {
+      return self.create_container(spec);
+    }
+    if self.container_current(spec) {
+      return self.start_container(&spec.name);
+    }
+
+    eprintln!("Recreating container {} for its new spec", spec.name);
+    // The old container stays until its data is safe in the volumes
+    self
+      .copy_data_to_volumes(spec)
+      .map_err(|e| format!("Kept container {}: {}", spec.name, e))?;
+    self.remove_container(&spec.name)?;
+    self.create_container(spec)
+  }
 }
 
-// --- Tauri commands ---
+pub struct Docker;
 
-#[tauri::command]
-pub fn start_docker_container(container_name: String) -> Result<(), String> {
-  // Call the helper function directly
-  start_container(&container_name)
+impl ContainerRuntime for Docker {
+  fn binary(&self) -> &'static str {
+    "docker"
+  }
+}
+
+pub struct Podman;
+
+impl ContainerRuntime for Podman {
+  fn binary(&self) -> &'static str {
+    "podman"
+  }
+
+  // Podman usually runs with SELinux enforcing, which needs bind mounts relabeled
+  fn volume_arg(&self, host: &Path, container: &str) -> String {
+    format!("{}:{}:Z", host.display(), container)
+  }
 }
 
+// Runtimes that are installed and reachable, in order of preference
+pub fn available_runtimes() -> Vec<Arc<dyn ContainerRuntime>> {
+  let runtimes: Vec<Arc<dyn ContainerRuntime>> = vec![Arc::new(Docker), Arc::new(Podman)];
+  runtimes
+    .into_iter()
+    .filter(|runtime| runtime.available())
+    .collect()
+}
+
+// Runtime selected in the config, detecting one for `Auto`.
+// Returns None in external-service mode, where the app starts no containers.
+pub fn select_runtime(
+  kind: ContainerRuntimeKind,
+) -> Result<Option<Arc<dyn ContainerRuntime>>, String> {
+  let runtime: Arc<dyn ContainerRuntime> = match kind {
+    ContainerRuntimeKind::External => return Ok(None),
+    ContainerRuntimeKind::Auto => {
+      return available_runtimes()
+        .into_iter()
+        .next()
+        .map(Some)
+        .ok_or_else(|| {
+          "No container runtime found. Install Docker or Podman, or point the app at \
+running services in settings"
+            .to_string()
+        })
+    }
+    ContainerRuntimeKind::Docker => Arc::new(Docker),
+    ContainerRuntimeKind::Podman => Arc::new(Podman),
+  };
+  if !runtime.available() {
+    return Err(format!(
+      "{} is not installed or its service is not running",
+      runtime.binary()
+    ));
+  }
+  Ok(Some(runtime))
+}
+
+// Label holding a hash of the spec a container was created from
+const SPEC_LABEL: &str = "elab.spec";
+
+// Everything needed to create a container for a managed service
+#[derive(Serialize, Debug, Clone)]
+pub struct ContainerSpec {
+  // Image with a pinned tag so upgrades happen deliberately
+  pub image: String,
+  pub name: String,
+  // (host port, container port) pairs
+  pub ports: Vec<(String, String)>,
+  // (host directory, container path) bind mounts
+  pub volumes: Vec<(PathBuf, String)>,
+  pub env: Vec<(String, String)>,
+}
+
+impl ContainerSpec {
+  // Hash identifying this spec, stored on the container to detect outdated containers
+  fn hash(&self) -> String {
+    let spec = serde_json::to_string(self).unwrap_or_default();
+    format!("{:x}", Sha256::digest(spec.as_bytes()))
+  }
+}
+
+// --- Tauri commands ---
+
+// Names of the container runtimes found on this machine, for the settings screen
 #[tauri::command]
-pub fn setup_and_start_container(
-  image: String,
-  container_name: String,
-  ports: Vec<(String, String)>,
-) -> Result<(), String> {
-  let ports_ref: Vec<(&str, &str)> = ports
+pub fn detect_container_runtimes() -> Vec<String> {
+  available_runtimes()
     .iter()
-    .map(|(host, container)| (host.as_str(), container.as_str()))
-    .collect();
-
-  // Call the helper function directly
-  create_container_if_needed(&image, &container_name, &ports_ref)
+    .map(|runtime| runtime.binary().to_string())
+    .collect()
 }
@@ -0,0 +1,386 @@
+use crate::fs::{
+  collect_note_paths, delete_note, note_page_texts, note_path, read_note, write_note,
+};
+use crate::qdrant::index_note_file;
+use crate::refactor::retarget_links;
+use crate::related::{note_vector, Pooling};
+use crate::vector_store::cosine;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use uuid::Uuid;
+
+// Minimum similarity for two notes to count as near-duplicates
+const DEFAULT_THRESHOLD: f32 = 0.92;
+// Number of hash functions in a MinHash signature
+const MINHASH_SIZE: usize = 64;
+// Words per shingle
+const SHINGLE_SIZE: usize = 3;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateKind {
+  // Identical page text
+  Exact,
+  // Embeddings with high cosine similarity
+  Embedding,
+  // Similar text by MinHash, for notes without embeddings
+  MinHash,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DuplicateNote {
+  pub id: String,
+  pub path: String,
+  pub title: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DuplicateGroup {
+  pub kind: DuplicateKind,
+  // Lowest similarity between linked notes of the group, 1 for exact duplicates
+  pub similarity: f32,
+  pub notes: Vec<DuplicateNote>,
+}
+
+struct NoteText {
+  id: String,
+  path: String,
+  title: String,
+  text: String,
+}
+
+// Page text of a note, lowercased with whitespace collapsed so formatting differences are ignored
+fn normalized_text(note: &Value) -> String {
+  let texts: Vec<String> = note_page_texts(note)
+    .into_iter()
+    .map(|(_, text)| text)
+    .collect();
+  texts
+    .join(" ")
+    .split_whitespace()
+    .collect::<Vec<_>>()
+    .join(" ")
+    .to_lowercase()
+}
+
+pub fn content_hash(text: &str) -> String {
+  format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+fn minhash(text: &str) -> Option<Vec<u64>> {
+  let words: Vec<&str> = text.split(' ').collect();
+  if words.len() < SHINGLE_SIZE {
+    return None;
+  }
+  let mut signature = vec![u64::MAX; MINHASH_SIZE];
+  for shingle in words.windows(SHINGLE_SIZE) {
+    for (seed, min) in signature.iter_mut().enumerate() {
+      let mut hasher = DefaultHasher::new();
+      seed.hash(&mut hasher);
+      shingle.hash(&mut hasher);
+      *min = (*min).min(hasher.finish());
+    }
+  }
+  Some(signature)
+}
+
+// Estimated Jaccard similarity of the shingle sets behind two signatures
+fn minhash_similarity(a: &[u64], b: &[u64]) -> f32 {
+  let equal = a.iter().zip(b).filter(|(x, y)| x == y).count();
+  equal as f32 / MINHASH_SIZE as f32
+}
+
+fn find_root(parents: &mut [usize], index: usize) -> usize {
+  let mut root = index;
+  while parents[root] != root {
+    root = parents[root];
+  }
+  parents[index] = root;
+  root
+}
+
+// Group similar pairs of notes into connected groups
+fn group_pairs(
+  notes: &[NoteText],
+  pairs: &[(usize, usize, f32)],
+  kind: DuplicateKind,
+) -> Vec<DuplicateGroup> {
+  let mut parents: Vec<usize> = (0..notes.len()).collect();
+  for &(a, b, _) in pairs {
+    let (root_a, root_b) = (find_root(&mut parents, a), find_root(&mut parents, b));
+    parents[root_a] = root_b;
+  }
+
+  let mut groups: HashMap<usize, (Vec<usize>, f32)> = HashMap::new();
+  for &(a, _, similarity) in pairs {
+    let root = find_root(&mut parents, a);
+    let group = groups.entry(root).or_insert((Vec::new(), 1.0));
+    group.1 = group.1.min(similarity);
+  }
+  for index in 0..notes.len() {
+    let root = find_root(&mut parents, index);
+    if let Some(group) = groups.get_mut(&root) {
+      group.0.push(index);
+    }
+  }
+
+  groups
+    .into_values()
+    .map(|(members, similarity)| DuplicateGroup {
+      kind,
+      similarity,
+      notes: members
+        .into_iter()
+        .map(|index| DuplicateNote {
+          id: notes[index].id.clone(),
+          path: notes[index].path.clone(),
+          title: notes[index].title.clone(),
+        })
+        .collect(),
+    })
+    .collect()
+}
+
+// Find notes with identical text and notes that are nearly the same. Near-duplicates are compared
+// by embedding when both notes are indexed and by MinHash over their text otherwise.
+// `threshold` is the minimum similarity between 0 and 1.
+#[tauri::command]
+pub async fn find_duplicates(threshold: Option<f32>) -> Result<Vec<DuplicateGroup>, String> {
+  let threshold = threshold.unwrap_or(DEFAULT_THRESHOLD);
+  let mut notes = Vec::new();
+  for path in collect_note_paths()? {
+    let Some(path_str) = path.to_str() else {
+      continue;
+    };
+    let Ok(note) = read_note(path_str) else {
+      continue;
+    };
+    let text = normalized_text(&note);
+    if text.is_empty() {
+      continue;
+    }
+    notes.push(NoteText {
+      id: note["id"].as_str().unwrap_or("").to_string(),
+      path: path_str.to_string(),
+      title: note["title"].as_str().unwrap_or("").to_string(),
+      text,
+    });
+  }
+
+  let mut by_hash: HashMap<String, Vec<usize>> = HashMap::new();
+  for (index, note) in notes.iter().enumerate() {
+    by_hash
+      .entry(content_hash(&note.text))
+      .or_default()
+      .push(index);
+  }
+  let mut exact_pairs = Vec::new();
+  for members in by_hash.values() {
+    for &other in &members[1..] {
+      exact_pairs.push((members[0], other, 1.0));
+    }
+  }
+  // Exact duplicates are reported once, not again as near-duplicates
+  let exact_copies: HashSet<usize> = exact_pairs.iter().map(|&(_, b, _)| b).collect();
+
+  // Without a reachable vector store only exact and MinHash matches are reported
+  let mut vectors = Vec::with_capacity(notes.len());
+  let mut store_available = true;
+  for note in &notes {
+    if !store_available {
+      vectors.push(None);
+      continue;
+    }
+    match note_vector(&note.id, None, Pooling::Mean).await {
+      Ok(vector) => vectors.push(vector),
+      Err(e) => {
+        eprintln!("Comparing notes without embeddings: {}", e);
+        store_available = false;
+        vectors.push(None);
+      }
+    }
+  }
+  let signatures: Vec<Option<Vec<u64>>> = notes.iter().map(|note| minhash(&note.text)).collect();
+
+  let mut embedding_pairs = Vec::new();
+  let mut minhash_pairs = Vec::new();
+  for a in 0..notes.len() {
+    for b in a + 1..notes.len() {
+      if exact_copies.contains(&a) || exact_copies.contains(&b) || notes[a].text == notes[b].text {
+        continue;
+      }
+      if let (Some(vector_a), Some(vector_b)) = (&vectors[a], &vectors[b]) {
+        let similarity = cosine(vector_a, vector_b);
+        if similarity >= threshold {
+          embedding_pairs.push((a, b, similarity));
+        }
+      } else if let (Some(signature_a), Some(signature_b)) = (&signatures[a], &signatures[b]) {
+        let similarity = minhash_similarity(signature_a, signature_b);
+        if similarity >= threshold {
+          minhash_pairs.push((a, b, similarity));
+        }
+      }
+    }
+  }
+
+  let mut groups = group_pairs(&notes, &exact_pairs, DuplicateKind::Exact);
+  groups.extend(group_pairs(
+    &notes,
+    &embedding_pairs,
+    DuplicateKind::Embedding,
+  ));
+  groups.extend(group_pairs(&notes, &minhash_pairs, DuplicateKind::MinHash));
+  groups.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+  Ok(groups)
+}
+
+// Add the pages and tags of `source` to `merged`, keeping the earlier creation date. Notebooks
+// only show their first page, so a notebook source's page HTML is appended to it instead.
+fn merge_into(merged: &mut Value, source: &Value) -> Result<(), String> {
+  let is_notebook = merged["metadata"]["note_type"].as_str() == Some("notebook");
+  let page_ids: HashSet<String> = merged["pages"]
+    .as_array()
+    .into_iter()
+    .flatten()
+    .filter_map(|page| page["id"].as_str().map(str::to_string))
+    .collect();
+  let Some(pages) = merged["pages"].as_array_mut() else {
+    return Err("Note pages not found".to_string());
+  };
+  let source_pages = source["pages"].as_array().into_iter().flatten();
+  match pages.first_mut() {
+    Some(first) if is_notebook => {
+      let mut content = first["content"].as_str().unwrap_or("").to_string();
+      for page in source_pages {
+        content.push_str(page["content"].as_str().unwrap_or(""));
+      }
+      first["content"] = json!(content);
+    }
+    _ => {
+      for page in source_pages {
+        let mut page = page.clone();
+        // Copies of the same note can share page ids
+        if page["id"]
+          .as_str()
+          .is_none_or(|page_id| page_ids.contains(page_id))
+        {
+          page["id"] = json!(Uuid::new_v4().to_string());
+        }
+        pages.push(page);
+      }
+    }
+  }
+
+  let mut tags: Vec<Value> = merged["metadata"]["tags"]
+    .as_array()
+    .cloned()
+    .unwrap_or_default();
+  for tag in source["metadata"]["tags"].as_array().into_iter().flatten() {
+    if !tags.contains(tag) {
+      tags.push(tag.clone());
+    }
+  }
+  merged["metadata"]["tags"] = json!(tags);
+
+  // Keep the earlier creation date of the two
+  let source_created = &source["metadata"]["created_at"];
+  if let (Some(created), Some(source_created_str)) = (
+    merged["metadata"]["created_at"].as_str(),
+    source_created.as_str(),
+  ) {
+    if source_created_str < created {
+      merged["metadata"]["created_at"] = source_created.clone();
+    }
+  }
+  Ok(())
+}
+
+// Merge the note `source` into the note `target`: the source's content and tags are added and
+// links to the source point at the target, then the source note is deleted. Both notes must be
+// of the same type.
+#[tauri::command]
+pub async fn merge_notes(target: String, source: String) -> Result<Value, String> {
+  let source_id = source;
+  let (target, source) = (note_path(&target)?, note_path(&source_id)?);
+  if target == source {
+    return Err("Cannot merge a note into itself".to_string());
+  }
+  let target_note = read_note(&target)?;
+  if target_note["metadata"]["note_type"] != read_note(&source)?["metadata"]["note_type"] {
+    return Err("Cannot merge notes of different types".to_string());
+  }
+  let Some(target_id) = target_note["id"].as_str() else {
+    return Err(format!("Note {} has no id", target));
+  };
+  retarget_links(&source, target_id).await?;
+
+  // Read both again, as either may have linked to the source
+  let mut merged = read_note(&target)?;
+  merge_into(&mut merged, &read_note(&source)?)?;
+  write_note(&target, &merged)?;
+  delete_note(&source_id)?;
+
+  if let Err(e) = index_note_file(Path::new(&target)).await {
+    eprintln!("Failed to index merged note {}: {}", target, e);
+  }
+  Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn note(note_type: &str, tags: &[&str], pages: &[(&str, &str)]) -> Value {
+    json!({
+      "id": Uuid::new_v4().to_string(),
+      "title": "Note",
+      "metadata": {
+        "note_type": note_type,
+        "tags": tags,
+        "created_at": "2025-01-01T00:00:00Z",
+      },
+      "pages": pages
+        .iter()
+        .map(|(id, content)| json!({ "id": id, "content": content }))
+        .collect::<Vec<_>>(),
+    })
+  }
+
+  #[test]
+  fn merge_into_shows_the_source_on_the_first_notebook_page() {
+    let mut merged = note("notebook", &["a"], &[("p1", "<p>target</p>")]);
+    let source = note(
+      "notebook",
+      &["a", "b"],
+      &[("p1", "<p>source</p>"), ("p2", "<p>more</p>")],
+    );
+    merge_into(&mut merged, &source).unwrap();
+
+    let pages = merged["pages"].as_array().unwrap();
+    assert_eq!(pages.len(), 1);
+    assert_eq!(pages[0]["content"], "<p>target</p><p>source</p><p>more</p>");
+    assert_eq!(merged["metadata"]["tags"], json!(["a", "b"]));
+  }
+
+  #[test]
+  fn merge_into_appends_free_note_pages_with_fresh_ids() {
+    let mut merged = note("freenote", &[], &[("p1", "[]")]);
+    let source = note("freenote", &[], &[("p1", "[1]"), ("p2", "[2]")]);
+    merge_into(&mut merged, &source).unwrap();
+
+    let pages = merged["pages"].as_array().unwrap();
+    let ids: Vec<&str> = pages
+      .iter()
+      .map(|page| page["id"].as_str().unwrap())
+      .collect();
+    assert_eq!(pages.len(), 3);
+    assert_ne!(ids[1], "p1");
+    assert_eq!(ids[2], "p2");
+    assert_eq!(pages[1]["content"], "[1]");
+  }
+}
@@ -0,0 +1,156 @@
+use crate::config::{get_config, EmbeddingConfig, EmbeddingProvider};
+use crate::ollama::OllamaEmbedder;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum EmbeddingError {
+  #[error("Ollama API error: {0}")]
+  OllamaError(#[from] ollama_rs::error::OllamaError),
+  #[error("Embedding server error: {0}")]
+  HttpError(#[from] reqwest::Error),
+  #[error("Embeddings not found in response")]
+  EmbeddingsNotFound,
+  #[error("Model '{model}' produced {actual}-dimensional vectors but {expected} are configured")]
+  DimensionMismatch {
+    model: String,
+    expected: u64,
+    actual: u64,
+  },
+  #[error("{0}")]
+  Config(String),
+}
+
+// Turns text into vectors, implemented for Ollama and for OpenAI-compatible servers
+#[async_trait]
+pub trait Embedder: Send + Sync {
+  fn model(&self) -> &str;
+
+  // Configured size of the vectors this embedder produces
+  fn dimension(&self) -> u64;
+
+  // One vector per input, in input order
+  async fn embed(&self, inputs: Vec<String>) -> Result<Vec<Vec<f32>>, EmbeddingError>;
+}
+
+// Reject vectors whose size differs from the configured dimension
+pub fn check_dimensions(
+  embedder: &dyn Embedder,
+  vectors: &[Vec<f32>],
+) -> Result<(), EmbeddingError> {
+  for vector in vectors {
+    if vector.len() as u64 != embedder.dimension() {
+      return Err(EmbeddingError::DimensionMismatch {
+        model: embedder.model().to_string(),
+        expected: embedder.dimension(),
+        actual: vector.len() as u64,
+      });
+    }
+  }
+  Ok(())
+}
+
+// Embedder for servers implementing the OpenAI `/embeddings` endpoint
+pub struct OpenAiEmbedder {
+  client: reqwest::Client,
+  endpoint: String,
+  api_key: Option<String>,
+  model: String,
+  dimension: u64,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbeddingResponse {
+  data: Vec<OpenAiEmbedding>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbedding {
+  index: usize,
+  embedding: Vec<f32>,
+}
+
+impl OpenAiEmbedder {
+  pub fn new(config: &EmbeddingConfig) -> Self {
+    OpenAiEmbedder {
+      client: reqwest::Client::new(),
+      endpoint: config.endpoint.trim_end_matches('/').to_string(),
+      api_key: config.api_key.clone(),
+      model: config.model.clone(),
+      dimension: config.dimension,
+    }
+  }
+}
+
+#[async_trait]
+impl Embedder for OpenAiEmbedder {
+  fn model(&self) -> &str {
+    &self.model
+  }
+
+  fn dimension(&self) -> u64 {
+    self.dimension
+  }
+
+  async fn embed(&self, inputs: Vec<String>) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+    let expected = inputs.len();
+    let mut request = self
+      .client
+      .post(format!("{}/embeddings", self.endpoint))
+      .json(&json!({ "model": self.model, "input": inputs }));
+    if let Some(api_key) = &self.api_key {
+      request = request.bearer_auth(api_key);
+    }
+    let mut response: OpenAiEmbeddingResponse =
+      request.send().await?.error_for_status()?.json().await?;
+    if response.data.len() != expected {
+      return Err(EmbeddingError::EmbeddingsNotFound);
+    }
+    // The API does not guarantee response order matches input order
+    response.data.sort_by_key(|embedding| embedding.index);
+    Ok(
+      response
+        .data
+        .into_iter()
+        .map(|embedding| embedding.embedding)
+        .collect(),
+    )
+  }
+}
+
+static EMBEDDER: RwLock<Option<Arc<dyn Embedder>>> = RwLock::new(None);
+
+// The embedder selected in the user config, created on first use
+pub fn get_embedder() -> Result<Arc<dyn Embedder>, EmbeddingError> {
+  if let Some(embedder) = EMBEDDER.read().unwrap().as_ref() {
+    return Ok(embedder.clone());
+  }
+
+  let mut guard = EMBEDDER.write().unwrap();
+  if let Some(embedder) = guard.as_ref() {
+    return Ok(embedder.clone()); // Someone else initialized it first
+  }
+  let config = get_config().map_err(EmbeddingError::Config)?;
+  let embedder: Arc<dyn Embedder> = match config.embedding.provider {
+    EmbeddingProvider::Ollama => Arc::new(OllamaEmbedder::new(&config)?),
+    EmbeddingProvider::OpenAi => Arc::new(OpenAiEmbedder::new(&config.embedding)),
+  };
+  *guard = Some(embedder.clone());
+  Ok(embedder)
+}
+
+// Drop the cached embedder so the next access re-reads the config
+pub fn reset_embedder() {
+  *EMBEDDER.write().unwrap() = None;
+}
+
+// Embed several chunks with the configured embedder, checking every vector's dimension
+pub async fn embed_chunks(chunks: Vec<String>) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+  let embedder = get_embedder()?;
+  let vectors = embedder.embed(chunks).await?;
+  check_dimensions(embedder.as_ref(), &vectors)?;
+  Ok(vectors)
+}
@@ -0,0 +1,806 @@
+use crate::config::get_config;
+use crate::sync::{get_storage_remote, resend_all_notes, RemoteChanges, RemoteEntry, SyncRemote};
+use argon2::{Algorithm, Argon2, Params, Version};
+use async_trait::async_trait;
+use base64::{
+  engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+  Engine,
+};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+// Name of the remote document holding the keyring
+const KEYRING: &str = "keyring";
+// Marks sealed strings, telling them apart from paths stored before encryption was turned on
+const SEALED_PREFIX: &str = "e1:";
+const NONCE_LEN: usize = 24;
+// Aad of the wrapped index key; content keys use their id
+const INDEX_KEY_ID: &str = "index";
+// Upper bounds of the Argon2 parameters a keyring may ask for
+const MAX_MEMORY_KIB: u32 = 1024 * 1024;
+const MAX_ITERATIONS: u32 = 16;
+const MAX_PARALLELISM: u32 = 16;
+
+type HmacSha256 = Hmac<Sha256>;
+
+static SYNC_KEYS: RwLock<Option<Arc<SyncKeys>>> = RwLock::new(None);
+
+// Keys stored on the remote, each encrypted with a key derived from the passphrase, so every
+// device that knows the passphrase can read them and the remote can't
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Keyring {
+  // Argon2id parameters the passphrase key is derived with
+  salt: String,
+  memory_kib: u32,
+  iterations: u32,
+  parallelism: u32,
+  // Content keys by id. Older keys stay to read notes that weren't re-encrypted yet.
+  keys: BTreeMap<String, String>,
+  // Id of the key new changes are encrypted with
+  current: String,
+  // Derives opaque ids and nonces; never rotated so note ids on the remote stay the same
+  index_key: String,
+}
+
+// Keys unlocked with the passphrase, only ever held in memory
+pub struct SyncKeys {
+  // Remote the keys were unlocked for
+  remote: String,
+  current: String,
+  keys: BTreeMap<String, Key>,
+  index_key: Key,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SyncEncryptionStatus {
+  pub enabled: bool,
+  pub unlocked: bool,
+  pub current_key: Option<String>,
+  pub key_count: usize,
+}
+
+// What the remote stores in an entry's path: everything about the note but its revision
+#[derive(Serialize, Deserialize, Debug)]
+struct SealedEntry {
+  id: String,
+  path: String,
+  hash: String,
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+  let mut bytes = [0u8; N];
+  OsRng.fill_bytes(&mut bytes);
+  bytes
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn passphrase_key(passphrase: &str, keyring: &Keyring) -> Result<Key, String> {
+  // The parameters come from the remote, which mustn't be able to exhaust this device
+  if keyring.memory_kib > MAX_MEMORY_KIB
+    || keyring.iterations > MAX_ITERATIONS
+    || keyring.parallelism > MAX_PARALLELISM
+  {
+    return Err("The keyring asks for more work than a passphrase key may take".to_string());
+  }
+  let salt = STANDARD
+    .decode(&keyring.salt)
+    .map_err(|e| format!("Invalid keyring salt: {}", e))?;
+  let params = Params::new(
+    keyring.memory_kib,
+    keyring.iterations,
+    keyring.parallelism,
+    Some(32),
+  )
+  .map_err(|e| format!("Invalid keyring parameters: {}", e))?;
+  let mut key = Key::default();
+  Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+    .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+    .map_err(|e| format!("Failed to derive the passphrase key: {}", e))?;
+  Ok(key)
+}
+
+// Encrypt a key with the passphrase key under a random nonce
+fn wrap_key(passphrase_key: &Key, key: &Key, id: &str) -> Result<String, String> {
+  let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+  let payload = Payload {
+    msg: key.as_slice(),
+    aad: id.as_bytes(),
+  };
+  let ciphertext = XChaCha20Poly1305::new(passphrase_key)
+    .encrypt(&nonce, payload)
+    .map_err(|_| "Failed to encrypt key".to_string())?;
+  Ok(STANDARD.encode([nonce.as_slice(), &ciphertext].concat()))
+}
+
+fn unwrap_key(passphrase_key: &Key, wrapped: &str, id: &str) -> Result<Key, String> {
+  let bytes = STANDARD
+    .decode(wrapped)
+    .map_err(|e| format!("Invalid key {}: {}", id, e))?;
+  if bytes.len() < NONCE_LEN {
+    return Err(format!("Invalid key {}", id));
+  }
+  let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+  let payload = Payload {
+    msg: ciphertext,
+    aad: id.as_bytes(),
+  };
+  let key = XChaCha20Poly1305::new(passphrase_key)
+    .decrypt(XNonce::from_slice(nonce), payload)
+    // Authentication fails when the passphrase key is wrong
+    .map_err(|_| "Wrong sync passphrase".to_string())?;
+  if key.len() != 32 {
+    return Err(format!("Invalid key {}", id));
+  }
+  Ok(*Key::from_slice(&key))
+}
+
+impl Keyring {
+  // Keyring with a fresh content key and index key, protected by `passphrase`
+  fn create(passphrase: &str) -> Result<(Keyring, BTreeMap<String, Key>, Key), String> {
+    let mut keyring = Keyring {
+      salt: STANDARD.encode(random_bytes::<16>()),
+      memory_kib: Params::DEFAULT_M_COST,
+      iterations: Params::DEFAULT_T_COST,
+      parallelism: Params::DEFAULT_P_COST,
+      keys: BTreeMap::new(),
+      current: to_hex(&random_bytes::<8>()),
+      index_key: String::new(),
+    };
+    let passphrase_key = passphrase_key(passphrase, &keyring)?;
+    let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+    let index_key = XChaCha20Poly1305::generate_key(&mut OsRng);
+    keyring.keys.insert(
+      keyring.current.clone(),
+      wrap_key(&passphrase_key, &key, &keyring.current)?,
+    );
+    keyring.index_key = wrap_key(&passphrase_key, &index_key, INDEX_KEY_ID)?;
+    let keys = BTreeMap::from([(keyring.current.clone(), key)]);
+    Ok((keyring, keys, index_key))
+  }
+
+  fn unlock(&self, passphrase_key: &Key) -> Result<(BTreeMap<String, Key>, Key), String> {
+    let index_key = unwrap_key(passphrase_key, &self.index_key, INDEX_KEY_ID)?;
+    let mut keys = BTreeMap::new();
+    for (id, wrapped) in &self.keys {
+      keys.insert(id.clone(), unwrap_key(passphrase_key, wrapped, id)?);
+    }
+    if !keys.contains_key(&self.current) {
+      return Err("The keyring has no current key".to_string());
+    }
+    Ok((keys, index_key))
+  }
+
+  // The same keys protected by a new passphrase and salt
+  fn rewrap(
+    &self,
+    passphrase: &str,
+    keys: &BTreeMap<String, Key>,
+    index_key: &Key,
+  ) -> Result<Keyring, String> {
+    let mut keyring = Keyring {
+      salt: STANDARD.encode(random_bytes::<16>()),
+      keys: BTreeMap::new(),
+      index_key: String::new(),
+      ..self.clone()
+    };
+    let passphrase_key = passphrase_key(passphrase, &keyring)?;
+    for (id, key) in keys {
+      keyring
+        .keys
+        .insert(id.clone(), wrap_key(&passphrase_key, key, id)?);
+    }
+    keyring.index_key = wrap_key(&passphrase_key, index_key, INDEX_KEY_ID)?;
+    Ok(keyring)
+  }
+}
+
+impl SyncKeys {
+  fn keyed_hash(&self, data: &[&[u8]]) -> Vec<u8> {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.index_key)
+      .expect("HMAC accepts keys of any length");
+    for part in data {
+      mac.update(part);
+      mac.update(&[0]);
+    }
+    mac.finalize().into_bytes().to_vec()
+  }
+
+  // Id a note is stored under on the remote, the same on every device
+  pub fn opaque_id(&self, id: &str) -> String {
+    to_hex(&self.keyed_hash(&[b"note", id.as_bytes()])[..16])
+  }
+
+  // Encrypt with the current key. The nonce is derived from the content, so unchanged content
+  // encrypts to the same ciphertext and remotes that skip known pages keep doing so.
+  fn seal(&self, plaintext: &[u8], aad: &str) -> Result<String, String> {
+    let key = &self.keys[&self.current];
+    let digest = self.keyed_hash(&[b"nonce", self.current.as_bytes(), aad.as_bytes(), plaintext]);
+    let nonce = XNonce::from_slice(&digest[..NONCE_LEN]);
+    let payload = Payload {
+      msg: plaintext,
+      aad: aad.as_bytes(),
+    };
+    let ciphertext = XChaCha20Poly1305::new(key)
+      .encrypt(nonce, payload)
+      .map_err(|_| "Failed to encrypt note".to_string())?;
+    Ok(format!(
+      "{}{}:{}",
+      SEALED_PREFIX,
+      self.current,
+      URL_SAFE_NO_PAD.encode([nonce.as_slice(), &ciphertext].concat())
+    ))
+  }
+
+  fn open(&self, sealed: &str, aad: &str) -> Result<Vec<u8>, String> {
+    let (key_id, data) = sealed
+      .strip_prefix(SEALED_PREFIX)
+      .and_then(|sealed| sealed.split_once(':'))
+      .ok_or_else(|| "Content is not encrypted".to_string())?;
+    let key = self.keys.get(key_id).ok_or_else(|| {
+      "A note was encrypted with a key added after sync encryption was unlocked; unlock it again"
+        .to_string()
+    })?;
+    let bytes = URL_SAFE_NO_PAD
+      .decode(data)
+      .map_err(|e| format!("Invalid encrypted content: {}", e))?;
+    if bytes.len() < NONCE_LEN {
+      return Err("Invalid encrypted content".to_string());
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    let payload = Payload {
+      msg: ciphertext,
+      aad: aad.as_bytes(),
+    };
+    XChaCha20Poly1305::new(key)
+      .decrypt(XNonce::from_slice(nonce), payload)
+      .map_err(|_| "Encrypted content was tampered with".to_string())
+  }
+
+  fn seal_json<T: Serialize>(&self, value: &T, aad: &str) -> Result<String, String> {
+    let plaintext =
+      serde_json::to_vec(value).map_err(|e| format!("Failed to serialize note: {}", e))?;
+    self.seal(&plaintext, aad)
+  }
+
+  fn open_json<T: DeserializeOwned>(&self, sealed: &str, aad: &str) -> Result<T, String> {
+    serde_json::from_slice(&self.open(sealed, aad)?)
+      .map_err(|e| format!("Invalid decrypted content: {}", e))
+  }
+}
+
+// Keys unlocked for `remote`
+fn unlocked_keys(remote: &str) -> Result<Arc<SyncKeys>, String> {
+  SYNC_KEYS
+    .read()
+    .unwrap()
+    .as_ref()
+    .filter(|keys| keys.remote == remote)
+    .cloned()
+    .ok_or_else(|| "Sync encryption is locked; unlock it with the passphrase first".to_string())
+}
+
+// Sync remote that encrypts everything before handing it to another remote. The inner remote
+// only sees opaque ids, revisions, modification times and which notes are deleted. Paths and
+// hashes are sealed in the entry, titles and other fields in the note, and every page on its
+// own, so pages that didn't change aren't uploaded again. Attachments embedded in pages are
+// sealed along with them. Notes pushed before encryption was turned on are ignored, and purged
+// from the inner remote by `purge_plaintext` once they have an encrypted copy.
+pub struct EncryptedRemote {
+  inner: Arc<dyn SyncRemote>,
+  keys: Arc<SyncKeys>,
+}
+
+impl EncryptedRemote {
+  pub fn new(inner: Arc<dyn SyncRemote>) -> Result<Self, String> {
+    let keys = unlocked_keys(&inner.describe())?;
+    Ok(EncryptedRemote { inner, keys })
+  }
+
+  // Encrypted view of `inner` with fresh keys, without going through a passphrase
+  #[cfg(test)]
+  pub fn with_new_keys(inner: Arc<dyn SyncRemote>) -> Self {
+    let keys = SyncKeys {
+      remote: inner.describe(),
+      current: "k1".to_string(),
+      keys: BTreeMap::from([(
+        "k1".to_string(),
+        XChaCha20Poly1305::generate_key(&mut OsRng),
+      )]),
+      index_key: XChaCha20Poly1305::generate_key(&mut OsRng),
+    };
+    EncryptedRemote {
+      inner,
+      keys: Arc::new(keys),
+    }
+  }
+
+  fn seal_entry(&self, entry: &RemoteEntry) -> Result<RemoteEntry, String> {
+    let id = self.keys.opaque_id(&entry.id);
+    let sealed = SealedEntry {
+      id: entry.id.clone(),
+      path: entry.path.clone(),
+      hash: entry.hash.clone(),
+    };
+    Ok(RemoteEntry {
+      path: self.keys.seal_json(&sealed, &id)?,
+      id,
+      // The real hash is sealed with the path
+      hash: String::new(),
+      ..entry.clone()
+    })
+  }
+
+  // None for entries stored before encryption was turned on
+  fn open_entry(&self, entry: RemoteEntry) -> Result<Option<RemoteEntry>, String> {
+    if !entry.path.starts_with(SEALED_PREFIX) {
+      return Ok(None);
+    }
+    let sealed: SealedEntry = self.keys.open_json(&entry.path, &entry.id)?;
+    // Refuse entries copied over from another note
+    if self.keys.opaque_id(&sealed.id) != entry.id {
+      return Err(format!("Entry {} doesn't match its content", entry.id));
+    }
+    Ok(Some(RemoteEntry {
+      id: sealed.id,
+      path: sealed.path,
+      hash: sealed.hash,
+      ..entry
+    }))
+  }
+
+  // Pages are sealed with their position and the note's fields with the page count, so the
+  // remote can't reorder, drop or swap pages between notes
+  fn seal_note(&self, note: &Value, id: &str) -> Result<Value, String> {
+    let mut note = note.clone();
+    let pages = match note
+      .as_object_mut()
+      .and_then(|fields| fields.remove("pages"))
+    {
+      Some(Value::Array(pages)) => Some(pages),
+      _ => None,
+    };
+    let page_count = pages.as_ref().map_or(0, Vec::len);
+    let mut sealed = json!({ "sealed": self.keys.seal_json(&note, &note_aad(id, page_count))? });
+    if let Some(pages) = pages {
+      let pages = pages
+        .iter()
+        .enumerate()
+        .map(|(index, page)| {
+          Ok(json!({ "sealed": self.keys.seal_json(page, &page_aad(id, index))? }))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+      sealed["pages"] = Value::Array(pages);
+    }
+    Ok(sealed)
+  }
+
+  fn open_note(&self, sealed: &Value, id: &str) -> Result<Value, String> {
+    let pages = sealed["pages"].as_array();
+    let page_count = pages.map_or(0, Vec::len);
+    let mut note: Value = self.keys.open_json(
+      sealed["sealed"]
+        .as_str()
+        .ok_or_else(|| "Content is not encrypted".to_string())?,
+      &note_aad(id, page_count),
+    )?;
+    if let Some(pages) = pages {
+      let pages = pages
+        .iter()
+        .enumerate()
+        .map(|(index, page)| {
+          let page = page["sealed"]
+            .as_str()
+            .ok_or_else(|| "Page is not encrypted".to_string())?;
+          self.keys.open_json(page, &page_aad(id, index))
+        })
+        .collect::<Result<Vec<Value>, String>>()?;
+      note["pages"] = Value::Array(pages);
+    }
+    Ok(note)
+  }
+
+  // Replace every note stored before encryption was turned on that has an encrypted copy with
+  // a tombstone that keeps neither its path nor its content, then drop what only those notes
+  // used. Returns the number of notes purged. Devices still syncing without encryption delete
+  // their copy on pulling the tombstone, so a note without an encrypted copy is left alone.
+  pub async fn purge_plaintext(&self) -> Result<usize, String> {
+    let entries = self.inner.changes(0).await?.entries;
+    let sealed: HashSet<&str> = entries
+      .iter()
+      .filter(|entry| entry.path.starts_with(SEALED_PREFIX))
+      .map(|entry| entry.id.as_str())
+      .collect();
+    let mut purged = 0;
+    for entry in &entries {
+      if entry.path.starts_with(SEALED_PREFIX) || (entry.deleted && entry.path.is_empty()) {
+        continue;
+      }
+      if !sealed.contains(self.keys.opaque_id(&entry.id).as_str()) {
+        continue;
+      }
+      let tombstone = RemoteEntry {
+        path: String::new(),
+        hash: String::new(),
+        deleted: true,
+        modified_at: Utc::now(),
+        ..entry.clone()
+      };
+      // A note changed in the meantime is purged next time
+      if self
+        .inner
+        .push(&tombstone, None, entry.revision)
+        .await?
+        .is_some()
+      {
+        purged += 1;
+      }
+    }
+    self.inner.prune().await?;
+    Ok(purged)
+  }
+}
+
+#[async_trait]
+impl SyncRemote for EncryptedRemote {
+  fn describe(&self) -> String {
+    format!("encrypted:{}", self.inner.describe())
+  }
+
+  async fn changes(&self, since: u64) -> Result<RemoteChanges, String> {
+    let changes = self.inner.changes(since).await?;
+    let mut entries = Vec::new();
+    for entry in changes.entries {
+      if let Some(entry) = self.open_entry(entry)? {
+        entries.push(entry);
+      }
+    }
+    Ok(RemoteChanges {
+      entries,
+      cursor: changes.cursor,
+    })
+  }
+
+  async fn fetch(&self, id: &str) -> Result<Option<Value>, String> {
+    let opaque_id = self.keys.opaque_id(id);
+    match self.inner.fetch(&opaque_id).await? {
+      Some(sealed) => self.open_note(&sealed, &opaque_id).map(Some),
+      None => Ok(None),
+    }
+  }
+
+  async fn push(
+    &self,
+    entry: &RemoteEntry,
+    note: Option<&Value>,
+    base: u64,
+  ) -> Result<Option<u64>, String> {
+    let entry = self.seal_entry(entry)?;
+    let note = match note {
+      Some(note) => Some(self.seal_note(note, &entry.id)?),
+      None => None,
+    };
+    self.inner.push(&entry, note.as_ref(), base).await
+  }
+
+  async fn load_meta(&self, name: &str) -> Result<Option<(Value, u64)>, String> {
+    self.inner.load_meta(name).await
+  }
+
+  async fn store_meta(
+    &self,
+    name: &str,
+    value: &Value,
+    expected: Option<u64>,
+  ) -> Result<bool, String> {
+    self.inner.store_meta(name, value, expected).await
+  }
+
+  async fn prune(&self) -> Result<(), String> {
+    self.inner.prune().await
+  }
+}
+
+fn storage_remote() -> Result<Arc<dyn SyncRemote>, String> {
+  get_storage_remote()?.ok_or_else(|| "Sync is not configured".to_string())
+}
+
+// The keyring and its version on the remote
+async fn load_keyring(remote: &dyn SyncRemote) -> Result<Option<(Keyring, u64)>, String> {
+  match remote.load_meta(KEYRING).await? {
+    Some((keyring, version)) => serde_json::from_value(keyring)
+      .map(|keyring| Some((keyring, version)))
+      .map_err(|e| format!("Invalid keyring: {}", e)),
+    None => Ok(None),
+  }
+}
+
+// Store the keyring unless another device changed it after `expected`. Returns false if one did.
+async fn store_keyring(
+  remote: &dyn SyncRemote,
+  keyring: &Keyring,
+  expected: Option<u64>,
+) -> Result<bool, String> {
+  let keyring =
+    serde_json::to_value(keyring).map_err(|e| format!("Failed to serialize keyring: {}", e))?;
+  remote.store_meta(KEYRING, &keyring, expected).await
+}
+
+fn keyring_changed() -> String {
+  "The sync keyring was changed on another device; try again".to_string()
+}
+
+// Aad of a note's fields and of each of its pages
+fn note_aad(id: &str, page_count: usize) -> String {
+  format!("{}:{}", id, page_count)
+}
+
+fn page_aad(id: &str, index: usize) -> String {
+  format!("{}:page:{}", id, index)
+}
+
+// Argon2 is slow on purpose, so derive passphrase keys off the async runtime
+async fn blocking<T: Send + 'static>(
+  task: impl FnOnce() -> Result<T, String> + Send + 'static,
+) -> Result<T, String> {
+  tokio::task::spawn_blocking(task)
+    .await
+    .map_err(|e| format!("Failed to derive the passphrase key: {}", e))?
+}
+
+fn remember_keys(
+  remote: &dyn SyncRemote,
+  current: &str,
+  keys: BTreeMap<String, Key>,
+  index_key: Key,
+) {
+  *SYNC_KEYS.write().unwrap() = Some(Arc::new(SyncKeys {
+    remote: remote.describe(),
+    current: current.to_string(),
+    keys,
+    index_key,
+  }));
+}
+
+#[tauri::command]
+pub fn sync_encryption_status() -> Result<SyncEncryptionStatus, String> {
+  let enabled = get_config()?.sync.encrypt;
+  let keys = match get_storage_remote()? {
+    Some(remote) => unlocked_keys(&remote.describe()).ok(),
+    None => None,
+  };
+  Ok(SyncEncryptionStatus {
+    enabled,
+    unlocked: keys.is_some(),
+    current_key: keys.as_ref().map(|keys| keys.current.clone()),
+    key_count: keys.as_ref().map_or(0, |keys| keys.keys.len()),
+  })
+}
+
+// Unlock the keys of the configured remote with the passphrase until the app closes. The first
+// device to unlock a remote creates its keyring with that passphrase.
+#[tauri::command]
+pub async fn unlock_sync_encryption(passphrase: String) -> Result<SyncEncryptionStatus, String> {
+  if passphrase.is_empty() {
+    return Err("The sync passphrase can't be empty".to_string());
+  }
+  let remote = storage_remote()?;
+  let keyring = match load_keyring(remote.as_ref()).await? {
+    Some((keyring, _)) => Some(keyring),
+    None => {
+      let create_passphrase = passphrase.clone();
+      let (keyring, keys, index_key) =
+        blocking(move || Keyring::create(&create_passphrase)).await?;
+      if store_keyring(remote.as_ref(), &keyring, None).await? {
+        remember_keys(remote.as_ref(), &keyring.current, keys, index_key);
+        None
+      } else {
+        // Another device set up encryption first, so its keyring holds the keys to use
+        let (keyring, _) = load_keyring(remote.as_ref())
+          .await?
+          .ok_or_else(keyring_changed)?;
+        Some(keyring)
+      }
+    }
+  };
+  if let Some(keyring) = keyring {
+    let (keyring, keys, index_key) = blocking(move || {
+      let (keys, index_key) = keyring.unlock(&passphrase_key(&passphrase, &keyring)?)?;
+      Ok((keyring, keys, index_key))
+    })
+    .await?;
+    remember_keys(remote.as_ref(), &keyring.current, keys, index_key);
+  }
+  // Plaintext copies of notes that were since pushed encrypted would otherwise stay readable on
+  // the remote
+  if get_config()?.sync.encrypt {
+    let purged = EncryptedRemote::new(remote)?.purge_plaintext().await?;
+    if purged > 0 {
+      eprintln!("Purged {} unencrypted notes from the sync remote", purged);
+    }
+  }
+  sync_encryption_status()
+}
+
+#[tauri::command]
+pub fn lock_sync_encryption() {
+  *SYNC_KEYS.write().unwrap() = None;
+}
+
+// Protect the keyring with a new passphrase. Notes keep their keys, so nothing is re-encrypted.
+#[tauri::command]
+pub async fn change_sync_passphrase(current: String, new: String) -> Result<(), String> {
+  if new.is_empty() {
+    return Err("The sync passphrase can't be empty".to_string());
+  }
+  let remote = storage_remote()?;
+  let (keyring, version) = load_keyring(remote.as_ref())
+    .await?
+    .ok_or_else(|| "Sync encryption was never set up for this remote".to_string())?;
+  let keyring = blocking(move || {
+    let (keys, index_key) = keyring.unlock(&passphrase_key(&current, &keyring)?)?;
+    keyring.rewrap(&new, &keys, &index_key)
+  })
+  .await?;
+  if !store_keyring(remote.as_ref(), &keyring, Some(version)).await? {
+    return Err(keyring_changed());
+  }
+  Ok(())
+}
+
+// Encrypt further changes with a new key and queue every synced note to be pushed again, so the
+// next sync re-encrypts them with it
+#[tauri::command]
+pub async fn rotate_sync_key(passphrase: String) -> Result<SyncEncryptionStatus, String> {
+  let remote = storage_remote()?;
+  let (keyring, version) = load_keyring(remote.as_ref())
+    .await?
+    .ok_or_else(|| "Sync encryption was never set up for this remote".to_string())?;
+  let (keyring, keys, index_key) = blocking(move || {
+    let passphrase_key = passphrase_key(&passphrase, &keyring)?;
+    let (mut keys, index_key) = keyring.unlock(&passphrase_key)?;
+    let id = to_hex(&random_bytes::<8>());
+    let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+    let mut keyring = keyring;
+    keyring
+      .keys
+      .insert(id.clone(), wrap_key(&passphrase_key, &key, &id)?);
+    keyring.current = id.clone();
+    keys.insert(id, key);
+    Ok((keyring, keys, index_key))
+  })
+  .await?;
+  // Another device's rotation or passphrase change would otherwise be lost, along with the key
+  // it added
+  if !store_keyring(remote.as_ref(), &keyring, Some(version)).await? {
+    return Err(keyring_changed());
+  }
+  remember_keys(remote.as_ref(), &keyring.current, keys, index_key);
+  resend_all_notes()?;
+  sync_encryption_status()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::folder_remote::FolderRemote;
+  use std::fs;
+  use std::path::PathBuf;
+  use uuid::Uuid;
+
+  fn temp_root() -> PathBuf {
+    std::env::temp_dir().join(format!("encrypted-remote-test-{}", Uuid::new_v4()))
+  }
+
+  fn entry(id: &str, path: &str) -> RemoteEntry {
+    RemoteEntry {
+      id: id.to_string(),
+      path: path.to_string(),
+      hash: "hash".to_string(),
+      revision: 0,
+      deleted: false,
+      modified_at: Utc::now(),
+    }
+  }
+
+  fn note(id: &str) -> Value {
+    json!({
+      "id": id,
+      "title": "Secret",
+      "pages": [
+        { "id": "p1", "content": "<p>one</p>" },
+        { "id": "p2", "content": "<p>two</p>" },
+      ],
+    })
+  }
+
+  #[test]
+  fn refuses_reordered_or_dropped_pages() {
+    let root = temp_root();
+    let remote = EncryptedRemote::with_new_keys(Arc::new(FolderRemote::new(root.clone())));
+    let sealed = remote.seal_note(&note("n1"), "opaque").unwrap();
+    assert_eq!(remote.open_note(&sealed, "opaque").unwrap(), note("n1"));
+
+    let mut swapped = sealed.clone();
+    swapped["pages"].as_array_mut().unwrap().swap(0, 1);
+    assert!(remote.open_note(&swapped, "opaque").is_err());
+
+    let mut truncated = sealed.clone();
+    truncated["pages"].as_array_mut().unwrap().pop();
+    assert!(remote.open_note(&truncated, "opaque").is_err());
+
+    // Pages only open as part of the note they were sealed with
+    let other = remote.seal_note(&note("n2"), "other").unwrap();
+    let mut moved = sealed;
+    moved["pages"][0] = other["pages"][0].clone();
+    assert!(remote.open_note(&moved, "opaque").is_err());
+
+    let _ = fs::remove_dir_all(root);
+  }
+
+  #[tokio::test]
+  async fn purges_plaintext_notes_with_an_encrypted_copy() {
+    let root = temp_root();
+    let inner: Arc<dyn SyncRemote> = Arc::new(FolderRemote::new(root.clone()));
+    for id in ["copied", "plain"] {
+      inner
+        .push(&entry(id, &format!("{}.json", id)), Some(&note(id)), 0)
+        .await
+        .unwrap();
+    }
+    let remote = EncryptedRemote::with_new_keys(inner.clone());
+    remote
+      .push(&entry("copied", "copied.json"), Some(&note("copied")), 0)
+      .await
+      .unwrap();
+
+    assert_eq!(remote.purge_plaintext().await.unwrap(), 1);
+    assert_eq!(inner.fetch("copied").await.unwrap(), None);
+    let entries = inner.changes(0).await.unwrap().entries;
+    let copied = entries.iter().find(|entry| entry.id == "copied").unwrap();
+    assert!(copied.deleted && copied.path.is_empty());
+    assert_eq!(remote.fetch("copied").await.unwrap(), Some(note("copied")));
+    // Its only copy is still there for devices syncing without encryption
+    assert_eq!(inner.fetch("plain").await.unwrap(), Some(note("plain")));
+    assert_eq!(remote.purge_plaintext().await.unwrap(), 0);
+
+    let _ = fs::remove_dir_all(root);
+  }
+
+  #[tokio::test]
+  async fn refuses_keyring_writes_based_on_an_old_version() {
+    let root = temp_root();
+    let remote = FolderRemote::new(root.clone());
+    let (keyring, _, _) = Keyring::create("passphrase").unwrap();
+    assert!(store_keyring(&remote, &keyring, None).await.unwrap());
+    // Another device creating a keyring at the same time loses
+    assert!(!store_keyring(&remote, &keyring, None).await.unwrap());
+
+    let (keyring, version) = load_keyring(&remote).await.unwrap().unwrap();
+    assert!(store_keyring(&remote, &keyring, Some(version))
+      .await
+      .unwrap());
+    assert!(!store_keyring(&remote, &keyring, Some(version))
+      .await
+      .unwrap());
+
+    let _ = fs::remove_dir_all(root);
+  }
+
+  #[test]
+  fn refuses_keyrings_asking_for_too_much_memory() {
+    let (mut keyring, _, _) = Keyring::create("passphrase").unwrap();
+    keyring.memory_kib = u32::MAX;
+    assert!(passphrase_key("passphrase", &keyring).is_err());
+  }
+}
@@ -0,0 +1,204 @@
+use crate::sync::{RemoteChanges, RemoteEntry, SyncRemote};
+use async_trait::async_trait;
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+// Everything known about the notes in a folder remote
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Manifest {
+  revision: u64,
+  notes: BTreeMap<String, RemoteEntry>,
+}
+
+// A named document and the number of times it was stored
+#[derive(Serialize, Deserialize, Debug)]
+struct MetaDocument {
+  version: u64,
+  value: Value,
+}
+
+// Sync remote kept in a plain directory, e.g. on a USB drive or network share: `manifest.json`
+// lists every note and `notes/<id>.json` holds its content. Changes are made under a lock file
+// so devices writing to a shared folder at the same time don't lose each other's updates.
+pub struct FolderRemote {
+  root: PathBuf,
+}
+
+// Write to a temporary file first so a crash never leaves a truncated file
+fn write_atomic(path: &Path, content: &str) -> Result<(), String> {
+  let tmp_path = path.with_extension("json.tmp");
+  fs::write(&tmp_path, content)
+    .map_err(|e| format!("Failed to write {}: {}", tmp_path.display(), e))?;
+  fs::rename(&tmp_path, path).map_err(|e| format!("Failed to replace {}: {}", path.display(), e))
+}
+
+impl FolderRemote {
+  pub fn new(root: PathBuf) -> Self {
+    FolderRemote { root }
+  }
+
+  fn manifest_path(&self) -> PathBuf {
+    self.root.join("manifest.json")
+  }
+
+  fn note_file(&self, id: &str) -> Result<PathBuf, String> {
+    // Ids become file names, so anything but a plain id could escape the folder
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+      return Err(format!("Invalid note id '{}'", id));
+    }
+    Ok(self.root.join("notes").join(format!("{}.json", id)))
+  }
+
+  fn meta_file(&self, name: &str) -> Result<PathBuf, String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+      return Err(format!("Invalid sync document name '{}'", name));
+    }
+    Ok(self.root.join(format!("{}.json", name)))
+  }
+
+  fn read_meta(&self, name: &str) -> Result<Option<MetaDocument>, String> {
+    let path = self.meta_file(name)?;
+    if !path.exists() {
+      return Ok(None);
+    }
+    let content =
+      fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    serde_json::from_str(&content)
+      .map(Some)
+      .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+  }
+
+  fn load_manifest(&self) -> Result<Manifest, String> {
+    let path = self.manifest_path();
+    if !path.exists() {
+      return Ok(Manifest::default());
+    }
+    let content =
+      fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    serde_json::from_str(&content).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+  }
+
+  // Hold the folder's lock until the returned file is dropped
+  fn lock(&self) -> Result<File, String> {
+    fs::create_dir_all(self.root.join("notes"))
+      .map_err(|e| format!("Failed to create sync folder: {}", e))?;
+    let file = File::create(self.root.join(".lock"))
+      .map_err(|e| format!("Failed to open sync folder lock: {}", e))?;
+    file
+      .lock_exclusive()
+      .map_err(|e| format!("Failed to lock sync folder: {}", e))?;
+    Ok(file)
+  }
+}
+
+#[async_trait]
+impl SyncRemote for FolderRemote {
+  fn describe(&self) -> String {
+    format!("folder:{}", self.root.display())
+  }
+
+  async fn changes(&self, since: u64) -> Result<RemoteChanges, String> {
+    let manifest = self.load_manifest()?;
+    Ok(RemoteChanges {
+      entries: manifest
+        .notes
+        .into_values()
+        .filter(|entry| entry.revision > since)
+        .collect(),
+      cursor: manifest.revision,
+    })
+  }
+
+  async fn fetch(&self, id: &str) -> Result<Option<Value>, String> {
+    let path = self.note_file(id)?;
+    if !path.exists() {
+      return Ok(None);
+    }
+    let content =
+      fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    serde_json::from_str(&content)
+      .map(Some)
+      .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+  }
+
+  async fn push(
+    &self,
+    entry: &RemoteEntry,
+    note: Option<&Value>,
+    base: u64,
+  ) -> Result<Option<u64>, String> {
+    let path = self.note_file(&entry.id)?;
+    let _lock = self.lock()?;
+    let mut manifest = self.load_manifest()?;
+    if manifest
+      .notes
+      .get(&entry.id)
+      .is_some_and(|existing| existing.revision > base)
+    {
+      return Ok(None);
+    }
+
+    match note {
+      Some(note) => {
+        let content = serde_json::to_string_pretty(note)
+          .map_err(|e| format!("Failed to serialize note: {}", e))?;
+        write_atomic(&path, &content)?;
+      }
+      None => {
+        if path.exists() {
+          fs::remove_file(&path)
+            .map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+        }
+      }
+    }
+
+    manifest.revision += 1;
+    let mut entry = entry.clone();
+    entry.revision = manifest.revision;
+    manifest.notes.insert(entry.id.clone(), entry);
+    let content = serde_json::to_string_pretty(&manifest)
+      .map_err(|e| format!("Failed to serialize manifest: {}", e))?;
+    write_atomic(&self.manifest_path(), &content)?;
+    Ok(Some(manifest.revision))
+  }
+
+  async fn load_meta(&self, name: &str) -> Result<Option<(Value, u64)>, String> {
+    Ok(
+      self
+        .read_meta(name)?
+        .map(|document| (document.value, document.version)),
+    )
+  }
+
+  async fn store_meta(
+    &self,
+    name: &str,
+    value: &Value,
+    expected: Option<u64>,
+  ) -> Result<bool, String> {
+    let path = self.meta_file(name)?;
+    let _lock = self.lock()?;
+    // Read it again under the lock, so a device that stored it in the meantime isn't overwritten
+    let current = self.read_meta(name)?.map(|document| document.version);
+    if current != expected {
+      return Ok(false);
+    }
+    let document = MetaDocument {
+      version: current.unwrap_or(0) + 1,
+      value: value.clone(),
+    };
+    let content = serde_json::to_string_pretty(&document)
+      .map_err(|e| format!("Failed to serialize {}: {}", name, e))?;
+    write_atomic(&path, &content)?;
+    Ok(true)
+  }
+
+  // Notes are whole files, so nothing outlives them
+  async fn prune(&self) -> Result<(), String> {
+    Ok(())
+  }
+}
@@ -1,3 +1,5 @@
+use crate::qdrant::remove_note_embeddings;
+use crate::search::remove_note_index;
 use array_list::ArrayList;
 use chrono::{DateTime, Utc};
 use directories::ProjectDirs;
@@ -7,6 +9,7 @@ use std::collections::{HashMap, VecDeque};
 use std::fs;
 use std::io::{self, Error as IoError};
 use std::path::{Path, PathBuf};
+use std::sync::RwLock;
 use tauri::{command, AppHandle, Runtime, Window};
 use uuid::Uuid;
 
@@ -24,6 +27,13 @@ pub struct Metadata {
   pub last_accessed: DateTime<Utc>,
   pub note_type: String, // Changed to String to simplify
   pub tags: Vec<String>,
+  // Filled in by the background summary job
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub summary: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub suggested_title: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub summarized_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -37,8 +47,15 @@ pub struct PageContent {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DrawingData {
+  // Missing on strokes drawn before strokes had ids
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<String>,
   pub tool: String,
   pub points: Vec<f64>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub color: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub width: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -48,7 +65,18 @@ pub struct Note {
   pub pages: Vec<PageContent>,
 }
 
+// Data directory used by tests on the current thread instead of the real one
+#[cfg(test)]
+thread_local! {
+  pub static TEST_DATA_DIR: std::cell::RefCell<Option<PathBuf>> =
+    const { std::cell::RefCell::new(None) };
+}
+
 pub fn get_app_data_dir() -> Result<PathBuf, String> {
+  #[cfg(test)]
+  if let Some(dir) = TEST_DATA_DIR.with(|dir| dir.borrow().clone()) {
+    return Ok(dir);
+  }
   if let Some(proj_dirs) = ProjectDirs::from("com", "ehcaw", "neurate") {
     // Get the data directory path
     let data_dir = proj_dirs.data_dir();
@@ -110,12 +138,11 @@ pub fn path_exists(path: &str) -> bool {
 
 // Move a file or directory
 #[tauri::command]
-pub fn move_path(source: &str, destination: &str) -> Result<(), String> {
-  fs::rename(
-    std::path::Path::new(source),
-    std::path::Path::new(destination),
-  )
-  .map_err(|err| format!("Failed to rename {} to {}: {}", source, destination, err))
+pub async fn move_path(source: String, destination: String) -> Result<(), String> {
+  // Goes through the refactor so links to the moved notes follow them
+  crate::refactor::move_note(source, destination)
+    .await
+    .map(|_| ())
 }
 
 // Delete a file or directory
@@ -174,6 +201,125 @@ pub fn calculate_directory_size(path: &str) -> Result<u64, String> {
   Ok(total_size)
 }
 
+// Functions run with the path of every note saved through the note commands
+static NOTE_SAVED_HOOKS: RwLock<Vec<fn(&str)>> = RwLock::new(Vec::new());
+
+// Register a function to run whenever a note is saved, e.g. to keep an index up to date
+pub fn on_note_saved(hook: fn(&str)) {
+  NOTE_SAVED_HOOKS.write().unwrap().push(hook);
+}
+
+pub fn notify_note_saved(path: &str) {
+  for hook in NOTE_SAVED_HOOKS.read().unwrap().iter() {
+    hook(path);
+  }
+}
+
+// Path of every note file by note id, filled from the notes directory on first use
+static NOTE_PATHS: RwLock<Option<HashMap<String, PathBuf>>> = RwLock::new(None);
+
+// Give a note a UUID id if it has none yet. Older notes used their file path as id; those get
+// the UUID in their file name where possible so existing file names stay meaningful.
+fn ensure_note_id(path: &Path, note: &mut Value, taken: &HashMap<String, PathBuf>) -> bool {
+  if let Some(id) = note["id"].as_str() {
+    if Uuid::parse_str(id).is_ok() && !taken.contains_key(id) {
+      return false;
+    }
+  }
+  let id = path
+    .file_stem()
+    .and_then(|stem| stem.to_str())
+    .filter(|stem| Uuid::parse_str(stem).is_ok() && !taken.contains_key(*stem))
+    .map(str::to_string)
+    .unwrap_or_else(|| Uuid::new_v4().to_string());
+  note["id"] = json!(id);
+  true
+}
+
+// Read the id of every note. With `assign`, notes without a valid id are given one and rewritten.
+fn scan_note_ids(assign: bool) -> Result<HashMap<String, PathBuf>, String> {
+  let mut ids = HashMap::new();
+  for path in collect_note_paths()? {
+    let Some(path_str) = path.to_str() else {
+      continue;
+    };
+    let Ok(mut note) = read_note(path_str) else {
+      continue;
+    };
+    if assign && ensure_note_id(&path, &mut note, &ids) {
+      let note_str = serde_json::to_string_pretty(&note)
+        .map_err(|e| format!("Failed to serialize note: {}", e))?;
+      write_file(path_str, &note_str)?;
+    }
+    if let Some(id) = note["id"].as_str() {
+      ids.insert(id.to_string(), path);
+    }
+  }
+  Ok(ids)
+}
+
+// Id stored in the note file at `path`
+pub fn note_id(path: &str) -> Result<String, String> {
+  read_note(path)?["id"]
+    .as_str()
+    .map(str::to_string)
+    .ok_or_else(|| format!("Note {} has no id", path))
+}
+
+// Id of the note last known to be at `path`, which may no longer exist
+pub fn cached_note_id(path: &Path) -> Option<String> {
+  NOTE_PATHS
+    .read()
+    .unwrap()
+    .as_ref()?
+    .iter()
+    .find(|(_, cached)| cached.as_path() == path)
+    .map(|(id, _)| id.clone())
+}
+
+fn remember_note(id: &str, path: &Path) {
+  if let Some(paths) = NOTE_PATHS.write().unwrap().as_mut() {
+    paths.insert(id.to_string(), path.to_path_buf());
+  }
+}
+
+// Resolve a note id to the path of its file. Paths of existing note files are accepted as well
+// so callers that still hold a path keep working.
+pub fn note_path(id: &str) -> Result<String, String> {
+  if Path::new(id).is_file() {
+    return Ok(id.to_string());
+  }
+  let cached = NOTE_PATHS
+    .read()
+    .unwrap()
+    .as_ref()
+    .and_then(|paths| paths.get(id).cloned());
+  // Files moved outside the note commands make the cache stale, so rescan on a miss. The rescan
+  // only reads; ids are assigned by migrate_note_ids.
+  let path = match cached.filter(|path| path.is_file()) {
+    Some(path) => path,
+    None => {
+      let paths = scan_note_ids(false)?;
+      let path = paths.get(id).cloned();
+      *NOTE_PATHS.write().unwrap() = Some(paths);
+      path.ok_or_else(|| format!("Note {} not found", id))?
+    }
+  };
+  path
+    .to_str()
+    .map(str::to_string)
+    .ok_or_else(|| "Invalid path encoding".to_string())
+}
+
+// Assign ids to notes created before notes had their own ids. Returns the number of notes.
+#[tauri::command]
+pub fn migrate_note_ids() -> Result<usize, String> {
+  let paths = scan_note_ids(true)?;
+  let count = paths.len();
+  *NOTE_PATHS.write().unwrap() = Some(paths);
+  Ok(count)
+}
+
 pub fn add_page(note: &mut Note, content: String, drawings: Vec<DrawingData>) {
   let now = Utc::now();
   let page = PageContent {
@@ -191,12 +337,13 @@ pub fn create_new_note(title: &str, note_type: &str) -> Result<String, String> {
   let now = Utc::now();
   let app_dir = get_app_data_dir()?;
   let notes_dir = app_dir.join("notes");
-  let file_name = format!("{}.json", Uuid::new_v4());
+  let id = Uuid::new_v4().to_string();
+  let file_name = format!("{}.json", id);
   let file_path = notes_dir.join(&file_name);
   let note: serde_json::Value;
   if note_type == &String::from("notebook") {
     note = json!({
-        "id": file_path,
+        "id": id,
         "title": title,
         "metadata": {
             "created_at": now,
@@ -213,7 +360,7 @@ pub fn create_new_note(title: &str, note_type: &str) -> Result<String, String> {
     });
   } else {
     note = json!({
-        "id": file_path,
+        "id": id,
         "title": title,
         "metadata": {
             "created_at": now,
@@ -237,17 +384,15 @@ pub fn create_new_note(title: &str, note_type: &str) -> Result<String, String> {
     serde_json::to_string_pretty(&note).map_err(|e| format!("Failed to create new note: {}", e))?;
   // Write the file
   write_file(path_str, &note_str)?;
-  Ok(
-    file_path
-      .to_str()
-      .ok_or_else(|| "invalid path encoding".to_string())?
-      .to_string(),
-  )
+  remember_note(&id, &file_path);
+  notify_note_saved(path_str);
+  Ok(id)
 }
 
 // Additional functionality to update an existing note
 #[tauri::command]
-pub fn update_notebook_content(path: &str, page_id: &str, content: &str) -> Result<(), String> {
+pub fn update_notebook_content(id: &str, page_id: &str, content: &str) -> Result<(), String> {
+  let path = &note_path(id)?;
   // Read the current note
   let note_str = read_file(path)?;
 
@@ -279,16 +424,19 @@ pub fn update_notebook_content(path: &str, page_id: &str, content: &str) -> Resu
   let updated_str =
     serde_json::to_string_pretty(&note).map_err(|e| format!("Failed to serialize note: {}", e))?;
 
-  write_file(path, &updated_str)
+  write_file(path, &updated_str)?;
+  notify_note_saved(path);
+  Ok(())
 }
 
 #[tauri::command]
 pub fn update_freenote_content(
-  path: &str,
+  id: &str,
   page_id: &str,
   content: &str,
   lines: &str,
 ) -> Result<(), String> {
+  let path = &note_path(id)?;
   let note_str = read_file(path)?;
   let mut note: Value =
     serde_json::from_str(&note_str).map_err(|e| format!("Failed to parse note JSON: {}", e))?;
@@ -313,17 +461,30 @@ pub fn update_freenote_content(
   }
   let updated_str =
     serde_json::to_string_pretty(&note).map_err(|e| format!("Failed to serialize note: {}", e))?;
-  write_file(path, &updated_str)
+  write_file(path, &updated_str)?;
+  notify_note_saved(path);
+  Ok(())
 }
 
 #[tauri::command]
 pub fn gather_notes() -> Result<Vec<String>, String> {
+  let mut files = Vec::new();
+  for path in collect_note_paths()? {
+    if let Some(path_str) = path.to_str() {
+      files.push(read_file(path_str)?);
+    }
+  }
+  Ok(files)
+}
+
+// Walk the notes directory and return the path of every note file in it
+pub fn collect_note_paths() -> Result<Vec<PathBuf>, String> {
   let pathname = get_app_data_dir()?;
   let notes_dir = pathname.join("notes");
   if !notes_dir.exists() {
     return Ok(Vec::new());
   }
-  let mut files = Vec::new(); // ArrayList is not a standard Rust type
+  let mut paths = Vec::new();
   let mut q = VecDeque::new();
 
   q.push_back(notes_dir);
@@ -331,7 +492,7 @@ pub fn gather_notes() -> Result<Vec<String>, String> {
   while let Some(curr) = q.pop_front() {
     if curr.is_dir() {
       let entries =
-        fs::read_dir(&curr).map_err(|e| format!("Failed to read directory {:?}: {}", curr, e))?; // Add ? to propagate error
+        fs::read_dir(&curr).map_err(|e| format!("Failed to read directory {:?}: {}", curr, e))?;
 
       for entry_result in entries {
         let entry = entry_result.map_err(|e| format!("Failed to read entry: {}", e))?;
@@ -341,15 +502,94 @@ pub fn gather_notes() -> Result<Vec<String>, String> {
         if path.is_dir() {
           q.push_back(path);
         } else if path.is_file() && path.extension().and_then(|ext| ext.to_str()) == Some("json") {
-          if let Some(path_str) = path.to_str() {
-            let content = read_file(path_str)?;
-            files.push(content); // Use push() for Vec, not push_back()
+          paths.push(path);
+        }
+      }
+    }
+  }
+  Ok(paths)
+}
+
+// Read and parse a note file
+pub fn read_note(path: &str) -> Result<Value, String> {
+  let note_str = read_file(path)?;
+  serde_json::from_str(&note_str).map_err(|e| format!("Failed to parse note JSON: {}", e))
+}
+
+// Serialize and save a note file
+pub fn write_note(path: &str, note: &Value) -> Result<(), String> {
+  let note_str =
+    serde_json::to_string_pretty(note).map_err(|e| format!("Failed to serialize note: {}", e))?;
+  write_file(path, &note_str)?;
+  notify_note_saved(path);
+  Ok(())
+}
+
+// Extract the plain text of every page in a note as (page id, text) pairs.
+// Pages of both note types hold their typed text as HTML; free-note pages add whatever
+// handwriting recognition has transcribed from their strokes.
+pub fn note_page_texts(note: &Value) -> Vec<(String, String)> {
+  let is_notebook = note["metadata"]["note_type"].as_str() == Some("notebook");
+  let mut texts = Vec::new();
+  if let Some(pages) = note["pages"].as_array() {
+    for page in pages {
+      let Some(page_id) = page["id"].as_str() else {
+        continue;
+      };
+      let content = page["content"].as_str().unwrap_or("");
+      // New free notes start out with an empty stroke list in place of HTML
+      let content = if !is_notebook && content.trim_start().starts_with('[') {
+        ""
+      } else {
+        content
+      };
+      let mut text = strip_html(content);
+      if !is_notebook {
+        let recognized = page["recognized_text"].as_str().unwrap_or("").trim();
+        if !recognized.is_empty() {
+          if !text.is_empty() {
+            text.push(' ');
           }
+          text.push_str(recognized);
         }
       }
+      texts.push((page_id.to_string(), text));
     }
   }
-  Ok(files)
+  texts
+}
+
+// Timestamp stored in a note as an RFC 3339 string
+pub fn parse_time(value: &Value) -> Option<DateTime<Utc>> {
+  value
+    .as_str()
+    .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+    .map(|time| time.with_timezone(&Utc))
+}
+
+// Reduce HTML to its visible text, collapsing whitespace
+pub fn strip_html(html: &str) -> String {
+  let mut text = String::with_capacity(html.len());
+  let mut in_tag = false;
+  for c in html.chars() {
+    match c {
+      '<' => {
+        in_tag = true;
+        text.push(' ');
+      }
+      '>' => in_tag = false,
+      _ if !in_tag => text.push(c),
+      _ => {}
+    }
+  }
+  let text = text
+    .replace("&nbsp;", " ")
+    .replace("&lt;", "<")
+    .replace("&gt;", ">")
+    .replace("&quot;", "\"")
+    .replace("&#39;", "'")
+    .replace("&amp;", "&");
+  text.split_whitespace().collect::<Vec<_>>().join(" ")
 }
 
 #[tauri::command]
@@ -504,13 +744,30 @@ fn process_directory(
 }
 
 #[tauri::command]
-pub fn update_title(path: &str, new_title: &str) -> Result<(), String> {
-  let note = read_file(path)?;
-
-  let mut json: Value = serde_json::from_str(&note).map_err(|e| e.to_string())?;
-  json["title"] = json!(new_title);
+pub async fn update_title(id: String, new_title: String) -> Result<(), String> {
+  // Goes through the refactor so links to the note by its old title are rewritten
+  crate::refactor::rename_note(id, new_title)
+    .await
+    .map(|_| ())
+}
 
-  let updated_json = serde_json::to_string_pretty(&json).map_err(|e| e.to_string())?;
-  write_file(path, &updated_json)?;
+// Delete the note with the given id
+#[tauri::command]
+pub fn delete_note(id: &str) -> Result<(), String> {
+  let path = note_path(id)?;
+  let id = note_id(&path).unwrap_or_else(|_| id.to_string());
+  fs::remove_file(&path).map_err(|e| format!("Failed to delete note {}: {}", id, e))?;
+  if let Some(paths) = NOTE_PATHS.write().unwrap().as_mut() {
+    paths.remove(&id);
+  }
+  if let Err(e) = remove_note_index(&id) {
+    eprintln!("Failed to remove {} from the search index: {}", id, e);
+  }
+  // Once the file and its cache entry are gone the watcher can't tell which note it was
+  tauri::async_runtime::spawn(async move {
+    if let Err(e) = remove_note_embeddings(&id).await {
+      eprintln!("Failed to remove embeddings of {}: {}", id, e);
+    }
+  });
   Ok(())
 }
@@ -0,0 +1,322 @@
+use crate::fs::{collect_note_paths, get_app_data_dir, note_path, read_note};
+use crate::links::link_edges;
+use crate::related::{note_vector, Pooling};
+use crate::tagging::note_tags;
+use crate::vector_store::{dot, normalize};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::path::Path;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeKind {
+  Note,
+  Tag,
+  Folder,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeKind {
+  // Explicit [[link]], weighted by the number of links
+  Link,
+  // Note carries the tag
+  Tag,
+  // Note or folder is inside the folder
+  Folder,
+  // Embeddings are similar, weighted by cosine similarity
+  Similarity,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct GraphNode {
+  // "note:<id>", "tag:<name>" or "folder:<path relative to the notes directory>"
+  pub id: String,
+  pub kind: NodeKind,
+  pub label: String,
+  // Set for note nodes
+  pub note_id: Option<String>,
+  pub path: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct GraphEdge {
+  pub source: String,
+  pub target: String,
+  pub kind: EdgeKind,
+  pub weight: f32,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct KnowledgeGraph {
+  pub nodes: Vec<GraphNode>,
+  pub edges: Vec<GraphEdge>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GraphOptions {
+  // Only notes inside this folder, relative to the notes directory
+  pub folder: Option<String>,
+  // Only notes carrying this tag
+  pub tag: Option<String>,
+  pub include_tags: bool,
+  pub include_folders: bool,
+  pub include_similarity: bool,
+  // Minimum cosine similarity for a similarity edge
+  pub similarity_threshold: f32,
+  // Limit the graph to the neighbourhood of this note id
+  pub center: Option<String>,
+  // Hops from `center` to include
+  pub depth: usize,
+}
+
+impl Default for GraphOptions {
+  fn default() -> Self {
+    GraphOptions {
+      folder: None,
+      tag: None,
+      include_tags: true,
+      include_folders: true,
+      include_similarity: false,
+      similarity_threshold: 0.8,
+      center: None,
+      depth: 2,
+    }
+  }
+}
+
+struct GraphNote {
+  node_id: String,
+  path: String,
+  folder: String,
+  note: Value,
+}
+
+fn note_node_id(note_id: &str) -> String {
+  format!("note:{}", note_id)
+}
+
+fn folder_node_id(folder: &str) -> String {
+  format!("folder:{}", folder)
+}
+
+// Folder of a note relative to the notes directory, "" for the top level
+fn relative_folder(notes_dir: &Path, path: &Path) -> String {
+  path
+    .parent()
+    .and_then(|parent| parent.strip_prefix(notes_dir).ok())
+    .map(|folder| folder.to_string_lossy().replace('\\', "/"))
+    .unwrap_or_default()
+}
+
+fn in_folder(note_folder: &str, folder: &str) -> bool {
+  let folder = folder.trim_matches('/');
+  folder.is_empty() || note_folder == folder || note_folder.starts_with(&format!("{}/", folder))
+}
+
+// Keep only the nodes within `depth` hops of `center` and the edges between them
+fn neighbourhood(graph: KnowledgeGraph, center: &str, depth: usize) -> KnowledgeGraph {
+  let mut adjacent: HashMap<&str, Vec<&str>> = HashMap::new();
+  for edge in &graph.edges {
+    adjacent.entry(&edge.source).or_default().push(&edge.target);
+    adjacent.entry(&edge.target).or_default().push(&edge.source);
+  }
+
+  let mut reached: HashSet<String> = HashSet::from([center.to_string()]);
+  let mut queue = VecDeque::from([(center, 0)]);
+  while let Some((node, distance)) = queue.pop_front() {
+    if distance == depth {
+      continue;
+    }
+    for next in adjacent.get(node).into_iter().flatten() {
+      if reached.insert(next.to_string()) {
+        queue.push_back((next, distance + 1));
+      }
+    }
+  }
+
+  KnowledgeGraph {
+    nodes: graph
+      .nodes
+      .into_iter()
+      .filter(|node| reached.contains(&node.id))
+      .collect(),
+    edges: graph
+      .edges
+      .into_iter()
+      .filter(|edge| reached.contains(&edge.source) && reached.contains(&edge.target))
+      .collect(),
+  }
+}
+
+// Graph of the vault for the graph view: notes, tags and folders as nodes, connected by links,
+// tags, folder containment and, if requested, embedding similarity. `options.center` narrows the
+// graph to the neighbourhood of one note.
+#[tauri::command]
+pub async fn get_knowledge_graph(options: Option<GraphOptions>) -> Result<KnowledgeGraph, String> {
+  let options = options.unwrap_or_default();
+  let notes_dir = get_app_data_dir()?.join("notes");
+
+  let mut notes = Vec::new();
+  for path in collect_note_paths()? {
+    let Some(path_str) = path.to_str() else {
+      continue;
+    };
+    let Ok(note) = read_note(path_str) else {
+      continue;
+    };
+    let folder = relative_folder(&notes_dir, &path);
+    if options
+      .folder
+      .as_deref()
+      .is_some_and(|filter| !in_folder(&folder, filter))
+    {
+      continue;
+    }
+    if options
+      .tag
+      .as_ref()
+      .is_some_and(|tag| !note_tags(&note).contains(tag))
+    {
+      continue;
+    }
+    let Some(note_id) = note["id"].as_str() else {
+      continue;
+    };
+    notes.push(GraphNote {
+      node_id: note_node_id(note_id),
+      path: path_str.to_string(),
+      folder,
+      note,
+    });
+  }
+
+  let mut graph = KnowledgeGraph::default();
+  let by_path: HashMap<&str, &GraphNote> = notes
+    .iter()
+    .map(|note| (note.path.as_str(), note))
+    .collect();
+  for note in &notes {
+    graph.nodes.push(GraphNode {
+      id: note.node_id.clone(),
+      kind: NodeKind::Note,
+      label: note.note["title"].as_str().unwrap_or("").to_string(),
+      note_id: note.note["id"].as_str().map(str::to_string),
+      path: Some(note.path.clone()),
+    });
+  }
+
+  for (source, target, count) in link_edges()? {
+    if let (Some(source), Some(target)) =
+      (by_path.get(source.as_str()), by_path.get(target.as_str()))
+    {
+      graph.edges.push(GraphEdge {
+        source: source.node_id.clone(),
+        target: target.node_id.clone(),
+        kind: EdgeKind::Link,
+        weight: count as f32,
+      });
+    }
+  }
+
+  if options.include_tags {
+    let mut tags: BTreeMap<String, Vec<&GraphNote>> = BTreeMap::new();
+    for note in &notes {
+      for tag in note_tags(&note.note) {
+        tags.entry(tag).or_default().push(note);
+      }
+    }
+    for (tag, tagged) in tags {
+      let tag_id = format!("tag:{}", tag);
+      for note in tagged {
+        graph.edges.push(GraphEdge {
+          source: note.node_id.clone(),
+          target: tag_id.clone(),
+          kind: EdgeKind::Tag,
+          weight: 1.0,
+        });
+      }
+      graph.nodes.push(GraphNode {
+        id: tag_id,
+        kind: NodeKind::Tag,
+        label: tag,
+        note_id: None,
+        path: None,
+      });
+    }
+  }
+
+  if options.include_folders {
+    let mut folders: HashSet<String> = HashSet::new();
+    for note in &notes {
+      if note.folder.is_empty() {
+        continue;
+      }
+      graph.edges.push(GraphEdge {
+        source: note.node_id.clone(),
+        target: folder_node_id(&note.folder),
+        kind: EdgeKind::Folder,
+        weight: 1.0,
+      });
+      // Add the folder and every ancestor folder under the notes directory
+      let mut folder = note.folder.as_str();
+      while folders.insert(folder.to_string()) {
+        let (parent, name) = folder.rsplit_once('/').unwrap_or(("", folder));
+        graph.nodes.push(GraphNode {
+          id: folder_node_id(folder),
+          kind: NodeKind::Folder,
+          label: name.to_string(),
+          note_id: None,
+          path: notes_dir.join(folder).to_str().map(str::to_string),
+        });
+        if parent.is_empty() {
+          break;
+        }
+        graph.edges.push(GraphEdge {
+          source: folder_node_id(folder),
+          target: folder_node_id(parent),
+          kind: EdgeKind::Folder,
+          weight: 1.0,
+        });
+        folder = parent;
+      }
+    }
+  }
+
+  if options.include_similarity {
+    let mut vectors = Vec::new();
+    for note in &notes {
+      let note_id = note.note["id"].as_str().unwrap_or("");
+      if let Some(vector) = note_vector(note_id, None, Pooling::Mean).await? {
+        vectors.push((note, normalize(vector)));
+      }
+    }
+    for (index, (note, vector)) in vectors.iter().enumerate() {
+      for (other, other_vector) in &vectors[index + 1..] {
+        let similarity = dot(vector, other_vector);
+        if similarity >= options.similarity_threshold {
+          graph.edges.push(GraphEdge {
+            source: note.node_id.clone(),
+            target: other.node_id.clone(),
+            kind: EdgeKind::Similarity,
+            weight: similarity,
+          });
+        }
+      }
+    }
+  }
+
+  match &options.center {
+    Some(center) => {
+      let center_path = note_path(center)?;
+      let center_id = by_path
+        .get(center_path.as_str())
+        .map(|note| note.node_id.clone())
+        .ok_or_else(|| format!("Note {} is not part of the graph", center))?;
+      Ok(neighbourhood(graph, &center_id, options.depth))
+    }
+    None => Ok(graph),
+  }
+}
@@ -0,0 +1,227 @@
+use crate::config::get_config;
+use crate::fs::{collect_note_paths, note_path, parse_time, read_note, write_note, DrawingData};
+use crate::ollama::get_ollama_client;
+use crate::qdrant::index_note_file;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use ollama_rs::generation::{
+  chat::{request::ChatMessageRequest, ChatMessage},
+  images::Image,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::Path;
+use tiny_skia::{Color, LineCap, LineJoin, Paint, PathBuilder, Pixmap, Stroke, Transform};
+
+// Longest side of the rendered page image
+const MAX_IMAGE_SIZE: f32 = 1024.0;
+// Blank border around the strokes, in canvas units
+const MARGIN: f32 = 20.0;
+const DEFAULT_STROKE_WIDTH: f32 = 5.0;
+
+const TRANSCRIBE_PROMPT: &str = "Transcribe the handwritten text in this image. \
+Reply with only the transcribed text, keeping line breaks. \
+If there is no legible text, reply with nothing.";
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PageRecognition {
+  pub page_id: String,
+  pub text: String,
+}
+
+// Strokes of a free-note page; `lines` is stored as a JSON string
+fn page_strokes(page: &Value) -> Vec<DrawingData> {
+  match &page["lines"] {
+    Value::String(lines) => serde_json::from_str(lines).unwrap_or_default(),
+    lines @ Value::Array(_) => serde_json::from_value(lines.clone()).unwrap_or_default(),
+    _ => Vec::new(),
+  }
+}
+
+// Render strokes black on white, cropped to the drawing, as PNG bytes.
+// Eraser strokes are painted white. Returns None for pages without any ink.
+pub fn render_strokes(strokes: &[DrawingData]) -> Result<Option<Vec<u8>>, String> {
+  let ink: Vec<&DrawingData> = strokes
+    .iter()
+    .filter(|stroke| stroke.tool != "eraser" && stroke.points.len() >= 2)
+    .collect();
+  if ink.is_empty() {
+    return Ok(None);
+  }
+
+  let (mut min_x, mut min_y) = (f32::MAX, f32::MAX);
+  let (mut max_x, mut max_y) = (f32::MIN, f32::MIN);
+  for stroke in &ink {
+    for point in stroke.points.chunks_exact(2) {
+      min_x = min_x.min(point[0] as f32);
+      min_y = min_y.min(point[1] as f32);
+      max_x = max_x.max(point[0] as f32);
+      max_y = max_y.max(point[1] as f32);
+    }
+  }
+  let width = max_x - min_x + 2.0 * MARGIN;
+  let height = max_y - min_y + 2.0 * MARGIN;
+  let scale = (MAX_IMAGE_SIZE / width.max(height)).min(1.0);
+
+  let mut pixmap = Pixmap::new(
+    (width * scale).ceil().max(1.0) as u32,
+    (height * scale).ceil().max(1.0) as u32,
+  )
+  .ok_or_else(|| "Failed to allocate page image".to_string())?;
+  pixmap.fill(Color::WHITE);
+  let transform = Transform::from_row(
+    scale,
+    0.0,
+    0.0,
+    scale,
+    (MARGIN - min_x) * scale,
+    (MARGIN - min_y) * scale,
+  );
+
+  for stroke in strokes {
+    let mut points = stroke.points.chunks_exact(2);
+    let Some(start) = points.next() else {
+      continue;
+    };
+    let mut path = PathBuilder::new();
+    path.move_to(start[0] as f32, start[1] as f32);
+    // A single tap still needs a segment to leave a dot
+    path.line_to(start[0] as f32 + 0.1, start[1] as f32);
+    for point in points {
+      path.line_to(point[0] as f32, point[1] as f32);
+    }
+    let Some(path) = path.finish() else {
+      continue;
+    };
+
+    let mut paint = Paint::default();
+    if stroke.tool == "eraser" {
+      paint.set_color(Color::WHITE);
+    } else {
+      paint.set_color(Color::BLACK);
+    }
+    paint.anti_alias = true;
+    let line = Stroke {
+      width: stroke
+        .width
+        .map(|w| w as f32)
+        .unwrap_or(DEFAULT_STROKE_WIDTH),
+      line_cap: LineCap::Round,
+      line_join: LineJoin::Round,
+      ..Stroke::default()
+    };
+    pixmap.stroke_path(&path, &paint, &line, transform, None);
+  }
+
+  pixmap
+    .encode_png()
+    .map(Some)
+    .map_err(|e| format!("Failed to encode page image: {}", e))
+}
+
+// Transcribe the handwriting in a PNG image with the configured vision model
+async fn transcribe(png: &[u8]) -> Result<String, String> {
+  let model = get_config()?.vision_model;
+  let message = ChatMessage::user(TRANSCRIBE_PROMPT.to_string())
+    .with_images(vec![Image::from_base64(STANDARD.encode(png))]);
+  let response = get_ollama_client()?
+    .send_chat_messages(ChatMessageRequest::new(model.clone(), vec![message]))
+    .await
+    .map_err(|e| format!("Transcription with '{}' failed: {}", model, e))?;
+  Ok(response.message.content.trim().to_string())
+}
+
+// Transcribe the free-note pages of the note at `path` and store the text on each page.
+// Pages already transcribed since their last edit are skipped unless `force` is set.
+pub async fn recognize_note(
+  path: &str,
+  page_id: Option<&str>,
+  force: bool,
+) -> Result<Vec<PageRecognition>, String> {
+  let note = read_note(path)?;
+  if note["metadata"]["note_type"].as_str() == Some("notebook") {
+    return Ok(Vec::new());
+  }
+
+  let mut recognized = Vec::new();
+  for page in note["pages"].as_array().into_iter().flatten() {
+    let Some(id) = page["id"].as_str() else {
+      continue;
+    };
+    if page_id.is_some_and(|page_id| page_id != id) {
+      continue;
+    }
+    let up_to_date = match (
+      parse_time(&page["recognized_at"]),
+      parse_time(&page["last_modified"]),
+    ) {
+      (Some(recognized_at), Some(modified)) => recognized_at >= modified,
+      (Some(_), None) => true,
+      _ => false,
+    };
+    if up_to_date && !force {
+      continue;
+    }
+    let text = match render_strokes(&page_strokes(page))? {
+      Some(png) => transcribe(&png).await?,
+      None => String::new(),
+    };
+    recognized.push(PageRecognition {
+      page_id: id.to_string(),
+      text,
+    });
+  }
+  if recognized.is_empty() {
+    return Ok(recognized);
+  }
+
+  // Re-read so strokes drawn while the model was working are kept
+  let mut note = read_note(path)?;
+  let now = Utc::now();
+  if let Some(pages) = note["pages"].as_array_mut() {
+    for page in pages.iter_mut() {
+      let Some(result) = recognized
+        .iter()
+        .find(|result| page["id"].as_str() == Some(result.page_id.as_str()))
+      else {
+        continue;
+      };
+      page["recognized_text"] = json!(result.text);
+      page["recognized_at"] = json!(now);
+    }
+  }
+  write_note(path, &note)?;
+
+  // Make the transcription searchable straight away
+  if let Err(e) = index_note_file(Path::new(path)).await {
+    eprintln!("Failed to index handwriting of {}: {}", path, e);
+  }
+  Ok(recognized)
+}
+
+// Transcribe the handwriting of one note, or one page of it
+#[tauri::command]
+pub async fn recognize_handwriting(
+  id: String,
+  page_id: Option<String>,
+  force: Option<bool>,
+) -> Result<Vec<PageRecognition>, String> {
+  recognize_note(&note_path(&id)?, page_id.as_deref(), force.unwrap_or(false)).await
+}
+
+// Transcribe every free-note page in the vault that changed since its last transcription.
+// Returns the number of pages transcribed.
+#[tauri::command]
+pub async fn recognize_vault_handwriting() -> Result<usize, String> {
+  let mut count = 0;
+  for path in collect_note_paths()? {
+    let Some(path_str) = path.to_str() else {
+      continue;
+    };
+    match recognize_note(path_str, None, false).await {
+      Ok(pages) => count += pages.len(),
+      Err(e) => eprintln!("Failed to recognize handwriting in {}: {}", path_str, e),
+    }
+  }
+  Ok(count)
+}
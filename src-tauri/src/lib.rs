@@ -1,17 +1,37 @@
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 use std::time::{SystemTime, UNIX_EPOCH};
 
+mod clustering;
+mod config;
+mod docker;
+mod duplicates;
+mod embedder;
+mod encrypted_remote;
+mod folder_remote;
 mod fs;
-mod qdrant;
-mod ollama;
+mod graph;
+mod handwriting;
+mod links;
+mod local_store;
+mod merge;
 mod mongo;
-
+mod ollama;
+mod qdrant;
+mod rag;
+mod refactor;
+mod related;
+mod search;
+mod services;
+mod summary;
+mod sync;
+mod tagging;
+mod vector_store;
 
 // Re-export the functions from the fs module
 use fs::{
-  calculate_directory_size, create_directory, create_new_note, delete_path, gather_notes,
-  get_notes_tree, move_path, path_exists, read_file, update_freenote_content,
-  update_notebook_content, update_title, write_file,
+  calculate_directory_size, create_directory, create_new_note, delete_note, delete_path,
+  gather_notes, get_notes_tree, migrate_note_ids, move_path, path_exists, read_file,
+  update_freenote_content, update_notebook_content, update_title, write_file,
 };
 
 #[tauri::command]
@@ -30,6 +50,19 @@ fn my_test_command() {
 pub fn run() {
   tauri::Builder::default()
     .plugin(tauri_plugin_opener::init())
+    .setup(|app| {
+      services::start_services(app.handle().clone());
+      summary::start_summary_job(app.handle().clone());
+      ollama::report_ai_status(app.handle().clone());
+      fs::on_note_saved(links::update_note_links);
+      fs::on_note_saved(search::update_note_index);
+      tauri::async_runtime::spawn(async {
+        if let Err(e) = migrate_note_ids() {
+          eprintln!("Failed to migrate note ids: {}", e);
+        }
+      });
+      Ok(())
+    })
     .invoke_handler(tauri::generate_handler![
       greet,
       my_test_command,
@@ -45,8 +78,68 @@ pub fn run() {
       get_notes_tree,
       update_title,
       update_freenote_content,
-      update_notebook_content
+      update_notebook_content,
+      delete_note,
+      migrate_note_ids,
+      qdrant::reindex_vault,
+      qdrant::cancel_reindex,
+      qdrant::semantic_search,
+      search::index_notes,
+      search::search_notes,
+      related::suggest_connections,
+      rag::ask_notes,
+      ollama::ai_assist,
+      ollama::cancel_generation,
+      ollama::ollama_status,
+      ollama::list_ollama_models,
+      ollama::pull_ollama_model,
+      ollama::ai_status,
+      summary::regenerate_summary,
+      summary::accept_suggested_title,
+      summary::dismiss_suggested_title,
+      tagging::suggest_tags,
+      tagging::suggest_tags_for_untagged,
+      tagging::apply_tags,
+      tagging::list_vault_tags,
+      handwriting::recognize_handwriting,
+      handwriting::recognize_vault_handwriting,
+      clustering::cluster_notes,
+      clustering::get_smart_folders,
+      duplicates::find_duplicates,
+      duplicates::merge_notes,
+      links::get_backlinks,
+      links::get_outgoing_links,
+      links::get_unresolved_links,
+      links::complete_link,
+      links::rebuild_link_index,
+      refactor::preview_rename_note,
+      refactor::rename_note,
+      refactor::preview_move_note,
+      refactor::move_note,
+      refactor::undo_refactor,
+      graph::get_knowledge_graph,
+      services::service_status,
+      services::restart_service,
+      docker::detect_container_runtimes,
+      sync::sync_notes,
+      sync::push_notes,
+      sync::pull_notes,
+      sync::sync_status,
+      sync::list_sync_conflicts,
+      sync::resolve_sync_conflict,
+      encrypted_remote::sync_encryption_status,
+      encrypted_remote::unlock_sync_encryption,
+      encrypted_remote::lock_sync_encryption,
+      encrypted_remote::change_sync_passphrase,
+      encrypted_remote::rotate_sync_key,
+      config::load_config,
+      config::save_config
     ])
-    .run(tauri::generate_context!())
-    .expect("error while running tauri application");
+    .build(tauri::generate_context!())
+    .expect("error while building tauri application")
+    .run(|_app_handle, event| {
+      if let tauri::RunEvent::Exit = event {
+        services::stop_services();
+      }
+    });
 }
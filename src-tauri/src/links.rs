@@ -0,0 +1,608 @@
+use crate::fs::{
+  collect_note_paths, get_app_data_dir, note_page_texts, note_path, read_note, strip_html,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Characters of surrounding text kept on each side of a link
+const CONTEXT_CHARS: usize = 40;
+
+// A `[[target#page|label]]` link found in a note
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WikiLink {
+  // Note title or id as written
+  pub target: String,
+  pub page: Option<String>,
+  pub label: Option<String>,
+  // Page of the linking note that contains the link
+  pub source_page: String,
+  pub context: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct IndexedNote {
+  id: String,
+  title: String,
+  links: Vec<WikiLink>,
+}
+
+// Forward links of every note by path, and the paths linking to each note
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct LinkIndex {
+  notes: BTreeMap<String, IndexedNote>,
+  backlinks: BTreeMap<String, Vec<String>>,
+  // Paths by note id and by lowercase title, so links resolve without scanning every note
+  #[serde(skip)]
+  ids: HashMap<String, String>,
+  #[serde(skip)]
+  titles: HashMap<String, String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Backlink {
+  pub id: String,
+  pub path: String,
+  pub title: String,
+  pub page_id: String,
+  // Page of the linked note the link points at, if any
+  pub target_page: Option<String>,
+  pub context: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OutgoingLink {
+  #[serde(flatten)]
+  pub link: WikiLink,
+  // Id and path of the linked note, None if no note matches
+  pub resolved_id: Option<String>,
+  pub resolved: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct UnresolvedLink {
+  pub path: String,
+  pub title: String,
+  pub link: WikiLink,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LinkCompletion {
+  pub id: String,
+  pub path: String,
+  pub title: String,
+}
+
+static LINK_INDEX: Mutex<Option<LinkIndex>> = Mutex::new(None);
+
+fn index_path() -> Result<PathBuf, String> {
+  Ok(get_app_data_dir()?.join("links.json"))
+}
+
+// Id used in `[[id]]` links, falling back to the file name for notes without one
+fn note_id(path: &str, note: &Value) -> String {
+  match note["id"].as_str() {
+    Some(id) => id.to_string(),
+    None => Path::new(path)
+      .file_stem()
+      .and_then(|stem| stem.to_str())
+      .unwrap_or(path)
+      .to_string(),
+  }
+}
+
+// Every `[[...]]` link in a page's text, with the text around it
+pub fn parse_links(text: &str, source_page: &str) -> Vec<WikiLink> {
+  let chars: Vec<char> = text.chars().collect();
+  let mut links = Vec::new();
+  let mut index = 0;
+  while index + 1 < chars.len() {
+    if chars[index] != '[' || chars[index + 1] != '[' {
+      index += 1;
+      continue;
+    }
+    let start = index + 2;
+    let Some(length) = chars[start..]
+      .windows(2)
+      .position(|pair| pair == [']', ']'])
+    else {
+      break;
+    };
+    let inner: String = chars[start..start + length].iter().collect();
+    let end = start + length + 2;
+    if inner.contains('[') {
+      // An opening bracket inside means this wasn't the start of a link
+      index += 1;
+      continue;
+    }
+
+    let (reference, label) = match inner.split_once('|') {
+      Some((reference, label)) => (reference, Some(label.trim().to_string())),
+      None => (inner.as_str(), None),
+    };
+    let (target, page) = match reference.split_once('#') {
+      Some((target, page)) => (target, Some(page.trim().to_string())),
+      None => (reference, None),
+    };
+    let target = target.trim();
+    if !target.is_empty() {
+      let context_start = index.saturating_sub(CONTEXT_CHARS);
+      let context_end = (end + CONTEXT_CHARS).min(chars.len());
+      links.push(WikiLink {
+        target: target.to_string(),
+        page: page.filter(|page| !page.is_empty()),
+        label: label.filter(|label| !label.is_empty()),
+        source_page: source_page.to_string(),
+        context: chars[context_start..context_end].iter().collect(),
+      });
+    }
+    index = end;
+  }
+  links
+}
+
+fn escape_html(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+// Rewrite the target of every `[[...]]` link in page HTML for which `retarget` returns a new
+// target, keeping the page and label. Returns the new HTML and the number of links rewritten.
+pub fn rewrite_links(html: &str, retarget: impl Fn(&str) -> Option<String>) -> (String, usize) {
+  let mut rewritten = String::with_capacity(html.len());
+  let mut count = 0;
+  let mut rest = html;
+  while let Some(start) = rest.find("[[") {
+    let after = &rest[start + 2..];
+    let Some(length) = after.find("]]") else {
+      break;
+    };
+    let inner = &after[..length];
+    rewritten.push_str(&rest[..start]);
+    rest = &after[length + 2..];
+
+    // Links split by formatting tags are left alone
+    let links = if inner.contains('<') || inner.contains('[') {
+      Vec::new()
+    } else {
+      parse_links(&format!("[[{}]]", strip_html(inner)), "")
+    };
+    match links
+      .first()
+      .and_then(|link| retarget(&link.target).map(|target| (link, target)))
+    {
+      Some((link, target)) => {
+        rewritten.push_str("[[");
+        rewritten.push_str(&escape_html(&target));
+        if let Some(page) = &link.page {
+          rewritten.push('#');
+          rewritten.push_str(&escape_html(page));
+        }
+        if let Some(label) = &link.label {
+          rewritten.push('|');
+          rewritten.push_str(&escape_html(label));
+        }
+        rewritten.push_str("]]");
+        count += 1;
+      }
+      None => {
+        rewritten.push_str("[[");
+        rewritten.push_str(inner);
+        rewritten.push_str("]]");
+      }
+    }
+  }
+  rewritten.push_str(rest);
+  (rewritten, count)
+}
+
+fn index_note(path: &str) -> Result<IndexedNote, String> {
+  let note = read_note(path)?;
+  let links = note_page_texts(&note)
+    .iter()
+    .flat_map(|(page_id, text)| parse_links(text, page_id))
+    .collect();
+  Ok(IndexedNote {
+    id: note_id(path, &note),
+    title: note["title"].as_str().unwrap_or("").to_string(),
+    links,
+  })
+}
+
+impl LinkIndex {
+  // Path of the note a link target refers to: a note id or path, or else a title ignoring case
+  fn resolve(&self, target: &str) -> Option<&str> {
+    if let Some(path) = self.ids.get(target) {
+      return Some(path);
+    }
+    if let Some((path, _)) = self.notes.get_key_value(target) {
+      return Some(path);
+    }
+    self.titles.get(&target.to_lowercase()).map(String::as_str)
+  }
+
+  // Where several notes share an id or title, the first by path wins
+  fn index_names(&mut self) {
+    self.ids.clear();
+    self.titles.clear();
+    for (path, note) in &self.notes {
+      self
+        .ids
+        .entry(note.id.clone())
+        .or_insert_with(|| path.clone());
+      self
+        .titles
+        .entry(note.title.trim().to_lowercase())
+        .or_insert_with(|| path.clone());
+    }
+  }
+
+  fn link_targets(&self, links: &[WikiLink]) -> HashSet<String> {
+    links
+      .iter()
+      .filter_map(|link| self.resolve(&link.target))
+      .map(str::to_string)
+      .collect()
+  }
+
+  // Store the links of the note at `path`, returning whether anything changed. Only a new,
+  // retitled or re-identified note can change where other notes' links resolve; otherwise just
+  // the backlinks of the targets this note gained or lost are touched.
+  fn update(&mut self, path: &str, note: IndexedNote) -> bool {
+    let Some(old) = self.notes.get(path) else {
+      self.notes.insert(path.to_string(), note);
+      self.rebuild_backlinks();
+      return true;
+    };
+    if old.id != note.id || old.title != note.title {
+      self.notes.insert(path.to_string(), note);
+      self.rebuild_backlinks();
+      return true;
+    }
+    if old.links == note.links {
+      return false;
+    }
+
+    let old_targets = self.link_targets(&old.links);
+    let new_targets = self.link_targets(&note.links);
+    self.notes.insert(path.to_string(), note);
+    for target in old_targets.difference(&new_targets) {
+      if let Some(sources) = self.backlinks.get_mut(target) {
+        sources.retain(|source| source != path);
+        if sources.is_empty() {
+          self.backlinks.remove(target);
+        }
+      }
+    }
+    for target in new_targets.difference(&old_targets) {
+      let sources = self.backlinks.entry(target.clone()).or_default();
+      // Kept in path order, as a rebuild leaves them
+      if let Err(position) = sources.binary_search_by(|source| source.as_str().cmp(path)) {
+        sources.insert(position, path.to_string());
+      }
+    }
+    true
+  }
+
+  fn rebuild_backlinks(&mut self) {
+    self.index_names();
+    let mut backlinks: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (source, note) in &self.notes {
+      let targets: HashSet<&str> = note
+        .links
+        .iter()
+        .filter_map(|link| self.resolve(&link.target))
+        .collect();
+      for target in targets {
+        backlinks
+          .entry(target.to_string())
+          .or_default()
+          .push(source.clone());
+      }
+    }
+    self.backlinks = backlinks;
+  }
+
+  fn build() -> Result<Self, String> {
+    let mut index = LinkIndex::default();
+    for path in collect_note_paths()? {
+      let Some(path_str) = path.to_str() else {
+        continue;
+      };
+      match index_note(path_str) {
+        Ok(note) => {
+          index.notes.insert(path_str.to_string(), note);
+        }
+        Err(e) => eprintln!("Failed to index links of {}: {}", path_str, e),
+      }
+    }
+    index.rebuild_backlinks();
+    Ok(index)
+  }
+
+  fn save(&self) -> Result<(), String> {
+    let content =
+      serde_json::to_string(self).map_err(|e| format!("Failed to serialize link index: {}", e))?;
+    fs::write(index_path()?, content).map_err(|e| format!("Failed to save link index: {}", e))
+  }
+
+  // Drop notes whose files were deleted, returning whether any were
+  fn prune(&mut self) -> bool {
+    let before = self.notes.len();
+    self.notes.retain(|path, _| Path::new(path).exists());
+    self.notes.len() != before
+  }
+}
+
+// Run `f` against the link index, loading it from disk or building it on first use. The index
+// lock is held throughout, so every change to the index and links.json is made by one writer
+// at a time.
+fn with_loaded_index<T>(f: impl FnOnce(&mut LinkIndex) -> T) -> Result<T, String> {
+  let mut guard = LINK_INDEX.lock().unwrap();
+  if guard.is_none() {
+    let path = index_path()?;
+    let loaded: Option<LinkIndex> = match fs::read_to_string(&path) {
+      Ok(content) => serde_json::from_str(&content).ok(),
+      Err(_) => None,
+    };
+    // Notes are stored by absolute path, so after the data directory moved none of them exist
+    // and the index has to be built again where the notes are now
+    let loaded = loaded.filter(|index| {
+      index.notes.is_empty() || index.notes.keys().any(|path| Path::new(path).exists())
+    });
+    let index = match loaded {
+      Some(mut index) => {
+        index.index_names();
+        index
+      }
+      None => {
+        let index = LinkIndex::build()?;
+        index.save()?;
+        index
+      }
+    };
+    *guard = Some(index);
+  }
+  Ok(f(guard.as_mut().unwrap()))
+}
+
+// Like `with_loaded_index`, first dropping notes deleted since the last look
+fn with_index<T>(f: impl FnOnce(&mut LinkIndex) -> T) -> Result<T, String> {
+  with_loaded_index(|index| {
+    if index.prune() {
+      index.rebuild_backlinks();
+      index.save()?;
+    }
+    Ok(f(index))
+  })?
+}
+
+// Re-read the links of the note at `path` and update the index
+pub fn update_links(path: &str) -> Result<(), String> {
+  let note = index_note(path)?;
+  with_loaded_index(|index| {
+    if index.update(path, note) {
+      index.save()?;
+    }
+    Ok(())
+  })?
+}
+
+// Notes linking to the note at `path`, each with its links that resolve to it
+pub fn links_to(path: &str) -> Result<Vec<(String, Vec<WikiLink>)>, String> {
+  with_index(|index| {
+    index
+      .backlinks
+      .get(path)
+      .into_iter()
+      .flatten()
+      .filter_map(|source| {
+        let note = index.notes.get(source)?;
+        let links = note
+          .links
+          .iter()
+          .filter(|link| index.resolve(&link.target) == Some(path))
+          .cloned()
+          .collect();
+        Some((source.clone(), links))
+      })
+      .collect()
+  })
+}
+
+// Resolved links between notes as (source path, target path, number of links)
+pub fn link_edges() -> Result<Vec<(String, String, usize)>, String> {
+  with_index(|index| {
+    let mut edges: BTreeMap<(String, String), usize> = BTreeMap::new();
+    for (source, note) in &index.notes {
+      for link in &note.links {
+        if let Some(target) = index.resolve(&link.target) {
+          *edges
+            .entry((source.clone(), target.to_string()))
+            .or_insert(0) += 1;
+        }
+      }
+    }
+    edges
+      .into_iter()
+      .map(|((source, target), count)| (source, target, count))
+      .collect()
+  })
+}
+
+// Save hook keeping the index current as notes are edited
+pub fn update_note_links(path: &str) {
+  if let Err(e) = update_links(path) {
+    eprintln!("Failed to update links of {}: {}", path, e);
+  }
+}
+
+// Notes linking to the note `id`, one entry per link
+#[tauri::command]
+pub fn get_backlinks(id: String) -> Result<Vec<Backlink>, String> {
+  let sources = links_to(&note_path(&id)?)?;
+  with_index(|index| {
+    let mut backlinks = Vec::new();
+    for (source, links) in sources {
+      let Some(note) = index.notes.get(&source) else {
+        continue;
+      };
+      for link in links {
+        backlinks.push(Backlink {
+          id: note.id.clone(),
+          path: source.clone(),
+          title: note.title.clone(),
+          page_id: link.source_page,
+          target_page: link.page,
+          context: link.context,
+        });
+      }
+    }
+    backlinks
+  })
+}
+
+// Links in the note `id` with the notes they point to
+#[tauri::command]
+pub fn get_outgoing_links(id: String) -> Result<Vec<OutgoingLink>, String> {
+  let path = note_path(&id)?;
+  with_index(|index| {
+    let Some(note) = index.notes.get(&path) else {
+      return Vec::new();
+    };
+    note
+      .links
+      .iter()
+      .map(|link| {
+        let resolved = index.resolve(&link.target);
+        OutgoingLink {
+          link: link.clone(),
+          resolved_id: resolved
+            .and_then(|path| index.notes.get(path))
+            .map(|note| note.id.clone()),
+          resolved: resolved.map(str::to_string),
+        }
+      })
+      .collect()
+  })
+}
+
+// Links anywhere in the vault that don't match any note
+#[tauri::command]
+pub fn get_unresolved_links() -> Result<Vec<UnresolvedLink>, String> {
+  with_index(|index| {
+    let mut unresolved = Vec::new();
+    for (path, note) in &index.notes {
+      for link in &note.links {
+        if index.resolve(&link.target).is_none() {
+          unresolved.push(UnresolvedLink {
+            path: path.clone(),
+            title: note.title.clone(),
+            link: link.clone(),
+          });
+        }
+      }
+    }
+    unresolved
+  })
+}
+
+// Notes whose title matches `prefix` for completing a `[[` link. Titles starting with the prefix
+// come first, followed by titles containing it.
+#[tauri::command]
+pub fn complete_link(prefix: String, limit: Option<usize>) -> Result<Vec<LinkCompletion>, String> {
+  let prefix = prefix.trim().to_lowercase();
+  with_index(|index| {
+    let mut matches: Vec<(bool, LinkCompletion)> = index
+      .notes
+      .iter()
+      .filter_map(|(path, note)| {
+        let title = note.title.to_lowercase();
+        if !title.contains(&prefix) {
+          return None;
+        }
+        let completion = LinkCompletion {
+          id: note.id.clone(),
+          path: path.clone(),
+          title: note.title.clone(),
+        };
+        Some((!title.starts_with(&prefix), completion))
+      })
+      .collect();
+    matches.sort_by(|(a_later, a), (b_later, b)| {
+      a_later
+        .cmp(b_later)
+        .then_with(|| a.title.to_lowercase().cmp(&b.title.to_lowercase()))
+    });
+    matches
+      .into_iter()
+      .take(limit.unwrap_or(10))
+      .map(|(_, completion)| completion)
+      .collect()
+  })
+}
+
+// Rebuild the link index from every note in the vault, returning the number of links found
+#[tauri::command]
+pub fn rebuild_link_index() -> Result<usize, String> {
+  // Locked for the whole rebuild so updates made meanwhile aren't overwritten by it
+  let mut guard = LINK_INDEX.lock().unwrap();
+  let index = LinkIndex::build()?;
+  index.save()?;
+  let count = index.notes.values().map(|note| note.links.len()).sum();
+  *guard = Some(index);
+  Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fs::TEST_DATA_DIR;
+  use serde_json::json;
+  use uuid::Uuid;
+
+  fn write_note(dir: &Path, name: &str, title: &str, content: &str) {
+    let note = json!({
+      "id": Uuid::new_v4().to_string(),
+      "title": title,
+      "metadata": { "note_type": "notebook", "tags": [] },
+      "pages": [{ "id": "p1", "content": content }],
+    });
+    fs::write(dir.join("notes").join(name), note.to_string()).unwrap();
+  }
+
+  fn use_data_dir(dir: &Path) {
+    TEST_DATA_DIR.with(|data_dir| *data_dir.borrow_mut() = Some(dir.to_path_buf()));
+    *LINK_INDEX.lock().unwrap() = None;
+  }
+
+  #[test]
+  fn rebuilds_the_index_after_the_data_directory_moved() {
+    let root = std::env::temp_dir().join(format!("links-test-{}", Uuid::new_v4()));
+    let old_dir = root.join("old");
+    fs::create_dir_all(old_dir.join("notes")).unwrap();
+    write_note(&old_dir, "a.json", "Alpha", "<p>target</p>");
+    write_note(&old_dir, "b.json", "Beta", "<p>see [[Alpha]]</p>");
+    use_data_dir(&old_dir);
+    let old_target = old_dir.join("notes/a.json");
+    assert_eq!(links_to(old_target.to_str().unwrap()).unwrap().len(), 1);
+
+    // links.json moves along with the notes, still listing their old paths
+    let new_dir = root.join("new");
+    fs::rename(&old_dir, &new_dir).unwrap();
+    use_data_dir(&new_dir);
+    let target = new_dir.join("notes/a.json");
+    let sources = links_to(target.to_str().unwrap()).unwrap();
+    let source = new_dir.join("notes/b.json");
+    assert_eq!(sources.len(), 1);
+    assert_eq!(sources[0].0, source.to_str().unwrap());
+
+    let _ = fs::remove_dir_all(root);
+  }
+}
@@ -0,0 +1,200 @@
+use crate::config::VectorDistance;
+use crate::vector_store::{cosine, dot, ScoredChunk, VectorPoint, VectorStore};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct LocalCollection {
+  dimension: u64,
+  #[serde(default)]
+  distance: VectorDistance,
+  points: HashMap<String, VectorPoint>,
+}
+
+// Embedded vector store: each collection is a JSON file searched by brute force.
+// Collections are loaded lazily and written back in full after every change.
+pub struct LocalStore {
+  dir: PathBuf,
+  collections: Mutex<HashMap<String, LocalCollection>>,
+}
+
+// Score two vectors the way Qdrant does for `distance`: similarity for cosine and dot,
+// distance for euclid
+fn score(distance: VectorDistance, a: &[f32], b: &[f32]) -> f32 {
+  match distance {
+    VectorDistance::Dot => dot(a, b),
+    VectorDistance::Cosine => cosine(a, b),
+    VectorDistance::Euclid => a
+      .iter()
+      .zip(b)
+      .map(|(x, y)| (x - y) * (x - y))
+      .sum::<f32>()
+      .sqrt(),
+  }
+}
+
+impl LocalStore {
+  pub fn new(dir: PathBuf) -> Self {
+    LocalStore {
+      dir,
+      collections: Mutex::new(HashMap::new()),
+    }
+  }
+
+  fn collection_path(&self, collection: &str) -> PathBuf {
+    self.dir.join(format!("{}.json", collection))
+  }
+
+  // Run `f` against a collection, loading it from disk if it isn't cached yet.
+  // Returns None when the collection does not exist.
+  fn with_collection<T>(
+    &self,
+    collection: &str,
+    f: impl FnOnce(&mut LocalCollection) -> T,
+  ) -> Result<Option<T>, String> {
+    let mut collections = self.collections.lock().unwrap();
+    if !collections.contains_key(collection) {
+      let path = self.collection_path(collection);
+      if !path.exists() {
+        return Ok(None);
+      }
+      let content = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read vector file {}: {}", path.display(), e))?;
+      let loaded: LocalCollection = serde_json::from_str(&content)
+        .map_err(|e| format!("Failed to parse vector file {}: {}", path.display(), e))?;
+      collections.insert(collection.to_string(), loaded);
+    }
+    Ok(collections.get_mut(collection).map(f))
+  }
+
+  fn persist(&self, collection: &str, data: &LocalCollection) -> Result<(), String> {
+    fs::create_dir_all(&self.dir)
+      .map_err(|e| format!("Failed to create vector directory: {}", e))?;
+    let path = self.collection_path(collection);
+    let tmp_path = path.with_extension("json.tmp");
+    let content =
+      serde_json::to_string(data).map_err(|e| format!("Failed to serialize vectors: {}", e))?;
+    // Write to a temporary file first so a crash never leaves a truncated index
+    fs::write(&tmp_path, content)
+      .map_err(|e| format!("Failed to write vector file {}: {}", tmp_path.display(), e))?;
+    fs::rename(&tmp_path, &path)
+      .map_err(|e| format!("Failed to replace vector file {}: {}", path.display(), e))
+  }
+
+  fn update_collection(
+    &self,
+    collection: &str,
+    f: impl FnOnce(&mut LocalCollection),
+  ) -> Result<(), String> {
+    let persisted = self.with_collection(collection, |data| {
+      f(data);
+      self.persist(collection, data)
+    })?;
+    persisted.unwrap_or_else(|| Err(format!("Collection '{}' does not exist", collection)))
+  }
+}
+
+#[async_trait]
+impl VectorStore for LocalStore {
+  async fn collection_exists(&self, collection: &str) -> Result<bool, String> {
+    Ok(self.with_collection(collection, |_| ())?.is_some())
+  }
+
+  async fn collection_dimension(&self, collection: &str) -> Result<Option<u64>, String> {
+    self.with_collection(collection, |data| data.dimension)
+  }
+
+  async fn create_collection(
+    &self,
+    collection: &str,
+    dimension: u64,
+    distance: VectorDistance,
+  ) -> Result<(), String> {
+    let data = LocalCollection {
+      dimension,
+      distance,
+      points: HashMap::new(),
+    };
+    self.persist(collection, &data)?;
+    self
+      .collections
+      .lock()
+      .unwrap()
+      .insert(collection.to_string(), data);
+    Ok(())
+  }
+
+  async fn delete_collection(&self, collection: &str) -> Result<(), String> {
+    self.collections.lock().unwrap().remove(collection);
+    let path = self.collection_path(collection);
+    if path.exists() {
+      fs::remove_file(&path)
+        .map_err(|e| format!("Failed to remove vector file {}: {}", path.display(), e))?;
+    }
+    Ok(())
+  }
+
+  async fn upsert(&self, collection: &str, points: Vec<VectorPoint>) -> Result<(), String> {
+    self.update_collection(collection, |data| {
+      for point in points {
+        data.points.insert(point.id.clone(), point);
+      }
+    })
+  }
+
+  async fn search(
+    &self,
+    collection: &str,
+    vector: &[f32],
+    limit: usize,
+    exclude_note: Option<&str>,
+  ) -> Result<Vec<ScoredChunk>, String> {
+    let results = self.with_collection(collection, |data| {
+      let mut scored: Vec<ScoredChunk> = data
+        .points
+        .values()
+        .filter(|point| Some(point.payload.note_id.as_str()) != exclude_note)
+        .map(|point| ScoredChunk {
+          id: point.id.clone(),
+          score: score(data.distance, vector, &point.vector),
+          payload: point.payload.clone(),
+        })
+        .collect();
+      // Euclid scores are distances, so smaller is better
+      match data.distance {
+        VectorDistance::Euclid => scored.sort_by(|a, b| a.score.total_cmp(&b.score)),
+        _ => scored.sort_by(|a, b| b.score.total_cmp(&a.score)),
+      }
+      scored.truncate(limit);
+      scored
+    })?;
+    Ok(results.unwrap_or_default())
+  }
+
+  async fn get_by_note(&self, collection: &str, note_id: &str) -> Result<Vec<VectorPoint>, String> {
+    let points = self.with_collection(collection, |data| {
+      data
+        .points
+        .values()
+        .filter(|point| point.payload.note_id == note_id)
+        .cloned()
+        .collect()
+    })?;
+    Ok(points.unwrap_or_default())
+  }
+
+  async fn delete_by_note(&self, collection: &str, note_id: &str) -> Result<(), String> {
+    if !self.collection_exists(collection).await? {
+      return Ok(());
+    }
+    self.update_collection(collection, |data| {
+      data
+        .points
+        .retain(|_, point| point.payload.note_id != note_id);
+    })
+  }
+}
@@ -1,22 +1,7 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
-mod docker;
-mod fs;
 
 fn main() {
-  let image = "qdrant/qdrant".to_string();
-  let container_name = "jot_qdrant_instance".to_string();
-  let ports = vec![
-    ("6333".to_string(), "6333".to_string()),
-    ("6334".to_string(), "6334".to_string()),
-  ];
-  match docker::setup_and_start_container(image, container_name, ports) {
-    Ok(()) => {
-      println!("Container started successfully");
-    }
-    Err(e) => {
-      eprintln!("Failed to set up container: {}", e)
-    }
-  }
+  // Containers the app depends on are started and supervised in the background by the library
   elab_lib::run()
 }
@@ -0,0 +1,478 @@
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+// Stands in for newlines of the original text while the text is split into merge lines
+const NEWLINE: char = '\u{E000}';
+
+// Result of merging two versions of a note
+pub struct NoteMerge {
+  pub note: Value,
+  // Ids of the pages, or "title", changed on both sides in ways that couldn't be combined.
+  // The local version is kept for those.
+  pub conflicts: Vec<String>,
+}
+
+fn is_timestamp(value: &str) -> bool {
+  DateTime::parse_from_rfc3339(value).is_ok()
+}
+
+// Combine lists changed on both sides: items either side removed since `base` are dropped and
+// items either side added are kept, local ones first. Items are identified by `key`.
+fn union_by(
+  base: &[Value],
+  local: &[Value],
+  remote: &[Value],
+  key: fn(&Value) -> String,
+) -> Vec<Value> {
+  let base_keys: Vec<String> = base.iter().map(key).collect();
+  let local_keys: Vec<String> = local.iter().map(key).collect();
+  let remote_keys: Vec<String> = remote.iter().map(key).collect();
+  let mut items: Vec<Value> = local
+    .iter()
+    .zip(&local_keys)
+    .filter(|(_, item_key)| !base_keys.contains(item_key) || remote_keys.contains(item_key))
+    .map(|(item, _)| item.clone())
+    .collect();
+  for (item, item_key) in remote.iter().zip(&remote_keys) {
+    if !local_keys.contains(item_key) && !base_keys.contains(item_key) {
+      items.push(item.clone());
+    }
+  }
+  items
+}
+
+fn value_key(value: &Value) -> String {
+  value.to_string()
+}
+
+// Strokes are identified by their id; strokes drawn before strokes had ids by their content
+fn stroke_key(stroke: &Value) -> String {
+  match stroke["id"].as_str() {
+    Some(id) => id.to_string(),
+    None => format!("{:x}", Sha256::digest(stroke.to_string().as_bytes())),
+  }
+}
+
+// Three-way merge of any JSON value, `None` meaning the value is absent. Objects are merged key
+// by key and arrays as a union; for other values changed on both sides the later timestamp or
+// else the local value wins.
+fn merge_value(
+  base: Option<&Value>,
+  local: Option<&Value>,
+  remote: Option<&Value>,
+) -> Option<Value> {
+  if local == remote || base == remote {
+    return local.cloned();
+  }
+  if base == local {
+    return remote.cloned();
+  }
+  match (local, remote) {
+    (Some(Value::Object(local_fields)), Some(Value::Object(remote_fields))) => {
+      let mut fields = Map::new();
+      let keys = local_fields.keys().chain(
+        remote_fields
+          .keys()
+          .filter(|key| !local_fields.contains_key(*key)),
+      );
+      for key in keys {
+        let merged = merge_value(
+          base.and_then(|base| base.get(key)),
+          local_fields.get(key),
+          remote_fields.get(key),
+        );
+        if let Some(merged) = merged {
+          fields.insert(key.clone(), merged);
+        }
+      }
+      Some(Value::Object(fields))
+    }
+    (Some(Value::Array(local_items)), Some(Value::Array(remote_items))) => {
+      let base_items = base.and_then(Value::as_array).cloned().unwrap_or_default();
+      Some(Value::Array(union_by(
+        &base_items,
+        local_items,
+        remote_items,
+        value_key,
+      )))
+    }
+    (Some(Value::String(local_text)), Some(Value::String(remote_text)))
+      if is_timestamp(local_text) && is_timestamp(remote_text) =>
+    {
+      Some(Value::String(local_text.max(remote_text).clone()))
+    }
+    _ => local.cloned(),
+  }
+}
+
+// Split text into lines for a line-based merge: after every tag and every word, so edits to
+// different words of the same paragraph merge cleanly
+fn split_for_merge(text: &str) -> String {
+  let mut split = String::with_capacity(text.len() * 2);
+  for c in text.chars() {
+    if c == '\n' {
+      split.push(NEWLINE);
+      continue;
+    }
+    split.push(c);
+    if c == '>' || c == ' ' {
+      split.push('\n');
+    }
+  }
+  split
+}
+
+fn join_after_merge(text: &str) -> String {
+  text
+    .chars()
+    .filter(|c| *c != '\n')
+    .map(|c| if c == NEWLINE { '\n' } else { c })
+    .collect()
+}
+
+// Three-way merge of page text, None when both sides changed the same part
+pub fn merge_text(base: Option<&str>, local: &str, remote: &str) -> Option<String> {
+  if local == remote || base == Some(remote) {
+    return Some(local.to_string());
+  }
+  if base == Some(local) {
+    return Some(remote.to_string());
+  }
+  let merged = diffy::merge(
+    &split_for_merge(base?),
+    &split_for_merge(local),
+    &split_for_merge(remote),
+  )
+  .ok()?;
+  Some(join_after_merge(&merged))
+}
+
+// Strokes of a page field, stored either as a JSON string or as an array
+fn strokes(value: Option<&Value>) -> Vec<Value> {
+  match value {
+    Some(Value::String(strokes)) => serde_json::from_str(strokes).unwrap_or_default(),
+    Some(Value::Array(strokes)) => strokes.clone(),
+    _ => Vec::new(),
+  }
+}
+
+// Union of the strokes drawn on either side, keeping the field's storage format
+fn merge_strokes(base: Option<&Value>, local: &Value, remote: &Value) -> Value {
+  let merged = union_by(
+    &strokes(base),
+    &strokes(Some(local)),
+    &strokes(Some(remote)),
+    stroke_key,
+  );
+  match local {
+    Value::String(_) => Value::String(Value::Array(merged).to_string()),
+    _ => Value::Array(merged),
+  }
+}
+
+// Pages of both note types keep strokes in `lines` or `drawings` and typed HTML in `content`
+fn merge_page(
+  base: Option<&Value>,
+  local: &Value,
+  remote: &Value,
+  conflicts: &mut Vec<String>,
+) -> Value {
+  let mut page = merge_value(base, Some(local), Some(remote)).unwrap_or_else(|| local.clone());
+  if local == remote || base == Some(local) || base == Some(remote) {
+    return page;
+  }
+
+  for field in ["lines", "drawings"] {
+    let base_strokes = base.and_then(|base| base.get(field));
+    if let (Some(local_strokes), Some(remote_strokes)) = (local.get(field), remote.get(field)) {
+      if local_strokes != remote_strokes
+        && base_strokes != Some(local_strokes)
+        && base_strokes != Some(remote_strokes)
+      {
+        page[field] = merge_strokes(base_strokes, local_strokes, remote_strokes);
+      }
+    }
+  }
+  if let (Some(local_text), Some(remote_text)) =
+    (local["content"].as_str(), remote["content"].as_str())
+  {
+    let base_text = base.and_then(|base| base["content"].as_str());
+    match merge_text(base_text, local_text, remote_text) {
+      Some(text) => page["content"] = Value::String(text),
+      None => {
+        page["content"] = Value::String(local_text.to_string());
+        conflicts.push(page["id"].as_str().unwrap_or_default().to_string());
+      }
+    }
+  }
+  page
+}
+
+fn page_id(page: &Value) -> &str {
+  page["id"].as_str().unwrap_or_default()
+}
+
+fn find_page<'a>(pages: &'a [Value], id: &str) -> Option<&'a Value> {
+  pages.iter().find(|page| page_id(page) == id)
+}
+
+// Merge page lists by page id. Pages added on the remote are appended; a page deleted on one
+// side is dropped unless the other side edited it, which is a conflict.
+fn merge_pages(
+  base: &[Value],
+  local: &[Value],
+  remote: &[Value],
+  conflicts: &mut Vec<String>,
+) -> Vec<Value> {
+  let mut pages = Vec::new();
+  for page in local {
+    let id = page_id(page);
+    let base_page = find_page(base, id);
+    match (find_page(remote, id), base_page) {
+      (Some(remote_page), _) => pages.push(merge_page(base_page, page, remote_page, conflicts)),
+      // Deleted on the remote and untouched here
+      (None, Some(base_page)) if base_page == page => {}
+      (None, Some(_)) => {
+        conflicts.push(id.to_string());
+        pages.push(page.clone());
+      }
+      // Added here
+      (None, None) => pages.push(page.clone()),
+    }
+  }
+  for page in remote {
+    let id = page_id(page);
+    if find_page(local, id).is_some() {
+      continue;
+    }
+    match find_page(base, id) {
+      None => pages.push(page.clone()),
+      // Deleted here and untouched on the remote
+      Some(base_page) if base_page == page => {}
+      Some(_) => {
+        conflicts.push(id.to_string());
+        pages.push(page.clone());
+      }
+    }
+  }
+  pages
+}
+
+// Three-way merge of a note edited both here and on a remote since `base`, the version both
+// last agreed on. Without a base only changes that can't clash, like added pages and strokes,
+// are combined.
+pub fn merge_notes(base: Option<&Value>, local: &Value, remote: &Value) -> NoteMerge {
+  let mut conflicts = Vec::new();
+  let mut note = merge_value(base, Some(local), Some(remote)).unwrap_or_else(|| local.clone());
+
+  let base_title = base.map(|base| &base["title"]);
+  if local["title"] != remote["title"]
+    && base_title != Some(&local["title"])
+    && base_title != Some(&remote["title"])
+  {
+    note["title"] = local["title"].clone();
+    conflicts.push("title".to_string());
+  }
+
+  let pages = |note: Option<&Value>| {
+    note
+      .and_then(|note| note["pages"].as_array())
+      .cloned()
+      .unwrap_or_default()
+  };
+  note["pages"] = Value::Array(merge_pages(
+    &pages(base),
+    &pages(Some(local)),
+    &pages(Some(remote)),
+    &mut conflicts,
+  ));
+  NoteMerge { note, conflicts }
+}
+
+// Title for the copy of a note whose changes couldn't be merged
+pub fn conflict_title(title: &str, at: DateTime<Utc>) -> String {
+  format!(
+    "{} (conflicted copy {})",
+    title,
+    at.format("%Y-%m-%d %H:%M")
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn page(id: &str, content: &str) -> Value {
+    json!({ "id": id, "content": content })
+  }
+
+  fn ids(pages: &[Value]) -> Vec<&str> {
+    pages.iter().map(page_id).collect()
+  }
+
+  #[test]
+  fn merge_text_combines_edits_to_different_words() {
+    let merged = merge_text(
+      Some("<p>one two three</p>"),
+      "<p>ONE two three</p>",
+      "<p>one two THREE</p>",
+    );
+    assert_eq!(merged.as_deref(), Some("<p>ONE two THREE</p>"));
+  }
+
+  #[test]
+  fn merge_text_keeps_newlines() {
+    let merged = merge_text(
+      Some("<p>one</p>\n<p>two</p>"),
+      "<p>ONE</p>\n<p>two</p>",
+      "<p>one</p>\n<p>TWO</p>",
+    );
+    assert_eq!(merged.as_deref(), Some("<p>ONE</p>\n<p>TWO</p>"));
+  }
+
+  #[test]
+  fn merge_text_takes_the_only_changed_side() {
+    assert_eq!(
+      merge_text(Some("<p>a</p>"), "<p>a</p>", "<p>b</p>").as_deref(),
+      Some("<p>b</p>")
+    );
+    assert_eq!(
+      merge_text(Some("<p>a</p>"), "<p>b</p>", "<p>a</p>").as_deref(),
+      Some("<p>b</p>")
+    );
+  }
+
+  #[test]
+  fn merge_text_fails_on_clashing_edits() {
+    assert_eq!(merge_text(Some("<p>a</p>"), "<p>b</p>", "<p>c</p>"), None);
+    // Without a base there is nothing to tell the edits apart
+    assert_eq!(merge_text(None, "<p>b</p>", "<p>c</p>"), None);
+  }
+
+  #[test]
+  fn merge_pages_keeps_pages_added_on_either_side() {
+    let base = [page("p1", "one")];
+    let local = [page("p1", "one"), page("local", "added here")];
+    let remote = [page("p1", "one"), page("remote", "added there")];
+    let mut conflicts = Vec::new();
+    let pages = merge_pages(&base, &local, &remote, &mut conflicts);
+    assert_eq!(ids(&pages), vec!["p1", "local", "remote"]);
+    assert!(conflicts.is_empty());
+  }
+
+  #[test]
+  fn merge_pages_drops_pages_deleted_on_either_side() {
+    let base = [page("p1", "one"), page("p2", "two"), page("p3", "three")];
+    let local = [page("p1", "one"), page("p3", "three")];
+    let remote = [page("p1", "one"), page("p2", "two")];
+    let mut conflicts = Vec::new();
+    let pages = merge_pages(&base, &local, &remote, &mut conflicts);
+    assert_eq!(ids(&pages), vec!["p1"]);
+    assert!(conflicts.is_empty());
+  }
+
+  #[test]
+  fn merge_pages_keeps_deleted_pages_edited_on_the_other_side() {
+    let base = [page("p1", "one"), page("p2", "two")];
+    let local = [page("p1", "one edited")];
+    let remote = [page("p2", "two edited")];
+    let mut conflicts = Vec::new();
+    let pages = merge_pages(&base, &local, &remote, &mut conflicts);
+    assert_eq!(
+      pages,
+      vec![page("p1", "one edited"), page("p2", "two edited")]
+    );
+    assert_eq!(conflicts, vec!["p1", "p2"]);
+  }
+
+  #[test]
+  fn merge_pages_takes_edits_from_either_side() {
+    let base = [page("p1", "<p>one</p>"), page("p2", "<p>two</p>")];
+    let local = [page("p1", "<p>one here</p>"), page("p2", "<p>two</p>")];
+    let remote = [page("p1", "<p>one</p>"), page("p2", "<p>two there</p>")];
+    let mut conflicts = Vec::new();
+    let pages = merge_pages(&base, &local, &remote, &mut conflicts);
+    assert_eq!(
+      pages,
+      vec![
+        page("p1", "<p>one here</p>"),
+        page("p2", "<p>two there</p>")
+      ]
+    );
+    assert!(conflicts.is_empty());
+  }
+
+  #[test]
+  fn merge_pages_keeps_local_text_on_conflict() {
+    let base = [page("p1", "<p>one</p>")];
+    let local = [page("p1", "<p>here</p>")];
+    let remote = [page("p1", "<p>there</p>")];
+    let mut conflicts = Vec::new();
+    let pages = merge_pages(&base, &local, &remote, &mut conflicts);
+    assert_eq!(pages, vec![page("p1", "<p>here</p>")]);
+    assert_eq!(conflicts, vec!["p1"]);
+  }
+
+  #[test]
+  fn merge_strokes_keeps_strokes_drawn_on_either_side() {
+    let (a, b, c) = (
+      json!({ "id": "a" }),
+      json!({ "id": "b" }),
+      json!({ "id": "c" }),
+    );
+    let merged = merge_strokes(
+      Some(&json!([a, b])),
+      &json!([a, b, c]),
+      &json!([b, { "id": "d" }]),
+    );
+    // "a" was erased on the remote
+    assert_eq!(merged, json!([b, c, { "id": "d" }]));
+  }
+
+  #[test]
+  fn merge_strokes_keeps_strokes_stored_as_a_string() {
+    let base = Value::String(json!([{ "id": "a" }]).to_string());
+    let local = Value::String(json!([{ "id": "a" }, { "id": "b" }]).to_string());
+    let remote = Value::String(json!([{ "id": "a" }, { "id": "c" }]).to_string());
+    let merged = merge_strokes(Some(&base), &local, &remote);
+    let expected = json!([{ "id": "a" }, { "id": "b" }, { "id": "c" }]);
+    assert_eq!(merged, Value::String(expected.to_string()));
+  }
+
+  #[test]
+  fn merge_page_merges_strokes_and_text_of_free_note_pages() {
+    let lines = |ids: &[&str]| {
+      let strokes: Vec<Value> = ids.iter().map(|id| json!({ "id": id })).collect();
+      Value::String(Value::Array(strokes).to_string())
+    };
+    let base = json!({ "id": "p1", "content": "<p>one two three</p>", "lines": lines(&["a"]) });
+    let local =
+      json!({ "id": "p1", "content": "<p>ONE two three</p>", "lines": lines(&["a", "b"]) });
+    let remote =
+      json!({ "id": "p1", "content": "<p>one two THREE</p>", "lines": lines(&["a", "c"]) });
+    let mut conflicts = Vec::new();
+    let merged = merge_page(Some(&base), &local, &remote, &mut conflicts);
+    assert_eq!(merged["content"], "<p>ONE two THREE</p>");
+    assert_eq!(merged["lines"], lines(&["a", "b", "c"]));
+    assert!(conflicts.is_empty());
+  }
+
+  #[test]
+  fn merge_page_merges_strokes_and_text_of_notebook_pages() {
+    let base =
+      json!({ "id": "p1", "content": "<p>one two three</p>", "drawings": [{ "id": "a" }] });
+    let local = json!({
+      "id": "p1",
+      "content": "<p>ONE two three</p>",
+      "drawings": [{ "id": "a" }, { "id": "b" }],
+    });
+    let remote = json!({ "id": "p1", "content": "<p>one two THREE</p>", "drawings": [] });
+    let mut conflicts = Vec::new();
+    let merged = merge_page(Some(&base), &local, &remote, &mut conflicts);
+    assert_eq!(merged["content"], "<p>ONE two THREE</p>");
+    assert_eq!(merged["drawings"], json!([{ "id": "b" }]));
+    assert!(conflicts.is_empty());
+  }
+}
@@ -1,25 +1,817 @@
+use crate::config::SyncConfig;
+use crate::sync::{note_hash, RemoteChanges, RemoteEntry, SyncRemote};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use mongodb::{
-  options::{ClientOptions, ServerApi, ServerApiVersion},
-  Client,
+  bson::{doc, from_bson, to_bson, Bson, Document},
+  error::{Error, ErrorKind, WriteFailure},
+  options::{ClientOptions, ReturnDocument, ServerApi, ServerApiVersion},
+  Client, Collection, Database, IndexModel,
 };
-use once_cell::sync::OnceCell;
-use std::sync::Arc;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+use std::time::Duration;
 
-static MONGO_CLIENT: OnceCell<Arc<Client>> = OnceCell::new();
-async fn get_client() -> mongodb::error::Result<&'static Arc<Client>> {
-  if let Some(client) = MONGO_CLIENT.get() {
-    return Ok(client);
+// Error code of a write rejected by a unique index
+const DUPLICATE_KEY: i32 = 11000;
+// Prefix of the references left in page HTML in place of embedded files
+const ATTACHMENT_PREFIX: &str = "attachment:";
+// How long a revision may stay pending before it is taken for an abandoned push
+const PENDING_TIMEOUT: Duration = Duration::from_secs(600);
+
+// Client and the URI it connects to
+static MONGO_CLIENT: RwLock<Option<(String, Client)>> = RwLock::new(None);
+
+// Client for the MongoDB server at `uri`
+async fn get_client(uri: &str) -> Result<Client, String> {
+  if let Some((cached_uri, client)) = MONGO_CLIENT.read().unwrap().as_ref() {
+    if cached_uri == uri {
+      return Ok(client.clone());
+    }
   }
 
-  let uri = "uri here";
-  let mut client_options = ClientOptions::parse(uri).await?;
+  let mut client_options = ClientOptions::parse(uri)
+    .await
+    .map_err(|e| format!("Invalid MongoDB URI: {}", e))?;
   let server_api = ServerApi::builder().version(ServerApiVersion::V1).build();
   client_options.server_api = Some(server_api);
-  let client = Client::with_options(client_options)?;
+  let client = Client::with_options(client_options)
+    .map_err(|e| format!("Failed to connect to MongoDB: {}", e))?;
+  *MONGO_CLIENT.write().unwrap() = Some((uri.to_string(), client.clone()));
+  Ok(client)
+}
+
+// Drop the cached client so the next access connects again
+pub fn reset_mongo_client() {
+  *MONGO_CLIENT.write().unwrap() = None;
+}
+
+fn is_duplicate_key(error: &Error) -> bool {
+  matches!(
+    *error.kind,
+    ErrorKind::Write(WriteFailure::WriteError(ref error)) if error.code == DUPLICATE_KEY
+  )
+}
+
+// A note without its pages. `note` and `pages` are left out when only listing changes.
+#[derive(Serialize, Deserialize, Debug)]
+struct NoteDocument {
+  #[serde(rename = "_id")]
+  id: String,
+  path: String,
+  hash: String,
+  revision: i64,
+  deleted: bool,
+  modified_at: DateTime<Utc>,
+  #[serde(default)]
+  note: Option<Bson>,
+  // Ids of the note's page documents, in page order
+  #[serde(default)]
+  pages: Vec<String>,
+}
+
+// One page of a note, keyed by note id and content hash so a stored page never changes and
+// a note switches to new pages in a single update of its note document
+#[derive(Serialize, Deserialize, Debug)]
+struct PageDocument {
+  #[serde(rename = "_id")]
+  id: String,
+  note_id: String,
+  // Revision of the push that uploaded the page
+  revision: i64,
+  // The page with embedded files replaced by attachment references
+  page: Bson,
+}
+
+// A file embedded in a page as a data URL, keyed by its hash and shared by every page using it
+#[derive(Serialize, Deserialize, Debug)]
+struct AttachmentDocument {
+  #[serde(rename = "_id")]
+  id: String,
+  data: String,
+  revision: i64,
+}
+
+impl From<NoteDocument> for RemoteEntry {
+  fn from(document: NoteDocument) -> Self {
+    RemoteEntry {
+      id: document.id,
+      path: document.path,
+      hash: document.hash,
+      revision: document.revision as u64,
+      deleted: document.deleted,
+      modified_at: document.modified_at,
+    }
+  }
+}
+
+// Move data URLs in `src` attributes out of `html`, leaving `attachment:<hash>` references
+fn extract_attachments(html: &str, attachments: &mut BTreeMap<String, String>) -> String {
+  let mut result = String::with_capacity(html.len());
+  let mut rest = html;
+  while let Some(start) = rest.find("src=") {
+    let value_start = start + 5;
+    let quote = rest[start + 4..].chars().next();
+    let end = match quote {
+      Some(quote @ ('"' | '\'')) if rest[value_start..].starts_with("data:") => {
+        rest[value_start..].find(quote).map(|end| value_start + end)
+      }
+      _ => None,
+    };
+    let Some(end) = end else {
+      result.push_str(&rest[..start + 4]);
+      rest = &rest[start + 4..];
+      continue;
+    };
+    let data = &rest[value_start..end];
+    let hash = format!("{:x}", Sha256::digest(data.as_bytes()));
+    result.push_str(&rest[..value_start]);
+    result.push_str(ATTACHMENT_PREFIX);
+    result.push_str(&hash);
+    attachments.insert(hash, data.to_string());
+    rest = &rest[end..];
+  }
+  result.push_str(rest);
+  result
+}
+
+// Hashes of the attachments referenced in `html`
+fn attachment_refs(html: &str) -> Vec<String> {
+  html
+    .match_indices(ATTACHMENT_PREFIX)
+    .filter_map(|(index, _)| {
+      let start = index + ATTACHMENT_PREFIX.len();
+      html.get(start..start + 64)
+    })
+    .filter(|hash| hash.chars().all(|c| c.is_ascii_hexdigit()))
+    .map(str::to_string)
+    .collect()
+}
+
+fn inline_attachments(html: &str, attachments: &HashMap<String, String>) -> String {
+  let mut html = html.to_string();
+  for (hash, data) in attachments {
+    html = html.replace(&format!("{}{}", ATTACHMENT_PREFIX, hash), data);
+  }
+  html
+}
+
+// Sync remote in a MongoDB database with three collections: `notes` holds each note's fields
+// and revision, `pages` its pages and `attachments` the files embedded in them. Splitting them
+// keeps documents well under MongoDB's size limit and only uploads pages and files that are new.
+// Revisions come from a counter document, so changes since a sync token are a range query. The
+// counter also lists the revisions handed out to pushes that haven't finished writing, so a
+// reader never moves its sync token past a change that is still landing.
+pub struct MongoRemote {
+  uri: String,
+  database: String,
+}
+
+impl MongoRemote {
+  pub fn new(config: &SyncConfig) -> Self {
+    MongoRemote {
+      uri: config.mongo_uri.clone(),
+      database: config.mongo_database.clone(),
+    }
+  }
+
+  async fn database(&self) -> Result<Database, String> {
+    Ok(get_client(&self.uri).await?.database(&self.database))
+  }
+
+  async fn collection<T: Send + Sync>(&self, name: &str) -> Result<Collection<T>, String> {
+    Ok(self.database().await?.collection(name))
+  }
+
+  async fn ensure_indexes(&self) -> Result<(), String> {
+    let index = |keys: Document| IndexModel::builder().keys(keys).build();
+    self
+      .collection::<Document>("notes")
+      .await?
+      .create_index(index(doc! { "revision": 1 }))
+      .await
+      .map_err(|e| format!("Failed to create index: {}", e))?;
+    self
+      .collection::<Document>("pages")
+      .await?
+      .create_index(index(doc! { "note_id": 1 }))
+      .await
+      .map_err(|e| format!("Failed to create index: {}", e))?;
+    Ok(())
+  }
+
+  // Allocate a revision and mark it pending in the same update, dropping marks left by pushes
+  // that never finished
+  async fn next_revision(&self) -> Result<i64, String> {
+    let cutoff = doc! { "$subtract": ["$$NOW", PENDING_TIMEOUT.as_millis() as i64] };
+    let update = vec![
+      doc! { "$set": { "value": { "$add": [{ "$ifNull": ["$value", 0_i64] }, 1_i64] } } },
+      doc! { "$set": { "pending": { "$concatArrays": [
+        {
+          "$filter": {
+            "input": { "$ifNull": ["$pending", []] },
+            "cond": { "$gte": ["$$this.at", cutoff] },
+          }
+        },
+        [{ "revision": "$value", "at": "$$NOW" }],
+      ] } } },
+    ];
+    let counter = self
+      .collection::<Document>("counters")
+      .await?
+      .find_one_and_update(doc! { "_id": "revision" }, update)
+      .upsert(true)
+      .return_document(ReturnDocument::After)
+      .await
+      .map_err(|e| format!("Failed to allocate a revision: {}", e))?
+      .ok_or_else(|| "Revision counter is missing".to_string())?;
+    counter
+      .get_i64("value")
+      .map_err(|e| format!("Invalid revision counter: {}", e))
+  }
+
+  // Mark a revision from `next_revision` as written, or as abandoned after a failed push
+  async fn release_revision(&self, revision: i64) -> Result<(), String> {
+    self
+      .collection::<Document>("counters")
+      .await?
+      .update_one(
+        doc! { "_id": "revision" },
+        doc! { "$pull": { "pending": { "revision": revision } } },
+      )
+      .await
+      .map_err(|e| format!("Failed to release revision {}: {}", revision, e))?;
+    Ok(())
+  }
+
+  // Highest revision handed out and the oldest one whose push is still writing
+  async fn allocated_revisions(&self) -> Result<(i64, Option<i64>), String> {
+    let counter = self
+      .collection::<Document>("counters")
+      .await?
+      .find_one(doc! { "_id": "revision" })
+      .await
+      .map_err(|e| format!("Failed to read the revision counter: {}", e))?;
+    let Some(counter) = counter else {
+      return Ok((0, None));
+    };
+    let cutoff = Utc::now().timestamp_millis() - PENDING_TIMEOUT.as_millis() as i64;
+    let oldest_pending = counter
+      .get_array("pending")
+      .map(|pending| {
+        pending
+          .iter()
+          .filter_map(Bson::as_document)
+          .filter(|mark| {
+            mark
+              .get_datetime("at")
+              .is_ok_and(|at| at.timestamp_millis() >= cutoff)
+          })
+          .filter_map(|mark| mark.get_i64("revision").ok())
+          .min()
+      })
+      .unwrap_or(None);
+    Ok((counter.get_i64("value").unwrap_or(0), oldest_pending))
+  }
+
+  // Documents of `collection` with the given ids
+  async fn find_by_ids<T: DeserializeOwned + Send + Sync>(
+    &self,
+    collection: &str,
+    ids: &[String],
+  ) -> Result<Vec<T>, String> {
+    let mut cursor = self
+      .collection::<T>(collection)
+      .await?
+      .find(doc! { "_id": { "$in": ids } })
+      .await
+      .map_err(|e| format!("Failed to query {}: {}", collection, e))?;
+    let mut documents = Vec::new();
+    while cursor
+      .advance()
+      .await
+      .map_err(|e| format!("Failed to read {}: {}", collection, e))?
+    {
+      documents.push(
+        cursor
+          .deserialize_current()
+          .map_err(|e| format!("Invalid document in {}: {}", collection, e))?,
+      );
+    }
+    Ok(documents)
+  }
+
+  // Insert the documents whose ids aren't stored yet. Content-addressed documents never change,
+  // so one inserted by another device in the meantime is just as good.
+  async fn insert_missing<T: Serialize + DeserializeOwned + Send + Sync>(
+    &self,
+    collection: &str,
+    documents: Vec<(String, T)>,
+  ) -> Result<(), String> {
+    if documents.is_empty() {
+      return Ok(());
+    }
+    let ids: Vec<String> = documents.iter().map(|(id, _)| id.clone()).collect();
+    let existing: Vec<String> = self
+      .find_by_ids::<Document>(collection, &ids)
+      .await?
+      .into_iter()
+      .filter_map(|document| document.get_str("_id").ok().map(str::to_string))
+      .collect();
+    let target = self.collection::<T>(collection).await?;
+    for (id, document) in documents {
+      if existing.contains(&id) {
+        continue;
+      }
+      if let Err(e) = target.insert_one(document).await {
+        if !is_duplicate_key(&e) {
+          return Err(format!("Failed to upload to {}: {}", collection, e));
+        }
+      }
+    }
+    Ok(())
+  }
+
+  // Upload the pages and attachments of `note`, returning the note without pages and the ids
+  // of its page documents
+  async fn upload_pages(
+    &self,
+    id: &str,
+    note: &Value,
+    revision: i64,
+  ) -> Result<(Value, Vec<String>), String> {
+    let mut note = note.clone();
+    let pages = note
+      .as_object_mut()
+      .and_then(|fields| fields.remove("pages"))
+      .and_then(|pages| match pages {
+        Value::Array(pages) => Some(pages),
+        _ => None,
+      })
+      .unwrap_or_default();
+
+    let mut attachments = BTreeMap::new();
+    let mut page_ids = Vec::new();
+    let mut page_documents = Vec::new();
+    for mut page in pages {
+      if let Some(content) = page["content"].as_str() {
+        page["content"] = json!(extract_attachments(content, &mut attachments));
+      }
+      let page_id = format!("{}:{}", id, note_hash(&page));
+      page_ids.push(page_id.clone());
+      page_documents.push((
+        page_id.clone(),
+        PageDocument {
+          id: page_id,
+          note_id: id.to_string(),
+          revision,
+          page: to_bson(&page).map_err(|e| format!("Failed to convert page: {}", e))?,
+        },
+      ));
+    }
+
+    let attachments = attachments
+      .into_iter()
+      .map(|(hash, data)| {
+        (
+          hash.clone(),
+          AttachmentDocument {
+            id: hash,
+            data,
+            revision,
+          },
+        )
+      })
+      .collect();
+    // Attachments first, so a stored page never refers to a missing file
+    self.insert_missing("attachments", attachments).await?;
+    self.insert_missing("pages", page_documents).await?;
+    Ok((note, page_ids))
+  }
+
+  // Upload the note for a push holding `revision`, unless the remote copy changed after `base`
+  async fn store_note(
+    &self,
+    entry: &RemoteEntry,
+    note: Option<&Value>,
+    base: u64,
+    revision: i64,
+  ) -> Result<Option<u64>, String> {
+    let notes = self.collection::<Document>("notes").await?;
+    let (fields, page_ids) = match note {
+      Some(note) => {
+        let (fields, page_ids) = self.upload_pages(&entry.id, note, revision).await?;
+        let fields = to_bson(&fields).map_err(|e| format!("Failed to convert note: {}", e))?;
+        (fields, page_ids)
+      }
+      None => (Bson::Null, Vec::new()),
+    };
+    let document = doc! {
+      "path": &entry.path,
+      "hash": &entry.hash,
+      "revision": revision,
+      "deleted": entry.deleted,
+      "modified_at": entry.modified_at.to_rfc3339(),
+      "note": fields,
+      "pages": &page_ids,
+    };
+
+    // Only replace the copy this device last synced, so a concurrent change is never overwritten
+    let updated = notes
+      .update_one(
+        doc! { "_id": &entry.id, "revision": { "$lte": base as i64 } },
+        doc! { "$set": document.clone() },
+      )
+      .await
+      .map_err(|e| format!("Failed to push note {}: {}", entry.id, e))?;
+    if updated.matched_count == 0 {
+      let mut document = document;
+      document.insert("_id", &entry.id);
+      if let Err(e) = notes.insert_one(document).await {
+        // The note was pushed by another device since the check above
+        if is_duplicate_key(&e) {
+          return Ok(None);
+        }
+        return Err(format!("Failed to push note {}: {}", entry.id, e));
+      }
+    }
+
+    // Pages the note no longer uses, including ones left by pushes that lost a race.
+    // Attachments may be shared between notes and are left for `prune`.
+    self
+      .collection::<Document>("pages")
+      .await?
+      .delete_many(doc! { "note_id": &entry.id, "_id": { "$nin": &page_ids } })
+      .await
+      .map_err(|e| format!("Failed to remove old pages of note {}: {}", entry.id, e))?;
+    Ok(Some(revision as u64))
+  }
+}
+
+#[async_trait]
+impl SyncRemote for MongoRemote {
+  fn describe(&self) -> String {
+    // Leave credentials and options out of the description stored in the sync state
+    let address = self
+      .uri
+      .split_once("://")
+      .map_or(self.uri.as_str(), |(_, rest)| rest);
+    let address = address.rsplit_once('@').map_or(address, |(_, hosts)| hosts);
+    let hosts = address.split(['/', '?']).next().unwrap_or(address);
+    format!("mongo:{}/{}", hosts, self.database)
+  }
+
+  async fn changes(&self, since: u64) -> Result<RemoteChanges, String> {
+    self.ensure_indexes().await?;
+    // Read before the notes: any revision allocated later is above `allocated`
+    let (allocated, oldest_pending) = self.allocated_revisions().await?;
+    let mut cursor = self
+      .collection::<NoteDocument>("notes")
+      .await?
+      .find(doc! { "revision": { "$gt": since as i64 } })
+      .projection(doc! { "note": 0, "pages": 0 })
+      .await
+      .map_err(|e| format!("Failed to query changes: {}", e))?;
+    let mut entries = Vec::new();
+    while cursor
+      .advance()
+      .await
+      .map_err(|e| format!("Failed to read changes: {}", e))?
+    {
+      let document = cursor
+        .deserialize_current()
+        .map_err(|e| format!("Invalid note document: {}", e))?;
+      entries.push(RemoteEntry::from(document));
+    }
+    // Resume after the newest change seen, but never past a revision that was allocated without
+    // its note written yet, or one allocated after the counter was read. Changes above the
+    // cursor come again next time and are skipped as already synced.
+    let mut cursor = entries
+      .iter()
+      .map(|entry| entry.revision as i64)
+      .max()
+      .unwrap_or(0)
+      .min(allocated);
+    if let Some(pending) = oldest_pending {
+      cursor = cursor.min(pending - 1);
+    }
+    Ok(RemoteChanges {
+      entries,
+      cursor: (cursor.max(0) as u64).max(since),
+    })
+  }
+
+  async fn fetch(&self, id: &str) -> Result<Option<Value>, String> {
+    let document = self
+      .collection::<NoteDocument>("notes")
+      .await?
+      .find_one(doc! { "_id": id, "deleted": false })
+      .await
+      .map_err(|e| format!("Failed to fetch note {}: {}", id, e))?;
+    let Some(document) = document else {
+      return Ok(None);
+    };
+    let Some(fields) = document.note else {
+      return Ok(None);
+    };
+    let mut note: Value =
+      from_bson(fields).map_err(|e| format!("Invalid content of note {}: {}", id, e))?;
+
+    let mut stored: HashMap<String, Value> = HashMap::new();
+    for page in self
+      .find_by_ids::<PageDocument>("pages", &document.pages)
+      .await?
+    {
+      let value = from_bson(page.page).map_err(|e| format!("Invalid page {}: {}", page.id, e))?;
+      stored.insert(page.id, value);
+    }
+    let mut pages = Vec::new();
+    for page_id in &document.pages {
+      let page = stored
+        .get(page_id)
+        .cloned()
+        .ok_or_else(|| format!("Page {} of note {} is missing", page_id, id))?;
+      pages.push(page);
+    }
+
+    let refs: Vec<String> = pages
+      .iter()
+      .filter_map(|page| page["content"].as_str())
+      .flat_map(attachment_refs)
+      .collect();
+    if !refs.is_empty() {
+      let attachments: HashMap<String, String> = self
+        .find_by_ids::<AttachmentDocument>("attachments", &refs)
+        .await?
+        .into_iter()
+        .map(|attachment| (attachment.id, attachment.data))
+        .collect();
+      for page in pages.iter_mut() {
+        if let Some(content) = page["content"].as_str() {
+          page["content"] = json!(inline_attachments(content, &attachments));
+        }
+      }
+    }
+
+    note["pages"] = Value::Array(pages);
+    Ok(Some(note))
+  }
+
+  async fn push(
+    &self,
+    entry: &RemoteEntry,
+    note: Option<&Value>,
+    base: u64,
+  ) -> Result<Option<u64>, String> {
+    let notes = self.collection::<Document>("notes").await?;
+    // Check before uploading anything for a note that would be rejected anyway
+    let existing = notes
+      .find_one(doc! { "_id": &entry.id })
+      .projection(doc! { "revision": 1 })
+      .await
+      .map_err(|e| format!("Failed to look up note {}: {}", entry.id, e))?;
+    if let Some(existing) = existing {
+      if existing.get_i64("revision").unwrap_or(0) > base as i64 {
+        return Ok(None);
+      }
+    }
+
+    let revision = self.next_revision().await?;
+    let result = self.store_note(entry, note, base, revision).await;
+    // Readers hold their sync token below a pending revision, so one left behind only delays
+    // them until it times out
+    if let Err(e) = self.release_revision(revision).await {
+      eprintln!("{}", e);
+    }
+    result
+  }
+
+  async fn load_meta(&self, name: &str) -> Result<Option<(Value, u64)>, String> {
+    let document = self
+      .collection::<Document>("meta")
+      .await?
+      .find_one(doc! { "_id": name })
+      .await
+      .map_err(|e| format!("Failed to load {}: {}", name, e))?;
+    let Some(mut document) = document else {
+      return Ok(None);
+    };
+    let version = document.get_i64("version").unwrap_or(0) as u64;
+    let value = document.remove("value").unwrap_or(Bson::Null);
+    from_bson(value)
+      .map(|value| Some((value, version)))
+      .map_err(|e| format!("Invalid {}: {}", name, e))
+  }
+
+  async fn store_meta(
+    &self,
+    name: &str,
+    value: &Value,
+    expected: Option<u64>,
+  ) -> Result<bool, String> {
+    let value = to_bson(value).map_err(|e| format!("Failed to convert {}: {}", name, e))?;
+    let meta = self.collection::<Document>("meta").await?;
+    let Some(expected) = expected else {
+      // A duplicate means another device stored the first version
+      return match meta
+        .insert_one(doc! { "_id": name, "value": value, "version": 1_i64 })
+        .await
+      {
+        Ok(_) => Ok(true),
+        Err(e) if is_duplicate_key(&e) => Ok(false),
+        Err(e) => Err(format!("Failed to store {}: {}", name, e)),
+      };
+    };
+    let updated = meta
+      .update_one(
+        doc! { "_id": name, "version": expected as i64 },
+        doc! { "$set": { "value": value, "version": expected as i64 + 1 } },
+      )
+      .await
+      .map_err(|e| format!("Failed to store {}: {}", name, e))?;
+    Ok(updated.matched_count == 1)
+  }
+
+  async fn prune(&self) -> Result<(), String> {
+    let mut cursor = self
+      .collection::<PageDocument>("pages")
+      .await?
+      .find(doc! {})
+      .await
+      .map_err(|e| format!("Failed to query pages: {}", e))?;
+    let mut refs = Vec::new();
+    while cursor
+      .advance()
+      .await
+      .map_err(|e| format!("Failed to read pages: {}", e))?
+    {
+      let page = cursor
+        .deserialize_current()
+        .map_err(|e| format!("Invalid document in pages: {}", e))?;
+      if let Some(content) = page
+        .page
+        .as_document()
+        .and_then(|page| page.get_str("content").ok())
+      {
+        refs.extend(attachment_refs(content));
+      }
+    }
+    // Leave attachments uploaded by pushes that are still writing, as their pages may not be
+    // stored yet
+    let (allocated, oldest_pending) = self.allocated_revisions().await?;
+    let floor = oldest_pending.unwrap_or(allocated + 1);
+    self
+      .collection::<Document>("attachments")
+      .await?
+      .delete_many(doc! { "_id": { "$nin": &refs }, "revision": { "$lt": floor } })
+      .await
+      .map_err(|e| format!("Failed to remove unused attachments: {}", e))?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::SyncRemoteKind;
+  use uuid::Uuid;
+
+  // Remote in a throwaway database on the server at MONGO_TEST_URI, or a local mongod
+  fn test_remote() -> MongoRemote {
+    MongoRemote::new(&SyncConfig {
+      remote: SyncRemoteKind::Mongo,
+      mongo_uri: std::env::var("MONGO_TEST_URI")
+        .unwrap_or_else(|_| "mongodb://localhost:27017".to_string()),
+      mongo_database: format!("elab_test_{}", Uuid::new_v4().simple()),
+      ..SyncConfig::default()
+    })
+  }
+
+  fn entry(id: &str, note: Option<&Value>) -> RemoteEntry {
+    RemoteEntry {
+      id: id.to_string(),
+      path: format!("{}.json", id),
+      hash: note.map(note_hash).unwrap_or_default(),
+      revision: 0,
+      deleted: note.is_none(),
+      modified_at: Utc::now(),
+    }
+  }
+
+  // Needs a MongoDB server: cargo test -- --ignored
+  #[tokio::test]
+  #[ignore]
+  async fn round_trips_notes_through_mongodb() {
+    let remote = test_remote();
+    let image = "data:image/png;base64,iVBORw0KGgo=";
+    let note = json!({
+      "id": "n1",
+      "title": "First",
+      "metadata": { "note_type": "notebook", "tags": [] },
+      "pages": [
+        { "id": "p1", "content": format!("<p>hello</p><img src=\"{}\">", image) },
+        { "id": "p2", "content": "<p>second</p>" },
+      ],
+    });
+
+    let revision = remote
+      .push(&entry("n1", Some(&note)), Some(&note), 0)
+      .await
+      .unwrap()
+      .expect("first push");
+    assert_eq!(remote.fetch("n1").await.unwrap(), Some(note.clone()));
+    let changes = remote.changes(0).await.unwrap();
+    assert_eq!(changes.cursor, revision);
+    assert_eq!(changes.entries.len(), 1);
+    assert_eq!(changes.entries[0].hash, note_hash(&note));
+    // Nothing is pending once the push returned
+    assert_eq!(
+      remote.allocated_revisions().await.unwrap(),
+      (revision as i64, None)
+    );
+
+    // A push based on an older revision is refused
+    let mut edited = note.clone();
+    edited["title"] = json!("Edited");
+    let stale = entry("n1", Some(&edited));
+    assert_eq!(remote.push(&stale, Some(&edited), 0).await.unwrap(), None);
+    let revision = remote
+      .push(&entry("n1", Some(&edited)), Some(&edited), revision)
+      .await
+      .unwrap()
+      .expect("push on the latest revision");
+    assert_eq!(remote.fetch("n1").await.unwrap(), Some(edited));
+
+    // Tombstones leave no content behind
+    remote
+      .push(&entry("n1", None), None, revision)
+      .await
+      .unwrap()
+      .expect("deletion");
+    assert_eq!(remote.fetch("n1").await.unwrap(), None);
+    let changes = remote.changes(revision).await.unwrap();
+    assert!(changes.entries.iter().all(|entry| entry.deleted));
+    // Nor do the files only it embedded
+    remote.prune().await.unwrap();
+    let attachments = remote.collection::<Document>("attachments").await.unwrap();
+    assert_eq!(attachments.count_documents(doc! {}).await.unwrap(), 0);
+
+    remote.database().await.unwrap().drop().await.unwrap();
+  }
+
+  // Needs a MongoDB server: cargo test -- --ignored
+  #[tokio::test]
+  #[ignore]
+  async fn keeps_the_cursor_below_pending_revisions() {
+    let remote = test_remote();
+    let note = json!({ "id": "n1", "title": "First", "pages": [] });
+    // A push that allocated its revision but hasn't written the note yet
+    let pending = remote.next_revision().await.unwrap();
+    let revision = remote
+      .push(&entry("n1", Some(&note)), Some(&note), 0)
+      .await
+      .unwrap()
+      .expect("push");
+    assert!(revision as i64 > pending);
+
+    let changes = remote.changes(0).await.unwrap();
+    assert_eq!(changes.entries.len(), 1);
+    assert_eq!(changes.cursor, pending as u64 - 1);
+    remote.release_revision(pending).await.unwrap();
+    assert_eq!(remote.changes(0).await.unwrap().cursor, revision);
+
+    remote.database().await.unwrap().drop().await.unwrap();
+  }
+
+  // Needs a MongoDB server: cargo test -- --ignored
+  #[tokio::test]
+  #[ignore]
+  async fn refuses_meta_writes_based_on_an_old_version() {
+    let remote = test_remote();
+    let value = json!({ "current": "k1" });
+    assert!(remote.store_meta("keyring", &value, None).await.unwrap());
+    assert!(!remote.store_meta("keyring", &value, None).await.unwrap());
+
+    let (stored, version) = remote.load_meta("keyring").await.unwrap().unwrap();
+    assert_eq!(stored, value);
+    let rotated = json!({ "current": "k2" });
+    assert!(remote
+      .store_meta("keyring", &rotated, Some(version))
+      .await
+      .unwrap());
+    // A device still holding the old version doesn't overwrite the rotation
+    assert!(!remote
+      .store_meta("keyring", &value, Some(version))
+      .await
+      .unwrap());
+    assert_eq!(
+      remote.load_meta("keyring").await.unwrap(),
+      Some((rotated, version + 1))
+    );
 
-  // Initialize it only once
-  match MONGO_CLIENT.set(Arc::new(client)) {
-    Ok(_) => Ok(MONGO_CLIENT.get().unwrap()),
-    Err(_) => Ok(MONGO_CLIENT.get().unwrap()), // Someone else initialized it first
+    remote.database().await.unwrap().drop().await.unwrap();
   }
 }
@@ -1,25 +1,476 @@
-use ollama_rs::{generation::embeddings::request::GenerateEmbeddingsRequest, Ollama};
-use std::sync::OnceLock;
+use crate::config::{get_config, EmbeddingProvider, UserConfig, VectorStoreKind};
+use crate::embedder::{embed_chunks, Embedder, EmbeddingError};
+use crate::qdrant::qdrant_healthy;
+use crate::services::{service_port, OLLAMA_PORT, OLLAMA_SERVICE};
+use async_trait::async_trait;
+use ollama_rs::{
+  generation::{
+    chat::{request::ChatMessageRequest, ChatMessage},
+    embeddings::request::GenerateEmbeddingsRequest,
+    parameters::FormatType,
+  },
+  models::LocalModel,
+  Ollama,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::oneshot;
+use tokio_stream::StreamExt;
 
-use thiserror::Error; // Using thiserror for cleaner error handling
+// Event payload carrying one streamed piece of a chat response
+#[derive(Serialize, Debug, Clone)]
+pub struct ChatChunk {
+  pub request_id: String,
+  pub content: String,
+  pub done: bool,
+}
+
+// What the writing assistant should do with the selected text
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum AssistAction {
+  Continue,
+  Rewrite,
+  Summarize,
+  Expand,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OllamaStatus {
+  pub url: String,
+  pub reachable: bool,
+  pub version: Option<String>,
+  pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ModelStatus {
+  pub name: String,
+  pub installed: bool,
+}
+
+// Overall readiness of the AI features, reported to the UI at startup
+#[derive(Serialize, Debug, Clone)]
+pub struct AiStatus {
+  pub ollama: OllamaStatus,
+  // None when embeddings come from an OpenAI-compatible server instead of Ollama
+  pub embedding_model: Option<ModelStatus>,
+  pub chat_model: Option<ModelStatus>,
+  // None when vectors are kept in the local file store instead of Qdrant
+  pub qdrant_reachable: Option<bool>,
+  pub ready: bool,
+  pub problems: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PullProgress {
+  pub model: String,
+  pub status: String,
+  pub completed: Option<u64>,
+  pub total: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct VersionResponse {
+  version: String,
+}
+
+static OLLAMA_CLIENT: RwLock<Option<Ollama>> = RwLock::new(None);
+
+// A generation currently streaming. The token tells it apart from a later generation reusing
+// its request id after it was cancelled.
+struct InFlight {
+  token: u64,
+  cancel: oneshot::Sender<()>,
+}
 
-// Define a custom error type for this function
-#[derive(Error, Debug)]
-pub enum EmbeddingError {
-  #[error("Ollama API error: {0}")]
-  OllamaError(#[from] ollama_rs::error::OllamaError),
-  #[error("Embeddings not found in Ollama response")]
-  EmbeddingsNotFound,
+static NEXT_TOKEN: AtomicU64 = AtomicU64::new(0);
+// Generations currently streaming, keyed by request id
+static IN_FLIGHT: OnceLock<Mutex<HashMap<String, InFlight>>> = OnceLock::new();
+fn in_flight() -> &'static Mutex<HashMap<String, InFlight>> {
+  IN_FLIGHT.get_or_init(|| Mutex::new(HashMap::new()))
 }
 
-static OLLAMA_CLIENT: OnceLock<Ollama> = OnceLock::new();
-fn get_ollama_client() -> &'static Ollama {
-  OLLAMA_CLIENT.get_or_init(|| Ollama::new("http://localhost".to_string(), 11434))
+// Client for the managed Ollama container on the host port it was given,
+// or for the server at the configured URL
+pub fn get_ollama_client() -> Result<Ollama, String> {
+  if let Some(client) = OLLAMA_CLIENT.read().unwrap().as_ref() {
+    return Ok(client.clone());
+  }
+  let url = match service_port(OLLAMA_SERVICE, OLLAMA_PORT) {
+    Some(port) => format!("http://localhost:{}", port),
+    None => get_config()?.ollama_url,
+  };
+  let client =
+    Ollama::try_new(url.as_str()).map_err(|e| format!("Invalid Ollama URL '{}': {}", url, e))?;
+  *OLLAMA_CLIENT.write().unwrap() = Some(client.clone());
+  Ok(client)
+}
+
+// Drop the cached client so the next access re-reads the config
+pub fn reset_ollama_client() {
+  *OLLAMA_CLIENT.write().unwrap() = None;
+}
+
+// Embedder backed by the Ollama `/api/embed` endpoint
+pub struct OllamaEmbedder {
+  client: Ollama,
+  model: String,
+  dimension: u64,
+}
+
+impl OllamaEmbedder {
+  pub fn new(config: &UserConfig) -> Result<Self, EmbeddingError> {
+    Ok(OllamaEmbedder {
+      client: get_ollama_client().map_err(EmbeddingError::Config)?,
+      model: config.embedding.model.clone(),
+      dimension: config.embedding.dimension,
+    })
+  }
+}
+
+#[async_trait]
+impl Embedder for OllamaEmbedder {
+  fn model(&self) -> &str {
+    &self.model
+  }
+
+  fn dimension(&self) -> u64 {
+    self.dimension
+  }
+
+  async fn embed(&self, inputs: Vec<String>) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+    let expected = inputs.len();
+    let request = GenerateEmbeddingsRequest::new(self.model.clone(), inputs.into());
+    let res = self.client.generate_embeddings(request).await?;
+    if res.embeddings.len() != expected {
+      return Err(EmbeddingError::EmbeddingsNotFound);
+    }
+    Ok(res.embeddings)
+  }
 }
 
 pub async fn embed_note(content: &str) -> Result<Vec<Vec<f32>>, EmbeddingError> {
-  let request = GenerateEmbeddingsRequest::new("granite-embedding:30m".to_string(), content.into());
-  let ollama = get_ollama_client();
-  let res = ollama.generate_embeddings(request).await.unwrap();
-  Ok(res.embeddings)
+  embed_chunks(vec![content.to_string()]).await
+}
+
+// Run a chat with the configured chat model, emitting every token as a ChatChunk on `event`
+// and returning the full response. The generation can be aborted with `cancel_generation`.
+pub async fn stream_chat(
+  app_handle: &AppHandle,
+  event: &str,
+  request_id: &str,
+  messages: Vec<ChatMessage>,
+) -> Result<String, String> {
+  let (cancel, cancel_rx) = oneshot::channel();
+  let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+  {
+    let mut in_flight = in_flight().lock().unwrap();
+    if in_flight.contains_key(request_id) {
+      return Err(format!(
+        "A generation for request {} is already running",
+        request_id
+      ));
+    }
+    in_flight.insert(request_id.to_string(), InFlight { token, cancel });
+  }
+
+  let result = tokio::select! {
+    result = run_chat_stream(app_handle, event, request_id, messages) => result,
+    // A dropped sender is not a cancellation
+    Ok(()) = cancel_rx => {
+      // Let the frontend close the stream it was rendering
+      let _ = app_handle.emit(
+        event,
+        ChatChunk {
+          request_id: request_id.to_string(),
+          content: String::new(),
+          done: true,
+        },
+      );
+      Err("Generation was cancelled".to_string())
+    }
+  };
+  let mut in_flight = in_flight().lock().unwrap();
+  if in_flight
+    .get(request_id)
+    .is_some_and(|entry| entry.token == token)
+  {
+    in_flight.remove(request_id);
+  }
+  result
+}
+
+async fn run_chat_stream(
+  app_handle: &AppHandle,
+  event: &str,
+  request_id: &str,
+  messages: Vec<ChatMessage>,
+) -> Result<String, String> {
+  let model = get_config()?.chat_model;
+  let mut stream = get_ollama_client()?
+    .send_chat_messages_stream(ChatMessageRequest::new(model.clone(), messages))
+    .await
+    .map_err(|e| format!("Failed to start chat with '{}': {}", model, e))?;
+
+  let mut response = String::new();
+  while let Some(chunk) = stream.next().await {
+    let chunk = chunk.map_err(|_| format!("Chat with '{}' was interrupted", model))?;
+    response.push_str(&chunk.message.content);
+    let _ = app_handle.emit(
+      event,
+      ChatChunk {
+        request_id: request_id.to_string(),
+        content: chunk.message.content,
+        done: chunk.done,
+      },
+    );
+    if chunk.done {
+      break;
+    }
+  }
+  Ok(response)
+}
+
+// Run a chat with the configured chat model in JSON mode and parse the reply as `T`
+pub async fn chat_json<T: DeserializeOwned>(messages: Vec<ChatMessage>) -> Result<T, String> {
+  let model = get_config()?.chat_model;
+  let response = get_ollama_client()?
+    .send_chat_messages(ChatMessageRequest::new(model.clone(), messages).format(FormatType::Json))
+    .await
+    .map_err(|e| format!("Chat with '{}' failed: {}", model, e))?;
+  serde_json::from_str(&response.message.content)
+    .map_err(|e| format!("Unexpected reply from '{}': {}", model, e))
+}
+
+fn assist_prompt(action: AssistAction) -> &'static str {
+  match action {
+    AssistAction::Continue => {
+      "Continue the user's text in the same voice and style. \
+Reply with only the continuation, without repeating the original text."
+    }
+    AssistAction::Rewrite => {
+      "Rewrite the user's text to be clearer and better written while keeping its meaning. \
+Reply with only the rewritten text."
+    }
+    AssistAction::Summarize => {
+      "Summarize the user's text in a few sentences. Reply with only the summary."
+    }
+    AssistAction::Expand => {
+      "Expand the user's text with more detail, explanation and examples while keeping its \
+meaning and style. Reply with only the expanded text."
+    }
+  }
+}
+
+// Run a writing assistant action on `text`, streaming the result as ChatChunk events on
+// "ai-assist-stream" tagged with `request_id`. `context` is surrounding note text and
+// `instruction` an optional extra request from the user.
+#[tauri::command]
+pub async fn ai_assist(
+  app_handle: AppHandle,
+  request_id: String,
+  action: AssistAction,
+  text: String,
+  context: Option<String>,
+  instruction: Option<String>,
+) -> Result<String, String> {
+  let mut system = assist_prompt(action).to_string();
+  if let Some(instruction) = instruction.filter(|i| !i.trim().is_empty()) {
+    system.push_str(&format!(" Additional instruction: {}", instruction));
+  }
+  let mut messages = vec![ChatMessage::system(system)];
+  if let Some(context) = context.filter(|c| !c.trim().is_empty()) {
+    messages.push(ChatMessage::user(format!(
+      "For context, the surrounding note reads:\n{}",
+      context
+    )));
+  }
+  messages.push(ChatMessage::user(text));
+  stream_chat(&app_handle, "ai-assist-stream", &request_id, messages).await
+}
+
+// Abort the generation streaming for `request_id`; false if none is in flight
+#[tauri::command]
+pub fn cancel_generation(request_id: String) -> bool {
+  match in_flight().lock().unwrap().remove(&request_id) {
+    Some(entry) => entry.cancel.send(()).is_ok(),
+    None => false,
+  }
+}
+
+// Version reported by the Ollama server, failing quickly if it isn't running
+async fn ollama_version(client: &Ollama) -> Result<String, String> {
+  let http = reqwest::Client::builder()
+    .timeout(Duration::from_secs(3))
+    .build()
+    .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+  let response: VersionResponse = http
+    .get(format!("{}api/version", client.url_str()))
+    .send()
+    .await
+    .and_then(|response| response.error_for_status())
+    .map_err(|e| format!("Ollama is not reachable at {}: {}", client.url_str(), e))?
+    .json()
+    .await
+    .map_err(|e| format!("Unexpected version response from Ollama: {}", e))?;
+  Ok(response.version)
+}
+
+// Installed model names carry a tag, so "llama3.2" is installed as "llama3.2:latest"
+fn model_installed(models: &[LocalModel], name: &str) -> bool {
+  models
+    .iter()
+    .any(|model| model.name == name || model.name == format!("{}:latest", name))
+}
+
+// Whether the Ollama server answers
+pub async fn ollama_healthy() -> bool {
+  match get_ollama_client() {
+    Ok(client) => ollama_version(&client).await.is_ok(),
+    Err(_) => false,
+  }
+}
+
+// Pull `model` unless it is already installed, reporting progress like `pull_ollama_model`
+pub async fn ensure_ollama_model(app_handle: &AppHandle, model: &str) -> Result<(), String> {
+  if model_installed(&list_ollama_models().await?, model) {
+    return Ok(());
+  }
+  eprintln!("Pulling Ollama model {}", model);
+  pull_ollama_model(app_handle.clone(), model.to_string()).await
+}
+
+#[tauri::command]
+pub async fn ollama_status() -> Result<OllamaStatus, String> {
+  let client = get_ollama_client()?;
+  let url = client.url_str().to_string();
+  Ok(match ollama_version(&client).await {
+    Ok(version) => OllamaStatus {
+      url,
+      reachable: true,
+      version: Some(version),
+      error: None,
+    },
+    Err(e) => OllamaStatus {
+      url,
+      reachable: false,
+      version: None,
+      error: Some(e),
+    },
+  })
+}
+
+#[tauri::command]
+pub async fn list_ollama_models() -> Result<Vec<LocalModel>, String> {
+  get_ollama_client()?
+    .list_local_models()
+    .await
+    .map_err(|e| format!("Failed to list Ollama models: {}", e))
+}
+
+// Download `model` into Ollama, reporting progress as "ollama-pull-progress" events
+#[tauri::command]
+pub async fn pull_ollama_model(app_handle: AppHandle, model: String) -> Result<(), String> {
+  let mut stream = get_ollama_client()?
+    .pull_model_stream(model.clone(), false)
+    .await
+    .map_err(|e| format!("Failed to pull '{}': {}", model, e))?;
+
+  while let Some(status) = stream.next().await {
+    let status = status.map_err(|e| format!("Failed to pull '{}': {}", model, e))?;
+    let _ = app_handle.emit(
+      "ollama-pull-progress",
+      PullProgress {
+        model: model.clone(),
+        status: status.message,
+        completed: status.completed,
+        total: status.total,
+      },
+    );
+  }
+  Ok(())
+}
+
+// Check that Ollama and Qdrant are running and the configured models are installed
+pub async fn check_ai_status() -> Result<AiStatus, String> {
+  let config = get_config()?;
+  let ollama = ollama_status().await?;
+  let mut problems = Vec::new();
+  if let Some(error) = &ollama.error {
+    problems.push(error.clone());
+  }
+
+  let mut embedding_model = None;
+  let mut chat_model = None;
+  if ollama.reachable {
+    let models = list_ollama_models().await?;
+    if config.embedding.provider == EmbeddingProvider::Ollama {
+      let installed = model_installed(&models, &config.embedding.model);
+      if !installed {
+        problems.push(format!(
+          "Embedding model '{}' is not installed, so notes can't be searched",
+          config.embedding.model
+        ));
+      }
+      embedding_model = Some(ModelStatus {
+        name: config.embedding.model.clone(),
+        installed,
+      });
+    }
+    let installed = model_installed(&models, &config.chat_model);
+    if !installed {
+      problems.push(format!(
+        "Chat model '{}' is not installed, so questions and writing assistance are unavailable",
+        config.chat_model
+      ));
+    }
+    chat_model = Some(ModelStatus {
+      name: config.chat_model.clone(),
+      installed,
+    });
+  }
+
+  let mut qdrant_reachable = None;
+  if config.vector_store == VectorStoreKind::Qdrant {
+    let reachable = qdrant_healthy().await;
+    if !reachable {
+      problems.push("Qdrant is not reachable, so notes can't be indexed or searched".to_string());
+    }
+    qdrant_reachable = Some(reachable);
+  }
+
+  Ok(AiStatus {
+    ready: problems.is_empty(),
+    ollama,
+    embedding_model,
+    chat_model,
+    qdrant_reachable,
+    problems,
+  })
+}
+
+#[tauri::command]
+pub async fn ai_status() -> Result<AiStatus, String> {
+  check_ai_status().await
+}
+
+// Check the AI subsystem in the background and emit the result as an "ai-status" event.
+// Runs at startup and whenever a service becomes ready or stops.
+pub fn report_ai_status(app_handle: AppHandle) {
+  tauri::async_runtime::spawn(async move {
+    match check_ai_status().await {
+      Ok(status) => {
+        if !status.ready {
+          eprintln!("AI features unavailable: {}", status.problems.join("; "));
+        }
+        let _ = app_handle.emit("ai-status", status);
+      }
+      Err(e) => eprintln!("Failed to check AI status: {}", e),
+    }
+  });
 }
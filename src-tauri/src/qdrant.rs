@@ -1,51 +1,490 @@
-use crate::{fs::get_app_data_dir, ollama::embed_note};
+use crate::{
+  config::{get_config, VectorDistance},
+  embedder::embed_chunks,
+  fs::{
+    cached_note_id, collect_note_paths, get_app_data_dir, note_page_texts, note_path, read_note,
+  },
+  services::{service_port, QDRANT_GRPC_PORT, QDRANT_SERVICE},
+  vector_store::{
+    ensure_collection, get_vector_store, ChunkPayload, ScoredChunk, VectorPoint, VectorStore,
+  },
+};
+use async_trait::async_trait;
 use notify::{
   Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Result as NotifyResult, Watcher,
 };
 use qdrant_client::{
   qdrant::{
-    CreateCollectionBuilder, Distance, PointStruct, UpsertPointsBuilder, VectorParamsBuilder,
+    point_id::PointIdOptions, vector_output, vectors_config, Condition, CreateCollectionBuilder,
+    DeletePointsBuilder, Distance, Filter, PointId, PointStruct, ScrollPointsBuilder,
+    SearchPointsBuilder, UpsertPointsBuilder, Value, VectorParamsBuilder,
   },
-  Qdrant, QdrantError,
+  Payload, Qdrant,
 };
-use std::error::Error;
-use std::fs::read_to_string;
-use std::sync::OnceLock;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use tauri::{AppHandle, Emitter};
 use tokio::{spawn, sync::mpsc};
+use uuid::Uuid;
 
-static QDRANT_CLIENT: OnceLock<Qdrant> = OnceLock::new();
-fn get_qdrant_client() -> &'static Qdrant {
-  QDRANT_CLIENT.get_or_init(|| Qdrant::from_url("http://localhost:6334").build().unwrap())
+pub const NOTES_COLLECTION: &str = "notes";
+// Upper bound on the characters embedded as a single chunk
+const MAX_CHUNK_CHARS: usize = 1500;
+const SCROLL_PAGE_SIZE: u32 = 256;
+
+static REINDEX_RUNNING: AtomicBool = AtomicBool::new(false);
+static REINDEX_CANCELLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ReindexProgress {
+  pub processed: usize,
+  pub total: usize,
+  pub failed: usize,
+  pub current: Option<String>,
 }
 
-pub async fn check_collection_existence(name: &str) -> Result<bool, QdrantError> {
-  let client = get_qdrant_client();
-  // The '?' operator now correctly propagates QdrantError
-  let result = client.collection_exists(name).await?;
-  // Wrap the boolean result in Ok
-  Ok(result)
+#[derive(Serialize, Debug, Clone)]
+pub struct ReindexSummary {
+  pub indexed: usize,
+  pub chunks: usize,
+  pub failed: Vec<String>,
+  pub cancelled: bool,
 }
 
-pub async fn add_embedding(vectors: Vec<PointStruct>) -> Result<(), Box<dyn Error>> {
-  let qdrant_client = get_qdrant_client(); // Propagate error if client creation fails
-  let response = qdrant_client
-    .upsert_points(UpsertPointsBuilder::new("notes", vectors).wait(true))
-    .await?;
-  println!("Successfully upserted points: {:?}", response);
-  Ok(())
+static QDRANT_CLIENT: RwLock<Option<Arc<Qdrant>>> = RwLock::new(None);
+
+// gRPC URL of the managed container on the host port it was given,
+// or the configured URL for an external server
+fn qdrant_url() -> String {
+  if let Some(port) = service_port(QDRANT_SERVICE, QDRANT_GRPC_PORT) {
+    return format!("http://localhost:{}", port);
+  }
+  get_config()
+    .map(|config| config.qdrant_url)
+    .unwrap_or_else(|_| "http://localhost:6334".to_string())
+}
+
+fn get_qdrant_client() -> Result<Arc<Qdrant>, String> {
+  if let Some(client) = QDRANT_CLIENT.read().unwrap().as_ref() {
+    return Ok(client.clone());
+  }
+  let url = qdrant_url();
+  let client = Qdrant::from_url(&url)
+    .build()
+    .map_err(|e| format!("Invalid Qdrant URL '{}': {}", url, e))?;
+  let client = Arc::new(client);
+  *QDRANT_CLIENT.write().unwrap() = Some(client.clone());
+  Ok(client)
+}
+
+// Drop the cached client so the next access reconnects, e.g. after the service's port changed
+pub fn reset_qdrant_client() {
+  *QDRANT_CLIENT.write().unwrap() = None;
 }
 
-pub async fn create_collection(name: &str) -> Result<(), String> {
-  let client = get_qdrant_client();
-  client
-    .create_collection(
-      CreateCollectionBuilder::new(name)
-        .vectors_config(VectorParamsBuilder::new(384, Distance::Dot)),
-    )
+// Whether the Qdrant server answers health checks
+pub async fn qdrant_healthy() -> bool {
+  match get_qdrant_client() {
+    Ok(client) => client.health_check().await.is_ok(),
+    Err(_) => false,
+  }
+}
+
+pub async fn check_collection_existence(name: &str) -> Result<bool, String> {
+  get_qdrant_client()?
+    .collection_exists(name)
     .await
-    .unwrap();
-  Ok(())
+    .map_err(|e| format!("Failed to check collection '{}': {}", name, e))
+}
+
+// Decode a Qdrant payload into our chunk payload, skipping points written by something else
+fn chunk_payload(payload: HashMap<String, Value>) -> Option<ChunkPayload> {
+  let payload = serde_json::Value::from(Payload::from(payload));
+  match serde_json::from_value(payload) {
+    Ok(payload) => Some(payload),
+    Err(e) => {
+      eprintln!("Skipping point with unexpected payload: {}", e);
+      None
+    }
+  }
+}
+
+fn point_id_to_string(id: Option<PointId>) -> String {
+  match id.and_then(|id| id.point_id_options) {
+    Some(PointIdOptions::Uuid(uuid)) => uuid,
+    Some(PointIdOptions::Num(num)) => num.to_string(),
+    None => String::new(),
+  }
+}
+
+// VectorStore backed by the Qdrant server
+pub struct QdrantStore;
+
+#[async_trait]
+impl VectorStore for QdrantStore {
+  async fn collection_exists(&self, collection: &str) -> Result<bool, String> {
+    check_collection_existence(collection).await
+  }
+
+  async fn collection_dimension(&self, collection: &str) -> Result<Option<u64>, String> {
+    let info = get_qdrant_client()?
+      .collection_info(collection)
+      .await
+      .map_err(|e| format!("Failed to read collection '{}': {}", collection, e))?;
+    // Only a single unnamed vector per point has a meaningful dimension
+    let size = info
+      .result
+      .and_then(|info| info.config)
+      .and_then(|config| config.params)
+      .and_then(|params| params.vectors_config)
+      .and_then(|vectors| vectors.config)
+      .and_then(|config| match config {
+        vectors_config::Config::Params(params) => Some(params.size),
+        _ => None,
+      });
+    Ok(size)
+  }
+
+  async fn create_collection(
+    &self,
+    collection: &str,
+    dimension: u64,
+    distance: VectorDistance,
+  ) -> Result<(), String> {
+    let distance = match distance {
+      VectorDistance::Cosine => Distance::Cosine,
+      VectorDistance::Dot => Distance::Dot,
+      VectorDistance::Euclid => Distance::Euclid,
+    };
+    get_qdrant_client()?
+      .create_collection(
+        CreateCollectionBuilder::new(collection)
+          .vectors_config(VectorParamsBuilder::new(dimension, distance)),
+      )
+      .await
+      .map_err(|e| format!("Failed to create collection '{}': {}", collection, e))?;
+    Ok(())
+  }
+
+  async fn delete_collection(&self, collection: &str) -> Result<(), String> {
+    get_qdrant_client()?
+      .delete_collection(collection)
+      .await
+      .map_err(|e| format!("Failed to delete collection '{}': {}", collection, e))?;
+    Ok(())
+  }
+
+  async fn upsert(&self, collection: &str, points: Vec<VectorPoint>) -> Result<(), String> {
+    let mut structs = Vec::with_capacity(points.len());
+    for point in points {
+      let payload = serde_json::to_value(&point.payload)
+        .map_err(|e| format!("Failed to serialize payload: {}", e))?;
+      let payload =
+        Payload::try_from(payload).map_err(|e| format!("Failed to build payload: {}", e))?;
+      structs.push(PointStruct::new(point.id, point.vector, payload));
+    }
+    get_qdrant_client()?
+      .upsert_points(UpsertPointsBuilder::new(collection, structs).wait(true))
+      .await
+      .map_err(|e| format!("Failed to upsert points into '{}': {}", collection, e))?;
+    Ok(())
+  }
+
+  async fn search(
+    &self,
+    collection: &str,
+    vector: &[f32],
+    limit: usize,
+    exclude_note: Option<&str>,
+  ) -> Result<Vec<ScoredChunk>, String> {
+    let mut request =
+      SearchPointsBuilder::new(collection, vector.to_vec(), limit as u64).with_payload(true);
+    if let Some(note_id) = exclude_note {
+      request = request.filter(Filter::must_not([Condition::matches(
+        "note_id",
+        note_id.to_string(),
+      )]));
+    }
+    let response = get_qdrant_client()?
+      .search_points(request)
+      .await
+      .map_err(|e| format!("Failed to search '{}': {}", collection, e))?;
+
+    let mut results = Vec::with_capacity(response.result.len());
+    for point in response.result {
+      let Some(payload) = chunk_payload(point.payload) else {
+        continue;
+      };
+      results.push(ScoredChunk {
+        id: point_id_to_string(point.id),
+        score: point.score,
+        payload,
+      });
+    }
+    Ok(results)
+  }
+
+  async fn get_by_note(&self, collection: &str, note_id: &str) -> Result<Vec<VectorPoint>, String> {
+    let mut points = Vec::new();
+    let mut offset: Option<PointId> = None;
+    loop {
+      let mut request = ScrollPointsBuilder::new(collection)
+        .filter(Filter::must([Condition::matches(
+          "note_id",
+          note_id.to_string(),
+        )]))
+        .with_payload(true)
+        .with_vectors(true)
+        .limit(SCROLL_PAGE_SIZE);
+      if let Some(offset) = offset.take() {
+        request = request.offset(offset);
+      }
+      let response = get_qdrant_client()?
+        .scroll(request)
+        .await
+        .map_err(|e| format!("Failed to read points of {}: {}", note_id, e))?;
+
+      for point in response.result {
+        let vector = match point.vectors.and_then(|vectors| vectors.get_vector()) {
+          Some(vector_output::Vector::Dense(dense)) => dense.data,
+          _ => continue,
+        };
+        let Some(payload) = chunk_payload(point.payload) else {
+          continue;
+        };
+        points.push(VectorPoint {
+          id: point_id_to_string(point.id),
+          vector,
+          payload,
+        });
+      }
+
+      match response.next_page_offset {
+        Some(next) => offset = Some(next),
+        None => break,
+      }
+    }
+    Ok(points)
+  }
+
+  async fn delete_by_note(&self, collection: &str, note_id: &str) -> Result<(), String> {
+    get_qdrant_client()?
+      .delete_points(
+        DeletePointsBuilder::new(collection)
+          .points(Filter::must([Condition::matches(
+            "note_id",
+            note_id.to_string(),
+          )]))
+          .wait(true),
+      )
+      .await
+      .map_err(|e| format!("Failed to remove embeddings for {}: {}", note_id, e))?;
+    Ok(())
+  }
+}
+
+// Split text into chunks of at most MAX_CHUNK_CHARS, breaking on whitespace
+pub fn chunk_text(text: &str) -> Vec<String> {
+  let mut chunks = Vec::new();
+  let mut current = String::new();
+  for word in text.split_whitespace() {
+    if !current.is_empty() && current.len() + word.len() + 1 > MAX_CHUNK_CHARS {
+      chunks.push(std::mem::take(&mut current));
+    }
+    if !current.is_empty() {
+      current.push(' ');
+    }
+    current.push_str(word);
+  }
+  if !current.is_empty() {
+    chunks.push(current);
+  }
+  chunks
+}
+
+// Stable point id for one chunk of a page, so re-indexing overwrites instead of duplicating.
+// Keyed on the note id so the points survive the note being moved.
+fn chunk_point_id(note_id: &str, page_id: &str, chunk: usize) -> String {
+  let key = format!("{}#{}#{}", note_id, page_id, chunk);
+  Uuid::new_v5(&Uuid::NAMESPACE_URL, key.as_bytes()).to_string()
+}
+
+// Remove every point that belongs to the note `note_id`
+pub async fn remove_note_embeddings(note_id: &str) -> Result<(), String> {
+  get_vector_store()?
+    .delete_by_note(NOTES_COLLECTION, note_id)
+    .await
+}
+
+// Embed every page of the note at `path` and replace its points in the collection.
+// Returns the number of chunks written.
+pub async fn index_note_file(path: &Path) -> Result<usize, String> {
+  let path_str = path
+    .to_str()
+    .ok_or_else(|| "Invalid path encoding".to_string())?;
+  let note = read_note(path_str)?;
+  let title = note["title"].as_str().unwrap_or("").to_string();
+  let note_id = note["id"]
+    .as_str()
+    .ok_or_else(|| format!("Note {} has no id", path_str))?
+    .to_string();
+
+  let mut chunks = Vec::new();
+  for (page_id, text) in note_page_texts(&note) {
+    for (index, chunk) in chunk_text(&text).into_iter().enumerate() {
+      chunks.push((page_id.clone(), index, chunk));
+    }
+  }
+
+  let store = get_vector_store()?;
+  let embedding = get_config()?.embedding;
+  ensure_collection(
+    store.as_ref(),
+    NOTES_COLLECTION,
+    embedding.dimension,
+    embedding.distance,
+    false,
+  )
+  .await?;
+  if chunks.is_empty() {
+    store.delete_by_note(NOTES_COLLECTION, &note_id).await?;
+    return Ok(0);
+  }
+
+  let vectors = embed_chunks(chunks.iter().map(|(_, _, text)| text.clone()).collect())
+    .await
+    .map_err(|e| format!("Error embedding note {}: {}", path_str, e))?;
+  // Only drop the old chunks once the new ones exist, so a failed embedding keeps the note
+  // searchable
+  store.delete_by_note(NOTES_COLLECTION, &note_id).await?;
+
+  let points: Vec<VectorPoint> = chunks
+    .into_iter()
+    .zip(vectors)
+    .map(|((page_id, chunk, text), vector)| VectorPoint {
+      id: chunk_point_id(&note_id, &page_id, chunk),
+      vector,
+      payload: ChunkPayload {
+        note_id: note_id.clone(),
+        path: path_str.to_string(),
+        title: title.clone(),
+        page_id,
+        chunk,
+        text,
+      },
+    })
+    .collect();
+
+  let count = points.len();
+  store.upsert(NOTES_COLLECTION, points).await?;
+  Ok(count)
+}
+
+async fn run_reindex(app_handle: &AppHandle, recreate: bool) -> Result<ReindexSummary, String> {
+  // Embedding a probe up front surfaces an unreachable model or a wrong dimension before any
+  // collection is touched
+  embed_chunks(vec!["dimension".to_string()])
+    .await
+    .map_err(|e| format!("Failed to query embedding model: {}", e))?;
+  let embedding = get_config()?.embedding;
+  let store = get_vector_store()?;
+  ensure_collection(
+    store.as_ref(),
+    NOTES_COLLECTION,
+    embedding.dimension,
+    embedding.distance,
+    recreate,
+  )
+  .await?;
+
+  let paths = collect_note_paths()?;
+  let total = paths.len();
+  let mut summary = ReindexSummary {
+    indexed: 0,
+    chunks: 0,
+    failed: Vec::new(),
+    cancelled: false,
+  };
+
+  for (processed, path) in paths.iter().enumerate() {
+    if REINDEX_CANCELLED.load(Ordering::SeqCst) {
+      summary.cancelled = true;
+      break;
+    }
+    let current = path.to_string_lossy().into_owned();
+    let _ = app_handle.emit(
+      "reindex-progress",
+      ReindexProgress {
+        processed,
+        total,
+        failed: summary.failed.len(),
+        current: Some(current.clone()),
+      },
+    );
+    match index_note_file(path).await {
+      Ok(chunks) => {
+        summary.indexed += 1;
+        summary.chunks += chunks;
+      }
+      Err(e) => {
+        eprintln!("{}", e);
+        summary.failed.push(current);
+      }
+    }
+  }
+
+  let _ = app_handle.emit(
+    "reindex-progress",
+    ReindexProgress {
+      processed: summary.indexed + summary.failed.len(),
+      total,
+      failed: summary.failed.len(),
+      current: None,
+    },
+  );
+  Ok(summary)
 }
+
+// Rebuild the embeddings of every note, creating (or with `recreate`, rebuilding) the collection.
+// Progress is reported through "reindex-progress" events.
+#[tauri::command]
+pub async fn reindex_vault(
+  app_handle: AppHandle,
+  recreate: Option<bool>,
+) -> Result<ReindexSummary, String> {
+  if REINDEX_RUNNING.swap(true, Ordering::SeqCst) {
+    return Err("A re-index is already running".to_string());
+  }
+  REINDEX_CANCELLED.store(false, Ordering::SeqCst);
+  let result = run_reindex(&app_handle, recreate.unwrap_or(false)).await;
+  REINDEX_RUNNING.store(false, Ordering::SeqCst);
+  result
+}
+
+// Ask a running re-index to stop after the note it is currently processing
+#[tauri::command]
+pub fn cancel_reindex() {
+  REINDEX_CANCELLED.store(true, Ordering::SeqCst);
+}
+
+// Chunks whose embeddings are closest to the embedding of `query`
+#[tauri::command]
+pub async fn semantic_search(
+  query: String,
+  limit: Option<usize>,
+) -> Result<Vec<ScoredChunk>, String> {
+  let vector = embed_chunks(vec![query])
+    .await
+    .map_err(|e| format!("Failed to embed query: {}", e))?
+    .remove(0);
+  get_vector_store()?
+    .search(NOTES_COLLECTION, &vector, limit.unwrap_or(10), None)
+    .await
+}
+
 async fn setup_directory_watcher_task() -> Result<(), String> {
   let app_dir = get_app_data_dir()?; // Use ? for error handling
   let (tx, mut rx) = mpsc::channel::<Event>(100); // Use tokio's mpsc channel
@@ -80,48 +519,42 @@ async fn setup_directory_watcher_task() -> Result<(), String> {
 
   println!("Directory watcher started for: {}", app_dir.display());
 
+  let notes_dir = app_dir.join("notes");
   tokio::spawn(async move {
     // Keep the watcher alive for the duration of the task
     let _watcher = watcher;
-    let client = get_qdrant_client();
 
     while let Some(event) = rx.recv().await {
       // Consider handling multiple paths more efficiently if needed
       // (e.g., sequential processing or bounded concurrency)
       match event.kind {
         EventKind::Create(_) | EventKind::Modify(_) => {
-          // Handle Modify too? Decide if re-embedding is needed.
           for pathbuf in event.paths {
+            if !pathbuf.starts_with(&notes_dir)
+              || pathbuf.extension().and_then(|ext| ext.to_str()) != Some("json")
+            {
+              continue;
+            }
             // Spawn a task for each file to process concurrently
-            tokio::spawn(async move {
-              let path_str = pathbuf.to_string_lossy().into_owned();
-              let content = read_to_string(&pathbuf).unwrap();
-              match embed_note(&content).await {
-                Ok(result) => {
-                  let mut points = Vec::new();
-                  for n in 1..result.len() {
-                    let point_id = format!("{}-{}", path_str, n.to_string());
-                    let payload_data = [("index", (n as i64).into())];
-                    points.push(PointStruct::new(point_id, result[n].clone(), payload_data));
-                  }
-                  let response = client
-                    .upsert_points(UpsertPointsBuilder::new("notes", points).wait(true))
-                    .await;
-                  dbg!(response);
-                }
-                Err(e) => eprintln!("Error embedding this note: {}", &path_str),
+            spawn(async move {
+              if let Err(e) = index_note_file(&pathbuf).await {
+                eprintln!("{}", e);
               }
             });
           }
         }
         EventKind::Remove(_) => {
           for pathbuf in event.paths {
-            let path_str = pathbuf.to_string_lossy().into_owned();
-            println!(
-              "File removed, potentially remove embedding for: {}",
-              path_str
-            );
-            // Add logic here to remove the corresponding embedding from your index
+            let Some(note_id) = cached_note_id(&pathbuf) else {
+              continue;
+            };
+            // A note that was moved is still found by its id and keeps its embeddings
+            if note_path(&note_id).is_ok() {
+              continue;
+            }
+            if let Err(e) = remove_note_embeddings(&note_id).await {
+              eprintln!("{}", e);
+            }
           }
         }
         _ => {
@@ -0,0 +1,107 @@
+use crate::embedder::embed_chunks;
+use crate::ollama::stream_chat;
+use crate::qdrant::NOTES_COLLECTION;
+use crate::vector_store::{get_vector_store, ScoredChunk};
+use ollama_rs::generation::chat::ChatMessage;
+use serde::Serialize;
+use tauri::AppHandle;
+
+const SYSTEM_PROMPT: &str = "You answer questions using only the user's notes. \
+Each source is numbered like [1]. Cite the sources you rely on with their numbers in brackets. \
+If the sources do not contain the answer, say that the notes don't cover it.";
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Citation {
+  // Number of the source in the prompt, as referenced by the answer
+  pub source: usize,
+  pub note_id: String,
+  pub path: String,
+  pub title: String,
+  pub page_id: String,
+  pub text: String,
+  pub score: f32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct NotesAnswer {
+  pub answer: String,
+  pub citations: Vec<Citation>,
+}
+
+fn build_prompt(question: &str, chunks: &[ScoredChunk]) -> String {
+  let mut prompt = String::from("Sources:\n\n");
+  for (index, chunk) in chunks.iter().enumerate() {
+    prompt.push_str(&format!(
+      "[{}] {}\n{}\n\n",
+      index + 1,
+      chunk.payload.title,
+      chunk.payload.text
+    ));
+  }
+  prompt.push_str(&format!("Question: {}", question));
+  prompt
+}
+
+// Source numbers referenced as [n] in the answer
+fn cited_sources(answer: &str, count: usize) -> Vec<usize> {
+  let mut cited = Vec::new();
+  for part in answer.split('[').skip(1) {
+    let Some((number, _)) = part.split_once(']') else {
+      continue;
+    };
+    if let Ok(source) = number.trim().parse::<usize>() {
+      if (1..=count).contains(&source) && !cited.contains(&source) {
+        cited.push(source);
+      }
+    }
+  }
+  cited.sort_unstable();
+  cited
+}
+
+// Answer `question` from the most relevant note chunks. The answer is streamed as ChatChunk
+// events on "ask-notes-stream" tagged with `request_id`; the returned citations cover the
+// sources the answer refers to, or every retrieved source if it cites none.
+#[tauri::command]
+pub async fn ask_notes(
+  app_handle: AppHandle,
+  question: String,
+  request_id: String,
+  limit: Option<usize>,
+) -> Result<NotesAnswer, String> {
+  let vector = embed_chunks(vec![question.clone()])
+    .await
+    .map_err(|e| format!("Failed to embed question: {}", e))?
+    .remove(0);
+  let chunks = get_vector_store()?
+    .search(NOTES_COLLECTION, &vector, limit.unwrap_or(6), None)
+    .await?;
+
+  let messages = vec![
+    ChatMessage::system(SYSTEM_PROMPT.to_string()),
+    ChatMessage::user(build_prompt(&question, &chunks)),
+  ];
+  let answer = stream_chat(&app_handle, "ask-notes-stream", &request_id, messages).await?;
+
+  let mut sources = cited_sources(&answer, chunks.len());
+  if sources.is_empty() {
+    sources = (1..=chunks.len()).collect();
+  }
+  let citations = sources
+    .into_iter()
+    .map(|source| {
+      let chunk = &chunks[source - 1];
+      Citation {
+        source,
+        note_id: chunk.payload.note_id.clone(),
+        path: chunk.payload.path.clone(),
+        title: chunk.payload.title.clone(),
+        page_id: chunk.payload.page_id.clone(),
+        text: chunk.payload.text.clone(),
+        score: chunk.score,
+      }
+    })
+    .collect();
+
+  Ok(NotesAnswer { answer, citations })
+}
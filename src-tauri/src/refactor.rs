@@ -0,0 +1,527 @@
+use crate::duplicates::content_hash;
+use crate::fs::{
+  collect_note_paths, get_app_data_dir, note_path, notify_note_saved, read_file, read_note,
+};
+use crate::links::{links_to, rewrite_links};
+use crate::qdrant::index_note_file;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+// Refactors kept for undo
+const MAX_HISTORY: usize = 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Refactor {
+  Rename {
+    path: String,
+    old_title: String,
+    new_title: String,
+  },
+  // Moves a note file or a directory of notes
+  Move {
+    source: String,
+    destination: String,
+  },
+  // Points links at the note a duplicate was merged into; not kept for undo
+  Merge {
+    source: String,
+    target: String,
+  },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AffectedNote {
+  pub path: String,
+  pub title: String,
+  // Links rewritten in this note
+  pub links: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RefactorPreview {
+  pub refactor: Refactor,
+  pub affected: Vec<AffectedNote>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RefactorResult {
+  // Identifies the refactor in the undo history
+  pub id: String,
+  pub refactor: Refactor,
+  pub affected: Vec<AffectedNote>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct OriginalFile {
+  path: String,
+  content: String,
+  // Hash of the content the refactor wrote, to detect later edits before undoing
+  #[serde(default)]
+  written_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct RefactorRecord {
+  id: String,
+  refactor: Refactor,
+  applied_at: DateTime<Utc>,
+  // Contents of every edited note before the refactor, at its path before any move
+  originals: Vec<OriginalFile>,
+  // Note files that changed path, old path to new path
+  #[serde(default)]
+  moves: Vec<(String, String)>,
+}
+
+struct Plan {
+  refactor: Refactor,
+  // Note files that change path, old path to new path
+  moves: Vec<(String, String)>,
+  // Updated note contents by current path
+  edits: BTreeMap<String, Value>,
+  affected: Vec<AffectedNote>,
+}
+
+fn history_path() -> Result<PathBuf, String> {
+  Ok(get_app_data_dir()?.join("refactor_history.json"))
+}
+
+fn load_history() -> Result<Vec<RefactorRecord>, String> {
+  let path = history_path()?;
+  if !path.exists() {
+    return Ok(Vec::new());
+  }
+  let content =
+    fs::read_to_string(&path).map_err(|e| format!("Failed to read refactor history: {}", e))?;
+  serde_json::from_str(&content).map_err(|e| format!("Failed to parse refactor history: {}", e))
+}
+
+fn save_history(history: &[RefactorRecord]) -> Result<(), String> {
+  let content = serde_json::to_string(history)
+    .map_err(|e| format!("Failed to serialize refactor history: {}", e))?;
+  fs::write(history_path()?, content).map_err(|e| format!("Failed to save refactor history: {}", e))
+}
+
+// Rewrite links in the typed text of the pages of `note`, returning the number rewritten
+fn rewrite_note_links(note: &mut Value, retarget: &dyn Fn(&str) -> Option<String>) -> usize {
+  let is_notebook = note["metadata"]["note_type"].as_str() == Some("notebook");
+  let mut count = 0;
+  for page in note["pages"].as_array_mut().into_iter().flatten() {
+    let Some(content) = page["content"].as_str() else {
+      continue;
+    };
+    // Free notes without typed text hold a stroke list, as in `note_page_texts`
+    if !is_notebook && content.trim_start().starts_with('[') {
+      continue;
+    }
+    let (content, rewritten) = rewrite_links(content, retarget);
+    if rewritten > 0 {
+      page["content"] = json!(content);
+      count += rewritten;
+    }
+  }
+  count
+}
+
+// Load `path` into `edits` unless an earlier step already did
+fn edited_note<'a>(
+  edits: &'a mut BTreeMap<String, Value>,
+  path: &str,
+) -> Result<&'a mut Value, String> {
+  if !edits.contains_key(path) {
+    edits.insert(path.to_string(), read_note(path)?);
+  }
+  Ok(edits.get_mut(path).unwrap())
+}
+
+fn add_affected(affected: &mut Vec<AffectedNote>, path: &str, note: &Value, links: usize) {
+  if links == 0 {
+    return;
+  }
+  match affected.iter_mut().find(|entry| entry.path == path) {
+    Some(entry) => entry.links += links,
+    None => affected.push(AffectedNote {
+      path: path.to_string(),
+      title: note["title"].as_str().unwrap_or("").to_string(),
+      links,
+    }),
+  }
+}
+
+fn plan_rename(path: &str, new_title: &str) -> Result<Plan, String> {
+  let new_title = new_title.trim();
+  if new_title.is_empty() {
+    return Err("Title cannot be empty".to_string());
+  }
+  let mut edits = BTreeMap::new();
+  let note = edited_note(&mut edits, path)?;
+  let old_title = note["title"].as_str().unwrap_or("").to_string();
+  note["title"] = json!(new_title);
+
+  // Links by id or path keep working, only links by title need the new one
+  let old_key = old_title.trim().to_lowercase();
+  let retarget = |target: &str| (target.to_lowercase() == old_key).then(|| new_title.to_string());
+  let mut affected = Vec::new();
+  for (source, _) in links_to(path)? {
+    let note = edited_note(&mut edits, &source)?;
+    let count = rewrite_note_links(note, &retarget);
+    add_affected(&mut affected, &source, note, count);
+  }
+
+  Ok(Plan {
+    refactor: Refactor::Rename {
+      path: path.to_string(),
+      old_title,
+      new_title: new_title.to_string(),
+    },
+    moves: Vec::new(),
+    edits,
+    affected,
+  })
+}
+
+fn plan_move(source: &str, destination: &str) -> Result<Plan, String> {
+  let source_path = Path::new(source);
+  if !source_path.exists() {
+    return Err(format!("Path {} does not exist", source));
+  }
+  if Path::new(destination).exists() {
+    return Err(format!("Path {} already exists", destination));
+  }
+
+  let mut moves = Vec::new();
+  if source_path.is_dir() {
+    for path in collect_note_paths()? {
+      let Ok(relative) = path.strip_prefix(source_path) else {
+        continue;
+      };
+      let moved = Path::new(destination).join(relative);
+      if let (Some(old), Some(new)) = (path.to_str(), moved.to_str()) {
+        moves.push((old.to_string(), new.to_string()));
+      }
+    }
+  } else {
+    moves.push((source.to_string(), destination.to_string()));
+  }
+
+  let mut edits = BTreeMap::new();
+  let mut affected = Vec::new();
+  for (old, new) in &moves {
+    // Links by title or id survive a move, only links by path need the new one
+    let retarget = |target: &str| (target == old).then(|| new.clone());
+    for (linking, _) in links_to(old)? {
+      let note = edited_note(&mut edits, &linking)?;
+      let count = rewrite_note_links(note, &retarget);
+      add_affected(&mut affected, &linking, note, count);
+    }
+  }
+
+  Ok(Plan {
+    refactor: Refactor::Move {
+      source: source.to_string(),
+      destination: destination.to_string(),
+    },
+    moves,
+    edits,
+    affected,
+  })
+}
+
+fn restore(originals: &[OriginalFile]) {
+  for original in originals {
+    if let Err(e) = fs::write(&original.path, &original.content) {
+      eprintln!("Failed to restore {}: {}", original.path, e);
+    }
+  }
+}
+
+// Write every edit, then move the files. Edits are staged in temporary files first so a failure
+// leaves either all notes updated or none.
+fn apply(plan: &Plan) -> Result<RefactorRecord, String> {
+  let mut originals = Vec::new();
+  let mut staged = Vec::new();
+  for (path, note) in &plan.edits {
+    let content =
+      serde_json::to_string_pretty(note).map_err(|e| format!("Failed to serialize note: {}", e))?;
+    originals.push(OriginalFile {
+      path: path.clone(),
+      content: read_file(path)?,
+      written_hash: content_hash(&content),
+    });
+    let tmp_path = format!("{}.refactor-tmp", path);
+    if let Err(e) = fs::write(&tmp_path, content) {
+      for (tmp, _) in &staged {
+        let _ = fs::remove_file(tmp);
+      }
+      return Err(format!("Failed to write {}: {}", tmp_path, e));
+    }
+    staged.push((tmp_path, path.clone()));
+  }
+
+  for (index, (tmp_path, path)) in staged.iter().enumerate() {
+    if let Err(e) = fs::rename(tmp_path, path) {
+      restore(&originals[..index]);
+      for (tmp, _) in &staged[index..] {
+        let _ = fs::remove_file(tmp);
+      }
+      return Err(format!("Failed to update {}: {}", path, e));
+    }
+  }
+
+  if let Refactor::Move {
+    source,
+    destination,
+  } = &plan.refactor
+  {
+    if let Some(parent) = Path::new(destination).parent() {
+      if let Err(e) = fs::create_dir_all(parent) {
+        restore(&originals);
+        return Err(format!("Failed to create directory: {}", e));
+      }
+    }
+    if let Err(e) = fs::rename(source, destination) {
+      restore(&originals);
+      return Err(format!(
+        "Failed to rename {} to {}: {}",
+        source, destination, e
+      ));
+    }
+  }
+
+  Ok(RefactorRecord {
+    id: Uuid::new_v4().to_string(),
+    refactor: plan.refactor.clone(),
+    applied_at: Utc::now(),
+    originals,
+    moves: plan.moves.clone(),
+  })
+}
+
+// Bring the link index and embeddings in line with notes that were edited or moved
+async fn refresh_indexes(edited: &[String], moves: &[(String, String)]) {
+  let mut paths: Vec<&String> = edited.iter().collect();
+  paths.extend(moves.iter().map(|(_, new)| new));
+  paths.sort();
+  paths.dedup();
+  for path in paths {
+    if !Path::new(path).exists() {
+      continue;
+    }
+    // Save hooks keep the link and search indexes current
+    notify_note_saved(path);
+    if let Err(e) = index_note_file(Path::new(path)).await {
+      eprintln!("Failed to index {}: {}", path, e);
+    }
+  }
+}
+
+// Path of `path` after `moves` were applied
+fn moved_path(path: &str, moves: &[(String, String)]) -> String {
+  moves
+    .iter()
+    .find(|(old, _)| old == path)
+    .map(|(_, new)| new.clone())
+    .unwrap_or_else(|| path.to_string())
+}
+
+async fn run(plan: Plan) -> Result<RefactorResult, String> {
+  let record = apply(&plan)?;
+  let mut history = load_history()?;
+  history.push(record.clone());
+  if history.len() > MAX_HISTORY {
+    history.remove(0);
+  }
+  save_history(&history)?;
+
+  let edited: Vec<String> = plan
+    .edits
+    .keys()
+    .map(|path| moved_path(path, &plan.moves))
+    .collect();
+  refresh_indexes(&edited, &plan.moves).await;
+
+  let affected = plan
+    .affected
+    .into_iter()
+    .map(|mut note| {
+      note.path = moved_path(&note.path, &plan.moves);
+      note
+    })
+    .collect();
+  Ok(RefactorResult {
+    id: record.id,
+    refactor: record.refactor,
+    affected,
+  })
+}
+
+// Notes whose links would be rewritten by retitling the note `id`
+#[tauri::command]
+pub fn preview_rename_note(id: String, new_title: String) -> Result<RefactorPreview, String> {
+  let plan = plan_rename(&note_path(&id)?, &new_title)?;
+  Ok(RefactorPreview {
+    refactor: plan.refactor,
+    affected: plan.affected,
+  })
+}
+
+// Retitle the note `id` and rewrite every link that refers to it by title
+#[tauri::command]
+pub async fn rename_note(id: String, new_title: String) -> Result<RefactorResult, String> {
+  run(plan_rename(&note_path(&id)?, &new_title)?).await
+}
+
+// Rewrite every link to the note at `source` into a link to the note `target_id`, before the
+// source is merged into it and deleted. Returns the notes whose links were rewritten.
+pub async fn retarget_links(source: &str, target_id: &str) -> Result<Vec<AffectedNote>, String> {
+  let mut edits = BTreeMap::new();
+  let mut affected = Vec::new();
+  for (linking, links) in links_to(source)? {
+    // Whatever the links were written as, they resolved to the source
+    let targets: HashSet<String> = links.into_iter().map(|link| link.target).collect();
+    let retarget = |target: &str| targets.contains(target).then(|| target_id.to_string());
+    let note = edited_note(&mut edits, &linking)?;
+    let count = rewrite_note_links(note, &retarget);
+    add_affected(&mut affected, &linking, note, count);
+  }
+  let plan = Plan {
+    refactor: Refactor::Merge {
+      source: source.to_string(),
+      target: target_id.to_string(),
+    },
+    moves: Vec::new(),
+    edits,
+    affected,
+  };
+  apply(&plan)?;
+  let edited: Vec<String> = plan.edits.into_keys().collect();
+  refresh_indexes(&edited, &[]).await;
+  Ok(plan.affected)
+}
+
+// A moved directory is given by path, a moved note by id or path
+fn move_source(source: &str) -> Result<String, String> {
+  if Path::new(source).is_dir() {
+    Ok(source.to_string())
+  } else {
+    note_path(source)
+  }
+}
+
+// Notes whose links would be rewritten by moving a note or directory of notes
+#[tauri::command]
+pub fn preview_move_note(source: String, destination: String) -> Result<RefactorPreview, String> {
+  let plan = plan_move(&move_source(&source)?, &destination)?;
+  Ok(RefactorPreview {
+    refactor: plan.refactor,
+    affected: plan.affected,
+  })
+}
+
+// Move a note or directory of notes and rewrite every link that refers to them by path
+#[tauri::command]
+pub async fn move_note(source: String, destination: String) -> Result<RefactorResult, String> {
+  run(plan_move(&move_source(&source)?, &destination)?).await
+}
+
+// Revert the most recent rename or move, restoring every note it edited. Refuses when one of
+// those notes was edited since, as restoring it would drop the later changes.
+// Returns the refactor that was undone, or None if there is nothing to undo.
+#[tauri::command]
+pub async fn undo_refactor() -> Result<Option<Refactor>, String> {
+  let mut history = load_history()?;
+  let Some(record) = history.pop() else {
+    return Ok(None);
+  };
+  for original in &record.originals {
+    let path = moved_path(&original.path, &record.moves);
+    let unchanged = read_file(&path)
+      .map(|content| content_hash(&content) == original.written_hash)
+      .unwrap_or(false);
+    if !unchanged {
+      return Err(format!(
+        "Cannot undo, {} was edited after the refactor",
+        path
+      ));
+    }
+  }
+
+  let mut moves = Vec::new();
+  if let Refactor::Move {
+    source,
+    destination,
+  } = &record.refactor
+  {
+    if Path::new(source).exists() {
+      return Err(format!("Cannot undo move, {} exists again", source));
+    }
+    fs::rename(destination, source)
+      .map_err(|e| format!("Failed to move {} back to {}: {}", destination, source, e))?;
+    moves = record
+      .moves
+      .iter()
+      .map(|(old, new)| (new.clone(), old.clone()))
+      .collect();
+  }
+  for original in &record.originals {
+    fs::write(&original.path, &original.content)
+      .map_err(|e| format!("Failed to restore {}: {}", original.path, e))?;
+  }
+  save_history(&history)?;
+
+  let restored: Vec<String> = record
+    .originals
+    .iter()
+    .map(|original| original.path.clone())
+    .collect();
+  refresh_indexes(&restored, &moves).await;
+  Ok(Some(record.refactor))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn note(note_type: &str, contents: &[&str]) -> Value {
+    json!({
+      "id": "n1",
+      "title": "Note",
+      "metadata": { "note_type": note_type, "tags": [] },
+      "pages": contents
+        .iter()
+        .enumerate()
+        .map(|(index, content)| json!({ "id": format!("p{}", index), "content": content }))
+        .collect::<Vec<_>>(),
+    })
+  }
+
+  fn rename_old(target: &str) -> Option<String> {
+    (target.to_lowercase() == "old").then(|| "New".to_string())
+  }
+
+  #[test]
+  fn rewrites_links_in_notebook_pages() {
+    let mut note = note("notebook", &["<p>see [[Old|here]] and [[Other]]</p>"]);
+    assert_eq!(rewrite_note_links(&mut note, &rename_old), 1);
+    assert_eq!(
+      note["pages"][0]["content"],
+      "<p>see [[New|here]] and [[Other]]</p>"
+    );
+  }
+
+  #[test]
+  fn rewrites_links_in_free_note_text_but_not_strokes() {
+    let mut note = note("freenote", &["<p>see [[old]]</p>", "[[1,2]]"]);
+    assert_eq!(rewrite_note_links(&mut note, &rename_old), 1);
+    assert_eq!(note["pages"][0]["content"], "<p>see [[New]]</p>");
+    // Strokes are never taken for links, whatever they look like
+    let rename_any = |_: &str| Some("New".to_string());
+    assert_eq!(rewrite_note_links(&mut note, &rename_any), 1);
+    assert_eq!(note["pages"][1]["content"], "[[1,2]]");
+  }
+}
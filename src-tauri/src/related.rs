@@ -0,0 +1,146 @@
+use crate::embedder::embed_chunks;
+use crate::fs::{note_id, note_path, strip_html};
+use crate::qdrant::{chunk_text, NOTES_COLLECTION};
+use crate::vector_store::{get_vector_store, ScoredChunk};
+use serde::{Deserialize, Serialize};
+
+// Passages shown per related note
+const PASSAGES_PER_NOTE: usize = 3;
+
+// How chunk vectors are combined into a single query vector
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Pooling {
+  #[default]
+  Mean,
+  Max,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RelatedPassage {
+  pub page_id: String,
+  pub text: String,
+  pub score: f32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RelatedNote {
+  pub id: String,
+  pub path: String,
+  pub title: String,
+  pub score: f32,
+  pub passages: Vec<RelatedPassage>,
+}
+
+// Combine vectors of equal length into one, or None if there are none
+pub fn pool_vectors(vectors: &[Vec<f32>], pooling: Pooling) -> Option<Vec<f32>> {
+  let first = vectors.first()?;
+  let mut pooled = first.clone();
+  for vector in &vectors[1..] {
+    for (acc, value) in pooled.iter_mut().zip(vector) {
+      match pooling {
+        Pooling::Mean => *acc += value,
+        Pooling::Max => *acc = acc.max(*value),
+      }
+    }
+  }
+  if let Pooling::Mean = pooling {
+    let count = vectors.len() as f32;
+    pooled.iter_mut().for_each(|value| *value /= count);
+  }
+  Some(pooled)
+}
+
+// Note-level vector pooled from the stored chunks of the note `note_id`,
+// limited to one page when `page_id` is given
+pub async fn note_vector(
+  note_id: &str,
+  page_id: Option<&str>,
+  pooling: Pooling,
+) -> Result<Option<Vec<f32>>, String> {
+  let points = get_vector_store()?
+    .get_by_note(NOTES_COLLECTION, note_id)
+    .await?;
+  let vectors: Vec<Vec<f32>> = points
+    .into_iter()
+    .filter(|point| page_id.is_none_or(|id| point.payload.page_id == id))
+    .map(|point| point.vector)
+    .collect();
+  Ok(pool_vectors(&vectors, pooling))
+}
+
+// Group chunk hits by note, keeping the store's best-first order
+pub fn group_by_note(hits: Vec<ScoredChunk>, limit: usize) -> Vec<RelatedNote> {
+  let mut notes: Vec<RelatedNote> = Vec::new();
+  for hit in hits {
+    let passage = RelatedPassage {
+      page_id: hit.payload.page_id,
+      text: hit.payload.text,
+      score: hit.score,
+    };
+    match notes.iter_mut().find(|note| note.id == hit.payload.note_id) {
+      Some(note) => {
+        if note.passages.len() < PASSAGES_PER_NOTE {
+          note.passages.push(passage);
+        }
+      }
+      None => {
+        if notes.len() == limit {
+          continue;
+        }
+        notes.push(RelatedNote {
+          id: hit.payload.note_id,
+          path: hit.payload.path,
+          title: hit.payload.title,
+          score: hit.score,
+          passages: vec![passage],
+        })
+      }
+    }
+  }
+  notes
+}
+
+// Notes related to the note `id`, ranked by similarity with their best matching passages.
+// When `content` is given (e.g. the unsaved page being edited) it is embedded and used instead
+// of the stored vectors; otherwise `page_id` restricts the query to one stored page.
+#[tauri::command]
+pub async fn suggest_connections(
+  id: String,
+  page_id: Option<String>,
+  content: Option<String>,
+  pooling: Option<Pooling>,
+  limit: Option<usize>,
+) -> Result<Vec<RelatedNote>, String> {
+  let note_id = note_id(&note_path(&id)?)?;
+  let pooling = pooling.unwrap_or_default();
+  let limit = limit.unwrap_or(10);
+
+  let query = match content {
+    Some(content) => {
+      let chunks = chunk_text(&strip_html(&content));
+      if chunks.is_empty() {
+        return Ok(Vec::new());
+      }
+      let vectors = embed_chunks(chunks)
+        .await
+        .map_err(|e| format!("Failed to embed content: {}", e))?;
+      pool_vectors(&vectors, pooling)
+    }
+    None => note_vector(&note_id, page_id.as_deref(), pooling).await?,
+  };
+  let Some(query) = query else {
+    return Ok(Vec::new());
+  };
+
+  // Several chunks usually come from the same note, so over-fetch before grouping
+  let hits = get_vector_store()?
+    .search(
+      NOTES_COLLECTION,
+      &query,
+      limit * PASSAGES_PER_NOTE * 2,
+      Some(&note_id),
+    )
+    .await?;
+  Ok(group_by_note(hits, limit))
+}
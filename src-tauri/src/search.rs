@@ -1,11 +1,226 @@
-use tantivy::{Document, Index};
+use crate::fs::{collect_note_paths, get_app_data_dir, note_page_texts, read_note};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use tantivy::collector::TopDocs;
+use tantivy::directory::MmapDirectory;
+use tantivy::query::QueryParser;
+use tantivy::schema::{Field, Schema, Value, STORED, STRING, TEXT};
+use tantivy::snippet::SnippetGenerator;
+use tantivy::{doc, Index, IndexReader, IndexWriter, TantivyDocument, Term};
 
+const INDEX_DIR: &str = "search_index";
+// Memory the index writer may buffer before flushing a segment
+const WRITER_MEMORY: usize = 50_000_000;
+
+struct Fields {
+  id: Field,
+  path: Field,
+  title: Field,
+  text: Field,
+}
+
+// Full-text index of note titles and page text, including handwriting transcriptions
+struct SearchIndex {
+  index: Index,
+  reader: IndexReader,
+  // Tantivy allows a single writer per index
+  writer: Mutex<IndexWriter>,
+  fields: Fields,
+}
+
+static SEARCH_INDEX: RwLock<Option<Arc<SearchIndex>>> = RwLock::new(None);
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SearchHit {
+  pub id: String,
+  pub path: String,
+  pub title: String,
+  // Matching passage with the matched words wrapped in <b> tags
+  pub snippet: String,
+  pub score: f32,
+}
+
+fn get_search_index() -> Result<Arc<SearchIndex>, String> {
+  if let Some(index) = SEARCH_INDEX.read().unwrap().as_ref() {
+    return Ok(index.clone());
+  }
+  // Held while opening, since a second writer on the same index would fail
+  let mut slot = SEARCH_INDEX.write().unwrap();
+  if let Some(index) = slot.as_ref() {
+    return Ok(index.clone());
+  }
+
+  let mut builder = Schema::builder();
+  let fields = Fields {
+    id: builder.add_text_field("id", STRING | STORED),
+    path: builder.add_text_field("path", STRING | STORED),
+    title: builder.add_text_field("title", TEXT | STORED),
+    text: builder.add_text_field("text", TEXT | STORED),
+  };
+  let schema = builder.build();
+
+  let dir = get_app_data_dir()?.join(INDEX_DIR);
+  std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create search index: {}", e))?;
+  let directory =
+    MmapDirectory::open(&dir).map_err(|e| format!("Failed to open search index: {}", e))?;
+  let index = Index::open_or_create(directory, schema)
+    .map_err(|e| format!("Failed to open search index: {}", e))?;
+  let writer = index
+    .writer(WRITER_MEMORY)
+    .map_err(|e| format!("Failed to open search index writer: {}", e))?;
+  let reader = index
+    .reader()
+    .map_err(|e| format!("Failed to open search index reader: {}", e))?;
+
+  let search_index = Arc::new(SearchIndex {
+    index,
+    reader,
+    writer: Mutex::new(writer),
+    fields,
+  });
+  *slot = Some(search_index.clone());
+  Ok(search_index)
+}
+
+// Queue the note at `path` in place of its previous version, without committing
+fn add_note(
+  search_index: &SearchIndex,
+  writer: &mut IndexWriter,
+  path: &str,
+) -> Result<(), String> {
+  let note = read_note(path)?;
+  let Some(id) = note["id"].as_str() else {
+    return Ok(());
+  };
+  let text = note_page_texts(&note)
+    .into_iter()
+    .map(|(_, text)| text)
+    .collect::<Vec<_>>()
+    .join("\n");
+  let fields = &search_index.fields;
+  writer.delete_term(Term::from_field_text(fields.id, id));
+  writer
+    .add_document(doc!(
+      fields.id => id,
+      fields.path => path,
+      fields.title => note["title"].as_str().unwrap_or(""),
+      fields.text => text,
+    ))
+    .map_err(|e| format!("Failed to index {}: {}", path, e))?;
+  Ok(())
+}
+
+fn commit(search_index: &SearchIndex, writer: &mut IndexWriter) -> Result<(), String> {
+  writer
+    .commit()
+    .map_err(|e| format!("Failed to commit search index: {}", e))?;
+  search_index
+    .reader
+    .reload()
+    .map_err(|e| format!("Failed to reload search index: {}", e))
+}
+
+// Bring one note up to date in the index
+pub fn index_note(path: &str) -> Result<(), String> {
+  let search_index = get_search_index()?;
+  let mut writer = search_index.writer.lock().unwrap();
+  add_note(&search_index, &mut writer, path)?;
+  commit(&search_index, &mut writer)
+}
+
+// Save hook keeping the index current as notes and their transcriptions are saved
+pub fn update_note_index(path: &str) {
+  if let Err(e) = index_note(path) {
+    eprintln!("Failed to update the search index for {}: {}", path, e);
+  }
+}
+
+// Drop a deleted note from the index
+pub fn remove_note_index(id: &str) -> Result<(), String> {
+  let search_index = get_search_index()?;
+  let mut writer = search_index.writer.lock().unwrap();
+  writer.delete_term(Term::from_field_text(search_index.fields.id, id));
+  commit(&search_index, &mut writer)
+}
+
+// Rebuild the search index from every note in the vault. Returns the number of notes indexed.
 #[tauri::command]
-pub fn index_notes(app_handle: tauri::AppHandle) -> Result<(), String> {
-  // Build search index
+pub fn index_notes() -> Result<usize, String> {
+  let search_index = get_search_index()?;
+  let mut writer = search_index.writer.lock().unwrap();
+  writer
+    .delete_all_documents()
+    .map_err(|e| format!("Failed to clear search index: {}", e))?;
+  let mut count = 0;
+  for path in collect_note_paths()? {
+    let Some(path_str) = path.to_str() else {
+      continue;
+    };
+    match add_note(&search_index, &mut writer, path_str) {
+      Ok(()) => count += 1,
+      Err(e) => eprintln!("{}", e),
+    }
+  }
+  commit(&search_index, &mut writer)?;
+  Ok(count)
 }
 
+// Notes whose title or text match `query`, best matches first
 #[tauri::command]
-pub fn search_notes(query: &str) -> Result<Vec<String>, String> {
-  // Search indexed notes
+pub fn search_notes(query: &str, limit: Option<usize>) -> Result<Vec<SearchHit>, String> {
+  let search_index = get_search_index()?;
+  let fields = &search_index.fields;
+  let mut parser = QueryParser::for_index(&search_index.index, vec![fields.title, fields.text]);
+  parser.set_field_boost(fields.title, 2.0);
+  // Stray quotes or operators in typed queries shouldn't fail the search
+  let (query, _) = parser.parse_query_lenient(query);
+
+  // Tantivy panics on a limit of 0
+  let limit = limit.unwrap_or(20).max(1);
+  let searcher = search_index.reader.searcher();
+  let snippets = SnippetGenerator::create(&searcher, &*query, fields.text)
+    .map_err(|e| format!("Search failed: {}", e))?;
+
+  let mut hits = Vec::new();
+  let mut offset = 0;
+  // Notes deleted outside the app since they were indexed are skipped, so read further
+  // results until `limit` notes are found
+  while hits.len() < limit {
+    let top_docs = searcher
+      .search(&query, &TopDocs::with_limit(limit).and_offset(offset))
+      .map_err(|e| format!("Search failed: {}", e))?;
+    let fetched = top_docs.len();
+    for (score, address) in top_docs {
+      let document: TantivyDocument = searcher
+        .doc(address)
+        .map_err(|e| format!("Failed to read search result: {}", e))?;
+      let field = |field: Field| {
+        document
+          .get_first(field)
+          .and_then(|value| value.as_str())
+          .unwrap_or("")
+          .to_string()
+      };
+      let path = field(fields.path);
+      if !Path::new(&path).is_file() {
+        continue;
+      }
+      hits.push(SearchHit {
+        id: field(fields.id),
+        path,
+        title: field(fields.title),
+        snippet: snippets.snippet_from_doc(&document).to_html(),
+        score,
+      });
+      if hits.len() == limit {
+        break;
+      }
+    }
+    if fetched < limit {
+      break;
+    }
+    offset += fetched;
+  }
+  Ok(hits)
 }
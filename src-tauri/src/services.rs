@@ -0,0 +1,592 @@
+use crate::config::{
+  get_config, ContainerRuntimeKind, EmbeddingProvider, UserConfig, VectorStoreKind,
+};
+use crate::docker::{select_runtime, ContainerRuntime, ContainerSpec};
+use crate::embedder::reset_embedder;
+use crate::fs::get_app_data_dir;
+use crate::ollama::{ensure_ollama_model, ollama_healthy, report_ai_status, reset_ollama_client};
+use crate::qdrant::{qdrant_healthy, reset_qdrant_client};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, Ipv6Addr, TcpListener};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+// How long a started container may take to report ready
+const READY_TIMEOUT: Duration = Duration::from_secs(60);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// How often a ready service is checked
+const MONITOR_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// Consecutive failed starts before the supervisor gives up
+const MAX_RESTARTS: u32 = 5;
+// Qdrant server version matching the client library
+const QDRANT_IMAGE: &str = "qdrant/qdrant:v1.16.0";
+pub const QDRANT_SERVICE: &str = "qdrant";
+// Container ports of the Qdrant REST and gRPC APIs, also the preferred host ports
+pub const QDRANT_HTTP_PORT: u16 = 6333;
+pub const QDRANT_GRPC_PORT: u16 = 6334;
+const OLLAMA_IMAGE: &str = "ollama/ollama:0.12.6";
+pub const OLLAMA_SERVICE: &str = "ollama";
+pub const OLLAMA_PORT: u16 = 11434;
+// Host ports given to each service last time, reused so containers keep their spec
+const PORTS_FILE: &str = "service_ports.json";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceState {
+  Starting,
+  Ready,
+  // Downloading the models the service needs, which can take a while on first start
+  Pulling,
+  Restarting,
+  // Gave up after repeated failures, see `error`
+  Failed,
+  // An external service is not answering; it keeps being checked
+  Unavailable,
+  Stopped,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ServiceStatus {
+  pub name: String,
+  // Runtime and container running the service, None for external services
+  pub runtime: Option<String>,
+  pub container: Option<String>,
+  // Host port published for each container port
+  pub ports: BTreeMap<u16, u16>,
+  pub state: ServiceState,
+  pub restarts: u32,
+  pub error: Option<String>,
+  pub updated_at: DateTime<Utc>,
+  // Whether a supervisor task is watching this service
+  #[serde(skip)]
+  supervised: bool,
+  // Whether this process started the container, so quitting stops it. A container that was
+  // already running, e.g. for another instance of the app, is left alone.
+  #[serde(skip)]
+  started: bool,
+}
+
+// How the supervisor decides a service can take requests
+#[derive(Debug, Clone)]
+pub enum HealthCheck {
+  // Path answering with a success status, on the host port published for `port`
+  Http { port: u16, path: String },
+  // Health check through the Qdrant client
+  Qdrant,
+  // Version request through the Ollama client
+  Ollama,
+}
+
+// A service the app depends on, run in a container unless external services are configured
+#[derive(Debug, Clone)]
+pub struct ServiceSpec {
+  pub name: String,
+  pub container: ContainerSpec,
+  pub health: HealthCheck,
+  // Ollama models pulled once the service answers
+  pub models: Vec<String>,
+}
+
+static SERVICES: Mutex<BTreeMap<String, ServiceStatus>> = Mutex::new(BTreeMap::new());
+static RUNTIME: RwLock<Option<Arc<dyn ContainerRuntime>>> = RwLock::new(None);
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+// Services needed by `config`
+fn managed_services(config: &UserConfig) -> Result<Vec<ServiceSpec>, String> {
+  let data_dir = get_app_data_dir()?;
+  let external = config.container_runtime == ContainerRuntimeKind::External;
+  let mut services = Vec::new();
+  if config.vector_store == VectorStoreKind::Qdrant {
+    services.push(ServiceSpec {
+      name: QDRANT_SERVICE.to_string(),
+      container: ContainerSpec {
+        image: QDRANT_IMAGE.to_string(),
+        name: "jot_qdrant_instance".to_string(),
+        ports: vec![
+          (QDRANT_HTTP_PORT.to_string(), QDRANT_HTTP_PORT.to_string()),
+          (QDRANT_GRPC_PORT.to_string(), QDRANT_GRPC_PORT.to_string()),
+        ],
+        // Keep vectors outside the container so removing it loses nothing
+        volumes: vec![(data_dir.join("qdrant"), "/qdrant/storage".to_string())],
+        env: vec![("QDRANT__TELEMETRY_DISABLED".to_string(), "true".to_string())],
+      },
+      // Only the configured gRPC URL is known for an external server
+      health: if external {
+        HealthCheck::Qdrant
+      } else {
+        HealthCheck::Http {
+          port: QDRANT_HTTP_PORT,
+          path: "/readyz".to_string(),
+        }
+      },
+      models: Vec::new(),
+    });
+  }
+  if config.manage_ollama || external {
+    services.push(ServiceSpec {
+      name: OLLAMA_SERVICE.to_string(),
+      container: ContainerSpec {
+        image: OLLAMA_IMAGE.to_string(),
+        name: "jot_ollama_instance".to_string(),
+        ports: vec![(OLLAMA_PORT.to_string(), OLLAMA_PORT.to_string())],
+        // Models are several gigabytes, so they live outside the container
+        volumes: vec![(data_dir.join("ollama"), "/root/.ollama".to_string())],
+        env: Vec::new(),
+      },
+      health: if external {
+        HealthCheck::Ollama
+      } else {
+        HealthCheck::Http {
+          port: OLLAMA_PORT,
+          path: "/api/version".to_string(),
+        }
+      },
+      // A fresh container has no models, and nothing can be indexed without the embedding model
+      models: if !external && config.embedding.provider == EmbeddingProvider::Ollama {
+        vec![config.embedding.model.clone()]
+      } else {
+        Vec::new()
+      },
+    });
+  }
+  Ok(services)
+}
+
+// Record a state change and tell the UI about it
+fn update_status(
+  app_handle: &AppHandle,
+  spec: &ServiceSpec,
+  runtime: Option<&Arc<dyn ContainerRuntime>>,
+  update: impl FnOnce(&mut ServiceStatus),
+) {
+  let status = {
+    let mut services = SERVICES.lock().unwrap();
+    let status = services
+      .entry(spec.name.clone())
+      .or_insert_with(|| ServiceStatus {
+        name: spec.name.clone(),
+        runtime: None,
+        container: None,
+        ports: BTreeMap::new(),
+        state: ServiceState::Starting,
+        restarts: 0,
+        error: None,
+        updated_at: Utc::now(),
+        supervised: false,
+        started: false,
+      });
+    status.runtime = runtime.map(|runtime| runtime.binary().to_string());
+    status.container = runtime.map(|_| spec.container.name.clone());
+    update(status);
+    status.updated_at = Utc::now();
+    status.clone()
+  };
+  let _ = app_handle.emit("service-status", status);
+}
+
+// Host port published for `container_port` of a running service, None for external services
+// or before the service's container was started
+pub fn service_port(service: &str, container_port: u16) -> Option<u16> {
+  SERVICES
+    .lock()
+    .unwrap()
+    .get(service)
+    .and_then(|status| status.ports.get(&container_port).copied())
+}
+
+async fn healthy(client: &reqwest::Client, spec: &ServiceSpec) -> bool {
+  match &spec.health {
+    HealthCheck::Http { port, path } => {
+      let Some(host_port) = service_port(&spec.name, *port) else {
+        return false;
+      };
+      let url = format!("http://localhost:{}{}", host_port, path);
+      match client.get(url).send().await {
+        Ok(response) => response.status().is_success(),
+        Err(_) => false,
+      }
+    }
+    HealthCheck::Qdrant => qdrant_healthy().await,
+    HealthCheck::Ollama => ollama_healthy().await,
+  }
+}
+
+// Start the container, if there is one, and wait until the service answers health checks
+async fn start_and_wait(
+  client: &reqwest::Client,
+  spec: &ServiceSpec,
+  runtime: Option<&Arc<dyn ContainerRuntime>>,
+) -> Result<(), String> {
+  if let Some(runtime) = runtime {
+    let (runtime, service) = (runtime.clone(), spec.clone());
+    let started = tokio::task::spawn_blocking(move || {
+      let container = allocate_ports(runtime.as_ref(), &service)?;
+      let running = runtime.container_running(&container.name);
+      runtime.ensure_container(&container)?;
+      record_ports(&service.name, &container)?;
+      Ok::<_, String>(!running)
+    })
+    .await
+    .map_err(|e| format!("Failed to start container: {}", e))??;
+    if started {
+      if let Some(status) = SERVICES.lock().unwrap().get_mut(&spec.name) {
+        status.started = true;
+      }
+    }
+  }
+
+  let deadline = tokio::time::Instant::now() + READY_TIMEOUT;
+  while tokio::time::Instant::now() < deadline {
+    if healthy(client, spec).await {
+      return Ok(());
+    }
+    tokio::time::sleep(POLL_INTERVAL).await;
+  }
+  Err(format!(
+    "{} did not become ready within {} seconds",
+    spec.name,
+    READY_TIMEOUT.as_secs()
+  ))
+}
+
+fn load_ports() -> Result<BTreeMap<String, BTreeMap<u16, u16>>, String> {
+  let path = get_app_data_dir()?.join(PORTS_FILE);
+  if !path.exists() {
+    return Ok(BTreeMap::new());
+  }
+  let content = std::fs::read_to_string(&path)
+    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+  serde_json::from_str(&content).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+}
+
+fn save_ports(ports: &BTreeMap<String, BTreeMap<u16, u16>>) -> Result<(), String> {
+  let path = get_app_data_dir()?.join(PORTS_FILE);
+  let content = serde_json::to_string_pretty(ports)
+    .map_err(|e| format!("Failed to serialize service ports: {}", e))?;
+  std::fs::write(&path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+// (host port, container port) pairs of a container spec as numbers
+fn parse_ports(container: &ContainerSpec) -> Result<Vec<(u16, u16)>, String> {
+  container
+    .ports
+    .iter()
+    .map(|(host, inner)| match (host.parse(), inner.parse()) {
+      (Ok(host), Ok(inner)) => Ok((host, inner)),
+      _ => Err(format!("Invalid port mapping {}:{}", host, inner)),
+    })
+    .collect()
+}
+
+// Whether nothing is listening on `port` on any interface. Linux lets a wildcard bind succeed
+// next to a listener on loopback or IPv6, so each of those is tried as well.
+fn port_free(port: u16) -> bool {
+  let ipv4_free = [Ipv4Addr::UNSPECIFIED, Ipv4Addr::LOCALHOST]
+    .into_iter()
+    .all(|address| TcpListener::bind((address, port)).is_ok());
+  // Hosts without IPv6 have nothing listening there
+  ipv4_free
+    && match TcpListener::bind((Ipv6Addr::UNSPECIFIED, port)) {
+      Ok(_) => true,
+      Err(e) => e.kind() != ErrorKind::AddrInUse,
+    }
+}
+
+// Pick host ports for the service's container: the ports it had last time, or the spec's
+// preferred ports the first time. A running container from the same spec already holds them;
+// otherwise any port another process took in the meantime is swapped for a free one.
+fn allocate_ports(
+  runtime: &dyn ContainerRuntime,
+  spec: &ServiceSpec,
+) -> Result<ContainerSpec, String> {
+  let recorded = load_ports()?.remove(&spec.name).unwrap_or_default();
+  let mut container = spec.container.clone();
+  for (host, inner) in container.ports.iter_mut() {
+    if let Some(port) = inner.parse().ok().and_then(|inner| recorded.get(&inner)) {
+      *host = port.to_string();
+    }
+  }
+
+  let running = runtime.container_running(&container.name);
+  if running && runtime.container_current(&container) {
+    return Ok(container);
+  }
+  // An outdated container of ours would otherwise look like a conflict on its own ports
+  if running {
+    runtime.stop_container(&container.name)?;
+  }
+
+  // Keep the allocated listeners open until every port is picked so none is handed out twice
+  let mut reserved = Vec::new();
+  for (index, (host, inner)) in parse_ports(&container)?.into_iter().enumerate() {
+    if port_free(host) {
+      continue;
+    }
+    let listener = TcpListener::bind(("0.0.0.0", 0))
+      .map_err(|e| format!("Failed to find a free port: {}", e))?;
+    let port = listener
+      .local_addr()
+      .map_err(|e| format!("Failed to find a free port: {}", e))?
+      .port();
+    eprintln!(
+      "Port {} for {} is in use, publishing container port {} on {} instead",
+      host, spec.name, inner, port
+    );
+    container.ports[index].0 = port.to_string();
+    reserved.push(listener);
+  }
+  Ok(container)
+}
+
+// Make the container's published ports the service's current ports and remember them for the
+// next start. Clients connected through the old ports are reset.
+fn record_ports(service: &str, container: &ContainerSpec) -> Result<(), String> {
+  let ports: BTreeMap<u16, u16> = parse_ports(container)?
+    .into_iter()
+    .map(|(host, inner)| (inner, host))
+    .collect();
+  {
+    let mut services = SERVICES.lock().unwrap();
+    let Some(status) = services.get_mut(service) else {
+      return Ok(());
+    };
+    if status.ports == ports {
+      return Ok(());
+    }
+    status.ports = ports.clone();
+  }
+  reset_qdrant_client();
+  reset_ollama_client();
+  // The Ollama embedder holds its own client
+  reset_embedder();
+
+  let mut recorded = load_ports()?;
+  recorded.insert(service.to_string(), ports);
+  save_ports(&recorded)
+}
+
+// Pull the models the service needs that aren't installed yet
+async fn pull_models(
+  app_handle: &AppHandle,
+  spec: &ServiceSpec,
+  runtime: Option<&Arc<dyn ContainerRuntime>>,
+) -> Result<(), String> {
+  for model in &spec.models {
+    update_status(app_handle, spec, runtime, |status| {
+      status.state = ServiceState::Pulling
+    });
+    ensure_ollama_model(app_handle, model).await?;
+  }
+  Ok(())
+}
+
+// Watch a ready service until it stops running or stops answering, returning why
+async fn monitor(
+  client: &reqwest::Client,
+  spec: &ServiceSpec,
+  runtime: Option<&Arc<dyn ContainerRuntime>>,
+) -> Option<String> {
+  loop {
+    tokio::time::sleep(MONITOR_INTERVAL).await;
+    if SHUTTING_DOWN.load(Ordering::SeqCst) {
+      return None;
+    }
+    if let Some(runtime) = runtime {
+      let (runtime, name) = (runtime.clone(), spec.container.name.clone());
+      let running = tokio::task::spawn_blocking(move || runtime.container_running(&name))
+        .await
+        .unwrap_or(false);
+      if !running {
+        return Some(format!("Container {} stopped", spec.container.name));
+      }
+    }
+    if !healthy(client, spec).await {
+      return Some(format!("{} stopped responding", spec.name));
+    }
+  }
+}
+
+// Keep a service running: start it, wait for it to be ready, and restart it with exponential
+// backoff whenever it fails, until it fails too often in a row or the app quits. External
+// services can't be restarted, so they are only checked until they answer again.
+async fn supervise(
+  app_handle: AppHandle,
+  spec: ServiceSpec,
+  runtime: Option<Arc<dyn ContainerRuntime>>,
+) {
+  let runtime = runtime.as_ref();
+  let client = reqwest::Client::builder()
+    .timeout(Duration::from_secs(2))
+    .build()
+    .unwrap_or_default();
+  let mut failures = 0;
+  loop {
+    if SHUTTING_DOWN.load(Ordering::SeqCst) {
+      break;
+    }
+    update_status(&app_handle, &spec, runtime, |status| {
+      status.state = ServiceState::Starting;
+      status.supervised = true;
+    });
+
+    let error = match start_and_wait(&client, &spec, runtime).await {
+      Ok(()) => {
+        failures = 0;
+        // The service works without the models, so a failed pull is reported, not restarted
+        let pull_error = pull_models(&app_handle, &spec, runtime).await.err();
+        if let Some(e) = &pull_error {
+          eprintln!("Failed to pull models for {}: {}", spec.name, e);
+        }
+        update_status(&app_handle, &spec, runtime, |status| {
+          status.state = ServiceState::Ready;
+          status.error = pull_error;
+        });
+        report_ai_status(app_handle.clone());
+        let error = monitor(&client, &spec, runtime).await;
+        report_ai_status(app_handle.clone());
+        match error {
+          Some(error) => error,
+          None => break,
+        }
+      }
+      Err(e) => e,
+    };
+    if SHUTTING_DOWN.load(Ordering::SeqCst) {
+      break;
+    }
+    eprintln!("Service {} failed: {}", spec.name, error);
+
+    if runtime.is_none() {
+      update_status(&app_handle, &spec, runtime, |status| {
+        status.state = ServiceState::Unavailable;
+        status.error = Some(error);
+      });
+      tokio::time::sleep(MONITOR_INTERVAL).await;
+      continue;
+    }
+
+    failures += 1;
+    if failures > MAX_RESTARTS {
+      update_status(&app_handle, &spec, runtime, |status| {
+        status.state = ServiceState::Failed;
+        status.error = Some(error);
+      });
+      break;
+    }
+    let backoff = Duration::from_secs(1 << (failures - 1)).min(MAX_BACKOFF);
+    update_status(&app_handle, &spec, runtime, |status| {
+      status.state = ServiceState::Restarting;
+      status.restarts += 1;
+      status.error = Some(error);
+    });
+    tokio::time::sleep(backoff).await;
+  }
+  update_status(&app_handle, &spec, runtime, |status| {
+    status.supervised = false
+  });
+}
+
+// Pick the container runtime for the config, which runs commands and may take a moment
+async fn detect_runtime(
+  kind: ContainerRuntimeKind,
+) -> Result<Option<Arc<dyn ContainerRuntime>>, String> {
+  let runtime = tokio::task::spawn_blocking(move || select_runtime(kind))
+    .await
+    .map_err(|e| format!("Failed to detect container runtime: {}", e))??;
+  *RUNTIME.write().unwrap() = runtime.clone();
+  Ok(runtime)
+}
+
+// Start supervising every service the config needs, in the background
+pub fn start_services(app_handle: AppHandle) {
+  tauri::async_runtime::spawn(async move {
+    let result = async {
+      let config = get_config()?;
+      let services = managed_services(&config)?;
+      Ok::<_, String>((services, detect_runtime(config.container_runtime).await))
+    }
+    .await;
+    let (services, runtime) = match result {
+      Ok(result) => result,
+      Err(e) => {
+        eprintln!("Failed to start services: {}", e);
+        return;
+      }
+    };
+
+    match runtime {
+      Ok(runtime) => {
+        for spec in services {
+          tauri::async_runtime::spawn(supervise(app_handle.clone(), spec, runtime.clone()));
+        }
+      }
+      // Without a runtime nothing can start, tell the UI why
+      Err(e) => {
+        for spec in services {
+          update_status(&app_handle, &spec, None, |status| {
+            status.state = ServiceState::Failed;
+            status.error = Some(e.clone());
+          });
+        }
+      }
+    }
+  });
+}
+
+// Stop the managed containers this process started. Called when the app quits.
+pub fn stop_services() {
+  SHUTTING_DOWN.store(true, Ordering::SeqCst);
+  let runtime = RUNTIME.read().unwrap().clone();
+  let mut services = SERVICES.lock().unwrap();
+  for status in services.values_mut() {
+    if !status.started {
+      continue;
+    }
+    if let (Some(runtime), Some(container)) = (&runtime, &status.container) {
+      if let Err(e) = runtime.stop_container(container) {
+        eprintln!("Failed to stop {}: {}", container, e);
+      }
+    }
+    status.state = ServiceState::Stopped;
+  }
+}
+
+// Current state of every managed service
+#[tauri::command]
+pub fn service_status() -> Vec<ServiceStatus> {
+  SERVICES.lock().unwrap().values().cloned().collect()
+}
+
+// Start supervising a service again, e.g. after it failed or a container runtime was installed.
+// The runtime is detected again so a changed config takes effect.
+#[tauri::command]
+pub async fn restart_service(app_handle: AppHandle, name: String) -> Result<(), String> {
+  let config = get_config()?;
+  let spec = managed_services(&config)?
+    .into_iter()
+    .find(|spec| spec.name == name)
+    .ok_or_else(|| format!("Unknown service '{}'", name))?;
+  let supervised = SERVICES
+    .lock()
+    .unwrap()
+    .get(&name)
+    .is_some_and(|status| status.supervised);
+  if supervised {
+    return Err(format!("Service '{}' is already being supervised", name));
+  }
+
+  let runtime = detect_runtime(config.container_runtime).await?;
+  update_status(&app_handle, &spec, runtime.as_ref(), |status| {
+    status.restarts = 0;
+    status.error = None;
+    status.supervised = true;
+  });
+  tauri::async_runtime::spawn(supervise(app_handle, spec, runtime));
+  Ok(())
+}
@@ -0,0 +1,172 @@
+use crate::config::get_config;
+use crate::fs::{
+  collect_note_paths, note_page_texts, note_path, parse_time, read_note, write_note,
+};
+use crate::ollama::chat_json;
+use crate::refactor::rename_note;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use ollama_rs::generation::chat::ChatMessage;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+// How often the background job looks for notes to summarize
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+// Characters of note text sent to the model
+const MAX_SUMMARY_INPUT: usize = 8000;
+
+const SUMMARY_PROMPT: &str = "You summarize notes. Reply with a JSON object with two fields: \
+\"summary\", a single paragraph summarizing the note, and \"title\", a short descriptive title \
+for it of at most eight words.";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NoteSuggestion {
+  pub summary: String,
+  pub title: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct SummaryUpdated {
+  id: String,
+  path: String,
+  summary: String,
+  suggested_title: String,
+}
+
+// Most recent page edit of a note
+fn last_edited(note: &Value) -> Option<DateTime<Utc>> {
+  note["pages"]
+    .as_array()?
+    .iter()
+    .filter_map(|page| parse_time(&page["last_modified"]))
+    .max()
+}
+
+// A note is due once it has been idle for `idle` and edited since its last summary
+fn needs_summary(note: &Value, idle: ChronoDuration, now: DateTime<Utc>) -> bool {
+  let Some(edited) = last_edited(note) else {
+    return false;
+  };
+  let summarized = parse_time(&note["metadata"]["summarized_at"]);
+  now - edited >= idle && summarized.is_none_or(|summarized| summarized < edited)
+}
+
+fn note_text(note: &Value) -> String {
+  let text = note_page_texts(note)
+    .into_iter()
+    .map(|(_, text)| text)
+    .filter(|text| !text.is_empty())
+    .collect::<Vec<_>>()
+    .join("\n\n");
+  text.chars().take(MAX_SUMMARY_INPUT).collect()
+}
+
+// Ask the chat model for a summary and title of the note `id` and store them in its metadata.
+// Returns None for notes without any text.
+pub async fn generate_suggestion(id: &str) -> Result<Option<NoteSuggestion>, String> {
+  let note = read_note(&note_path(id)?)?;
+  let text = note_text(&note);
+  if text.is_empty() {
+    return Ok(None);
+  }
+
+  let suggestion: NoteSuggestion = chat_json(vec![
+    ChatMessage::system(SUMMARY_PROMPT.to_string()),
+    ChatMessage::user(text),
+  ])
+  .await?;
+
+  // Re-read so edits and moves made while the model was generating are kept
+  let path = &note_path(id)?;
+  let mut note = read_note(path)?;
+  if let Some(metadata) = note["metadata"].as_object_mut() {
+    metadata.insert("summary".to_string(), json!(suggestion.summary));
+    metadata.insert("suggested_title".to_string(), json!(suggestion.title));
+    metadata.insert("summarized_at".to_string(), json!(Utc::now()));
+  }
+  write_note(path, &note)?;
+  Ok(Some(suggestion))
+}
+
+async fn summarize_idle_notes(app_handle: &AppHandle) -> Result<(), String> {
+  let config = get_config()?;
+  if !config.auto_summary {
+    return Ok(());
+  }
+  let idle = ChronoDuration::seconds(config.summary_idle_secs as i64);
+
+  for path in collect_note_paths()? {
+    let Some(path_str) = path.to_str() else {
+      continue;
+    };
+    let Ok(note) = read_note(path_str) else {
+      continue;
+    };
+    if !needs_summary(&note, idle, Utc::now()) {
+      continue;
+    }
+    let Some(id) = note["id"].as_str() else {
+      continue;
+    };
+    match generate_suggestion(id).await {
+      Ok(Some(suggestion)) => {
+        let _ = app_handle.emit(
+          "note-summary-updated",
+          SummaryUpdated {
+            id: id.to_string(),
+            path: path_str.to_string(),
+            summary: suggestion.summary,
+            suggested_title: suggestion.title,
+          },
+        );
+      }
+      Ok(None) => {}
+      Err(e) => eprintln!("Failed to summarize {}: {}", path_str, e),
+    }
+  }
+  Ok(())
+}
+
+// Periodically summarize notes that have gone idle, when enabled in the config
+pub fn start_summary_job(app_handle: AppHandle) {
+  tauri::async_runtime::spawn(async move {
+    loop {
+      tokio::time::sleep(CHECK_INTERVAL).await;
+      if let Err(e) = summarize_idle_notes(&app_handle).await {
+        eprintln!("Summary job failed: {}", e);
+      }
+    }
+  });
+}
+
+// Summarize the note `id` now, regardless of the idle timer
+#[tauri::command]
+pub async fn regenerate_summary(id: String) -> Result<Option<NoteSuggestion>, String> {
+  generate_suggestion(&id).await
+}
+
+// Replace the note's title with its suggested title and return the new title
+#[tauri::command]
+pub async fn accept_suggested_title(id: String) -> Result<String, String> {
+  let path = &note_path(&id)?;
+  let note = read_note(path)?;
+  let title = note["metadata"]["suggested_title"]
+    .as_str()
+    .ok_or_else(|| "Note has no suggested title".to_string())?
+    .to_string();
+  rename_note(id, title.clone()).await?;
+  dismiss_suggested_title(path)?;
+  Ok(title)
+}
+
+// Drop the note's suggested title without applying it
+#[tauri::command]
+pub fn dismiss_suggested_title(id: &str) -> Result<(), String> {
+  let path = &note_path(id)?;
+  let mut note = read_note(path)?;
+  if let Some(metadata) = note["metadata"].as_object_mut() {
+    metadata.remove("suggested_title");
+  }
+  write_note(path, &note)
+}
@@ -0,0 +1,1086 @@
+use crate::config::{get_config, SyncRemoteKind};
+use crate::encrypted_remote::EncryptedRemote;
+use crate::folder_remote::FolderRemote;
+use crate::fs::{collect_note_paths, get_app_data_dir, note_path, read_note, write_note};
+use crate::merge::{conflict_title, merge_notes};
+use crate::mongo::MongoRemote;
+use crate::qdrant::{index_note_file, remove_note_embeddings};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use uuid::Uuid;
+
+const STATE_FILE: &str = "sync_state.json";
+const CONFLICTS_FILE: &str = "sync_conflicts.json";
+// Copies of notes as they were last synced, the common ancestor for merges
+const BASE_DIR: &str = "sync_base";
+
+static SYNC_RUNNING: AtomicBool = AtomicBool::new(false);
+
+// A note as recorded on a remote, without its content
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RemoteEntry {
+  pub id: String,
+  // Path relative to the notes directory with forward slashes, so folders survive the sync
+  pub path: String,
+  // See `note_hash`; empty for deleted notes
+  pub hash: String,
+  // Remote revision at which the note last changed
+  pub revision: u64,
+  // Tombstone left behind by a deleted note
+  pub deleted: bool,
+  pub modified_at: DateTime<Utc>,
+}
+
+// Entries changed on a remote after some revision
+#[derive(Debug, Clone)]
+pub struct RemoteChanges {
+  pub entries: Vec<RemoteEntry>,
+  // Sync token: the revision to ask for changes after next time
+  pub cursor: u64,
+}
+
+// Storage notes are synced through. Every change bumps a revision counter kept by the remote,
+// so a device only has to ask for what changed after the last revision it saw.
+#[async_trait]
+pub trait SyncRemote: Send + Sync {
+  // Identifies the remote; sync state recorded against a different remote is discarded
+  fn describe(&self) -> String;
+
+  async fn changes(&self, since: u64) -> Result<RemoteChanges, String>;
+
+  // Content of a note, None if the remote has none or only a tombstone
+  async fn fetch(&self, id: &str) -> Result<Option<Value>, String>;
+
+  // Store `note` under `entry`, or a tombstone when it is None, unless the remote copy changed
+  // after `base`, the revision this device last synced (0 if it never did). Returns the note's
+  // new revision, or None when the remote copy changed.
+  async fn push(
+    &self,
+    entry: &RemoteEntry,
+    note: Option<&Value>,
+    base: u64,
+  ) -> Result<Option<u64>, String>;
+
+  // Small named document kept next to the notes, such as the encryption keyring, and its
+  // version, which the remote bumps on every store
+  async fn load_meta(&self, name: &str) -> Result<Option<(Value, u64)>, String>;
+
+  // Store a named document unless it changed after `expected`, the version `load_meta` returned
+  // (None if there was no document). Returns false when another device stored it first.
+  async fn store_meta(
+    &self,
+    name: &str,
+    value: &Value,
+    expected: Option<u64>,
+  ) -> Result<bool, String>;
+
+  // Drop content kept apart from notes, such as shared attachments, that no note refers to
+  async fn prune(&self) -> Result<(), String>;
+}
+
+// A note as it was when it was last synced
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SyncedNote {
+  path: String,
+  hash: String,
+  revision: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+struct SyncState {
+  remote: String,
+  cursor: u64,
+  notes: BTreeMap<String, SyncedNote>,
+  last_synced_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SyncReport {
+  pub remote: String,
+  pub pushed: Vec<String>,
+  pub pulled: Vec<String>,
+  // Notes removed here because they were deleted on the remote
+  pub deleted: Vec<String>,
+  // Notes changed both here and on the remote since the last sync whose changes were combined
+  pub merged: Vec<String>,
+  // Notes whose changes couldn't be combined, see `SyncConflict`
+  pub conflicts: Vec<String>,
+  pub errors: Vec<String>,
+  pub synced_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SyncStatus {
+  pub remote: Option<String>,
+  pub last_synced_at: Option<DateTime<Utc>>,
+  // Notes created, edited, moved or deleted here since the last sync
+  pub pending: Vec<String>,
+}
+
+// Remote version of a note kept as a separate copy because it clashed with local edits
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncConflict {
+  pub id: String,
+  pub title: String,
+  // Note holding the remote version
+  pub copy_id: String,
+  pub remote_title: String,
+  // Ids of the pages, or "title", changed on both sides
+  pub pages: Vec<String>,
+  pub remote: String,
+  pub detected_at: DateTime<Utc>,
+}
+
+// Which version of a conflicted note to keep
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictResolution {
+  Local,
+  Remote,
+  // Keep the conflict copy as a note of its own
+  Both,
+}
+
+// Which way changes travel during a sync
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncDirection {
+  Both,
+  // Only send local changes
+  Push,
+  // Only apply remote changes
+  Pull,
+}
+
+// What happened to one note during a sync
+enum Outcome {
+  Unchanged,
+  Pushed,
+  Pulled,
+  Deleted,
+  // Changed on both sides and combined
+  Merged,
+  // Changed on both sides; the remote version was kept as a conflict copy
+  ConflictCopy,
+  Conflict,
+}
+
+// A note in the local notes directory
+struct LocalNote {
+  path: PathBuf,
+  relative: String,
+  hash: String,
+  note: Value,
+}
+
+// Hash identifying a note's content on every device
+pub fn note_hash(note: &Value) -> String {
+  let content = serde_json::to_string(note).unwrap_or_default();
+  format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+fn notes_dir() -> Result<PathBuf, String> {
+  Ok(get_app_data_dir()?.join("notes"))
+}
+
+// Every note with an id, by id
+fn local_notes() -> Result<BTreeMap<String, LocalNote>, String> {
+  let dir = notes_dir()?;
+  let mut notes = BTreeMap::new();
+  for path in collect_note_paths()? {
+    let Some(path_str) = path.to_str() else {
+      continue;
+    };
+    let note = match read_note(path_str) {
+      Ok(note) => note,
+      Err(e) => {
+        eprintln!("Skipping {} during sync: {}", path_str, e);
+        continue;
+      }
+    };
+    let Some(id) = note["id"].as_str().map(str::to_string) else {
+      continue;
+    };
+    let Ok(relative) = path.strip_prefix(&dir) else {
+      continue;
+    };
+    let relative = relative
+      .components()
+      .map(|component| component.as_os_str().to_string_lossy())
+      .collect::<Vec<_>>()
+      .join("/");
+    notes.insert(
+      id,
+      LocalNote {
+        hash: note_hash(&note),
+        path,
+        relative,
+        note,
+      },
+    );
+  }
+  Ok(notes)
+}
+
+// Local path of a note's remote path, refusing paths that would leave the notes directory
+fn local_path(relative: &str) -> Result<PathBuf, String> {
+  let path = Path::new(relative);
+  if relative.is_empty()
+    || !path
+      .components()
+      .all(|component| matches!(component, Component::Normal(_)))
+  {
+    return Err(format!("Refusing to sync a note to '{}'", relative));
+  }
+  Ok(notes_dir()?.join(path))
+}
+
+fn load_state() -> Result<SyncState, String> {
+  let path = get_app_data_dir()?.join(STATE_FILE);
+  if !path.exists() {
+    return Ok(SyncState::default());
+  }
+  let content =
+    fs::read_to_string(&path).map_err(|e| format!("Failed to read sync state: {}", e))?;
+  serde_json::from_str(&content).map_err(|e| format!("Failed to parse sync state: {}", e))
+}
+
+fn save_state(state: &SyncState) -> Result<(), String> {
+  let path = get_app_data_dir()?.join(STATE_FILE);
+  let tmp_path = path.with_extension("json.tmp");
+  let content = serde_json::to_string_pretty(state)
+    .map_err(|e| format!("Failed to serialize sync state: {}", e))?;
+  // Write to a temporary file first so a crash never leaves a truncated state
+  fs::write(&tmp_path, content).map_err(|e| format!("Failed to write sync state: {}", e))?;
+  fs::rename(&tmp_path, &path).map_err(|e| format!("Failed to replace sync state: {}", e))
+}
+
+fn base_path(id: &str) -> Result<PathBuf, String> {
+  // Ids become file names, so anything but a plain id could escape the directory
+  if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+    return Err(format!("Invalid note id '{}'", id));
+  }
+  Ok(
+    get_app_data_dir()?
+      .join(BASE_DIR)
+      .join(format!("{}.json", id)),
+  )
+}
+
+fn load_base(id: &str) -> Result<Option<Value>, String> {
+  let path = base_path(id)?;
+  if !path.exists() {
+    return Ok(None);
+  }
+  let content =
+    fs::read_to_string(&path).map_err(|e| format!("Failed to read sync base: {}", e))?;
+  serde_json::from_str(&content)
+    .map(Some)
+    .map_err(|e| format!("Failed to parse sync base: {}", e))
+}
+
+fn save_base(id: &str, note: &Value) -> Result<(), String> {
+  let path = base_path(id)?;
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+  }
+  let tmp_path = path.with_extension("json.tmp");
+  let content =
+    serde_json::to_string(note).map_err(|e| format!("Failed to serialize sync base: {}", e))?;
+  fs::write(&tmp_path, content).map_err(|e| format!("Failed to write sync base: {}", e))?;
+  fs::rename(&tmp_path, &path).map_err(|e| format!("Failed to replace sync base: {}", e))
+}
+
+fn remove_base(id: &str) -> Result<(), String> {
+  let path = base_path(id)?;
+  if path.exists() {
+    fs::remove_file(&path).map_err(|e| format!("Failed to remove sync base: {}", e))?;
+  }
+  Ok(())
+}
+
+fn load_conflicts() -> Result<Vec<SyncConflict>, String> {
+  let path = get_app_data_dir()?.join(CONFLICTS_FILE);
+  if !path.exists() {
+    return Ok(Vec::new());
+  }
+  let content =
+    fs::read_to_string(&path).map_err(|e| format!("Failed to read sync conflicts: {}", e))?;
+  serde_json::from_str(&content).map_err(|e| format!("Failed to parse sync conflicts: {}", e))
+}
+
+fn save_conflicts(conflicts: &[SyncConflict]) -> Result<(), String> {
+  let path = get_app_data_dir()?.join(CONFLICTS_FILE);
+  let tmp_path = path.with_extension("json.tmp");
+  let content = serde_json::to_string_pretty(conflicts)
+    .map_err(|e| format!("Failed to serialize sync conflicts: {}", e))?;
+  fs::write(&tmp_path, content).map_err(|e| format!("Failed to write sync conflicts: {}", e))?;
+  fs::rename(&tmp_path, &path).map_err(|e| format!("Failed to replace sync conflicts: {}", e))
+}
+
+// The remote selected in the user config as notes are stored in it, None when sync is off
+pub fn get_storage_remote() -> Result<Option<Arc<dyn SyncRemote>>, String> {
+  let config = get_config()?;
+  let remote: Arc<dyn SyncRemote> = match config.sync.remote {
+    SyncRemoteKind::None => return Ok(None),
+    SyncRemoteKind::Folder => {
+      if config.sync.folder.is_empty() {
+        return Err("No sync folder is configured".to_string());
+      }
+      Arc::new(FolderRemote::new(PathBuf::from(&config.sync.folder)))
+    }
+    SyncRemoteKind::Mongo => Arc::new(MongoRemote::new(&config.sync)),
+  };
+  Ok(Some(remote))
+}
+
+// The remote notes are synced through, encrypting them first when encryption is on
+pub fn get_sync_remote() -> Result<Option<Arc<dyn SyncRemote>>, String> {
+  let Some(remote) = get_storage_remote()? else {
+    return Ok(None);
+  };
+  if !get_config()?.sync.encrypt {
+    return Ok(Some(remote));
+  }
+  Ok(Some(Arc::new(EncryptedRemote::new(remote)?)))
+}
+
+// Mark every synced note as changed here so the next sync pushes it again
+pub fn resend_all_notes() -> Result<(), String> {
+  let mut state = load_state()?;
+  for synced in state.notes.values_mut() {
+    synced.hash.clear();
+  }
+  save_state(&state)
+}
+
+// Send a note changed or deleted here to the remote, unless the remote copy changed after the
+// revision `base`
+async fn push_note(
+  remote: &dyn SyncRemote,
+  id: &str,
+  local: Option<&LocalNote>,
+  base: u64,
+  state: &mut SyncState,
+) -> Result<Outcome, String> {
+  let synced = state.notes.get(id);
+  let entry = match (local, synced) {
+    (Some(note), _) => RemoteEntry {
+      id: id.to_string(),
+      path: note.relative.clone(),
+      hash: note.hash.clone(),
+      revision: 0,
+      deleted: false,
+      modified_at: Utc::now(),
+    },
+    (None, Some(synced)) => RemoteEntry {
+      id: id.to_string(),
+      path: synced.path.clone(),
+      hash: String::new(),
+      revision: 0,
+      deleted: true,
+      modified_at: Utc::now(),
+    },
+    (None, None) => return Ok(Outcome::Unchanged),
+  };
+
+  let Some(revision) = remote
+    .push(&entry, local.map(|note| &note.note), base)
+    .await?
+  else {
+    return Ok(Outcome::Conflict);
+  };
+  match local {
+    Some(note) => {
+      state.notes.insert(
+        id.to_string(),
+        SyncedNote {
+          path: note.relative.clone(),
+          hash: note.hash.clone(),
+          revision,
+        },
+      );
+      save_base(id, &note.note)?;
+    }
+    None => {
+      state.notes.remove(id);
+      remove_base(id)?;
+    }
+  }
+  Ok(Outcome::Pushed)
+}
+
+// Apply a note changed or deleted on the remote here
+async fn pull_note(
+  remote: &dyn SyncRemote,
+  entry: &RemoteEntry,
+  local: Option<&LocalNote>,
+  state: &mut SyncState,
+) -> Result<Outcome, String> {
+  if entry.deleted {
+    state.notes.remove(&entry.id);
+    remove_base(&entry.id)?;
+    let Some(note) = local else {
+      return Ok(Outcome::Unchanged);
+    };
+    fs::remove_file(&note.path).map_err(|e| format!("Failed to delete note: {}", e))?;
+    if let Err(e) = remove_note_embeddings(&entry.id).await {
+      eprintln!(
+        "Failed to remove embeddings of {}: {}",
+        note.path.display(),
+        e
+      );
+    }
+    return Ok(Outcome::Deleted);
+  }
+
+  let content = remote
+    .fetch(&entry.id)
+    .await?
+    .ok_or_else(|| "The remote has no content for this note".to_string())?;
+  let path = local_path(&entry.path)?;
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+  }
+  let path_str = path
+    .to_str()
+    .ok_or_else(|| "Invalid path encoding".to_string())?;
+  write_note(path_str, &content)?;
+
+  // The note was moved on the remote
+  if let Some(note) = local.filter(|note| note.path != path) {
+    fs::remove_file(&note.path).map_err(|e| format!("Failed to remove moved note: {}", e))?;
+  }
+  if let Err(e) = index_note_file(&path).await {
+    eprintln!("Failed to index {}: {}", path_str, e);
+  }
+
+  state.notes.insert(
+    entry.id.clone(),
+    SyncedNote {
+      path: entry.path.clone(),
+      hash: note_hash(&content),
+      revision: entry.revision,
+    },
+  );
+  save_base(&entry.id, &content)?;
+  Ok(Outcome::Pulled)
+}
+
+// Reconcile a note changed both here and on the remote. An edit wins over a deletion on the other
+// side. Edits on both sides are merged three-way against the copy from the last sync; when they
+// clash the merged note keeps the local edits and the remote version is saved as a conflict copy.
+async fn merge_conflict(
+  remote: &dyn SyncRemote,
+  id: &str,
+  local: Option<&LocalNote>,
+  remote_entry: Option<&RemoteEntry>,
+  state: &mut SyncState,
+) -> Result<Outcome, String> {
+  let entry = match remote_entry {
+    Some(entry) => entry.clone(),
+    None => {
+      let since = state.notes.get(id).map_or(0, |synced| synced.revision);
+      let changes = remote.changes(since).await?;
+      match changes.entries.into_iter().find(|entry| entry.id == id) {
+        Some(entry) => entry,
+        None => return Ok(Outcome::Conflict),
+      }
+    }
+  };
+  let remote_note = if entry.deleted {
+    None
+  } else {
+    remote.fetch(id).await?
+  };
+
+  let (note, remote_note) = match (local, remote_note) {
+    (Some(note), Some(remote_note)) => (note, remote_note),
+    // Deleted on the remote, edited here
+    (Some(note), None) => return push_note(remote, id, Some(note), entry.revision, state).await,
+    // Deleted here, edited on the remote
+    (None, Some(_)) => return pull_note(remote, &entry, None, state).await,
+    (None, None) => {
+      state.notes.remove(id);
+      remove_base(id)?;
+      return Ok(Outcome::Unchanged);
+    }
+  };
+
+  let base = load_base(id)?;
+  let merge = merge_notes(base.as_ref(), &note.note, &remote_note);
+  let path_str = note
+    .path
+    .to_str()
+    .ok_or_else(|| "Invalid path encoding".to_string())?;
+  write_note(path_str, &merge.note)?;
+  if let Err(e) = index_note_file(&note.path).await {
+    eprintln!("Failed to index {}: {}", path_str, e);
+  }
+
+  let merged = LocalNote {
+    path: note.path.clone(),
+    relative: note.relative.clone(),
+    hash: note_hash(&merge.note),
+    note: merge.note,
+  };
+  match push_note(remote, id, Some(&merged), entry.revision, state).await? {
+    Outcome::Pushed if merge.conflicts.is_empty() => return Ok(Outcome::Merged),
+    Outcome::Pushed => {}
+    // The remote changed again; the next sync merges with that version instead
+    outcome => return Ok(outcome),
+  }
+
+  // Only kept once the merged note is on the remote, so a failed push leaves no stray copy
+  let now = Utc::now();
+  let copy_id = Uuid::new_v4().to_string();
+  let remote_title = remote_note["title"]
+    .as_str()
+    .unwrap_or_default()
+    .to_string();
+  let mut copy = remote_note;
+  copy["id"] = Value::String(copy_id.clone());
+  copy["title"] = Value::String(conflict_title(&remote_title, now));
+  let copy_path = note.path.with_file_name(format!("{}.json", copy_id));
+  let copy_path_str = copy_path
+    .to_str()
+    .ok_or_else(|| "Invalid path encoding".to_string())?;
+  write_note(copy_path_str, &copy)?;
+  if let Err(e) = index_note_file(&copy_path).await {
+    eprintln!("Failed to index {}: {}", copy_path_str, e);
+  }
+
+  let mut conflicts = load_conflicts()?;
+  conflicts.push(SyncConflict {
+    id: id.to_string(),
+    title: note.note["title"].as_str().unwrap_or_default().to_string(),
+    copy_id,
+    remote_title,
+    pages: merge.conflicts,
+    remote: state.remote.clone(),
+    detected_at: now,
+  });
+  save_conflicts(&conflicts)?;
+  Ok(Outcome::ConflictCopy)
+}
+
+// Sync one note given its local copy, what was last synced and its remote change, if any
+async fn sync_note(
+  remote: &dyn SyncRemote,
+  id: &str,
+  local: Option<&LocalNote>,
+  remote_entry: Option<&RemoteEntry>,
+  direction: SyncDirection,
+  state: &mut SyncState,
+) -> Result<Outcome, String> {
+  let local_changed = match (local, state.notes.get(id)) {
+    (Some(note), Some(synced)) => note.hash != synced.hash || note.relative != synced.path,
+    (None, None) => false,
+    // Created or deleted here
+    _ => true,
+  };
+  match (local_changed, remote_entry) {
+    (false, None) => Ok(Outcome::Unchanged),
+    // Left pending until changes are pushed
+    (true, None) if direction == SyncDirection::Pull => Ok(Outcome::Unchanged),
+    (true, None) => {
+      let base = state.notes.get(id).map_or(0, |synced| synced.revision);
+      match push_note(remote, id, local, base, state).await? {
+        // The remote copy changed since the changes were last fetched
+        Outcome::Conflict if direction == SyncDirection::Both => {
+          merge_conflict(remote, id, local, None, state).await
+        }
+        outcome => Ok(outcome),
+      }
+    }
+    (false, Some(entry)) => pull_note(remote, entry, local, state).await,
+    (true, Some(entry)) => {
+      // Both sides already agree, e.g. after the same edit arrived through another device
+      let same = match local {
+        Some(note) => !entry.deleted && note.hash == entry.hash && note.relative == entry.path,
+        None => entry.deleted,
+      };
+      if !same {
+        // Merging writes to both sides, so one-way syncs leave it to the next full sync
+        if direction != SyncDirection::Both {
+          return Ok(Outcome::Conflict);
+        }
+        return merge_conflict(remote, id, local, Some(entry), state).await;
+      }
+      match local {
+        Some(note) => {
+          state.notes.insert(
+            id.to_string(),
+            SyncedNote {
+              path: note.relative.clone(),
+              hash: note.hash.clone(),
+              revision: entry.revision,
+            },
+          );
+          save_base(id, &note.note)?;
+        }
+        None => {
+          state.notes.remove(id);
+          remove_base(id)?;
+        }
+      }
+      Ok(Outcome::Unchanged)
+    }
+  }
+}
+
+// Bring the notes directory and `remote` up to date with each other. Only notes that changed
+// since the last sync are transferred: a note changed on one side is copied to the other, and a
+// note changed on both sides is merged, keeping a conflict copy of the remote version when the
+// edits clash so neither is lost. `direction` limits the sync to sending or to receiving changes.
+pub async fn sync_with(
+  remote: &dyn SyncRemote,
+  direction: SyncDirection,
+) -> Result<SyncReport, String> {
+  let mut state = load_state()?;
+  let description = remote.describe();
+  if state.remote != description {
+    state = SyncState {
+      remote: description.clone(),
+      ..SyncState::default()
+    };
+  }
+
+  let local = local_notes()?;
+  let changes = match direction {
+    SyncDirection::Push => RemoteChanges {
+      entries: Vec::new(),
+      cursor: state.cursor,
+    },
+    _ => remote.changes(state.cursor).await?,
+  };
+  // Entries at or below the synced revision are this device's own pushes
+  let remote_changed: BTreeMap<String, RemoteEntry> = changes
+    .entries
+    .into_iter()
+    .filter(|entry| {
+      state
+        .notes
+        .get(&entry.id)
+        .is_none_or(|synced| entry.revision > synced.revision)
+    })
+    .map(|entry| (entry.id.clone(), entry))
+    .collect();
+  let ids: BTreeSet<String> = local
+    .keys()
+    .chain(state.notes.keys())
+    .chain(remote_changed.keys())
+    .cloned()
+    .collect();
+
+  let mut report = SyncReport {
+    remote: description,
+    pushed: Vec::new(),
+    pulled: Vec::new(),
+    deleted: Vec::new(),
+    merged: Vec::new(),
+    conflicts: Vec::new(),
+    errors: Vec::new(),
+    synced_at: Utc::now(),
+  };
+  for id in ids {
+    let outcome = sync_note(
+      remote,
+      &id,
+      local.get(&id),
+      remote_changed.get(&id),
+      direction,
+      &mut state,
+    )
+    .await;
+    match outcome {
+      Ok(Outcome::Unchanged) => {}
+      Ok(Outcome::Pushed) => report.pushed.push(id),
+      Ok(Outcome::Pulled) => report.pulled.push(id),
+      Ok(Outcome::Deleted) => report.deleted.push(id),
+      Ok(Outcome::Merged) => report.merged.push(id),
+      Ok(Outcome::ConflictCopy | Outcome::Conflict) => report.conflicts.push(id),
+      Err(e) => report.errors.push(format!("{}: {}", id, e)),
+    }
+  }
+
+  // Keep the old cursor after failures so the missed remote changes are fetched again
+  if report.errors.is_empty() {
+    state.cursor = changes.cursor;
+  }
+  state.last_synced_at = Some(report.synced_at);
+  save_state(&state)?;
+  Ok(report)
+}
+
+async fn run_sync(direction: SyncDirection) -> Result<SyncReport, String> {
+  let remote = get_sync_remote()?.ok_or_else(|| "Sync is not configured".to_string())?;
+  if SYNC_RUNNING.swap(true, Ordering::SeqCst) {
+    return Err("A sync is already running".to_string());
+  }
+  let result = sync_with(remote.as_ref(), direction).await;
+  SYNC_RUNNING.store(false, Ordering::SeqCst);
+  result
+}
+
+// Sync with the remote selected in the config
+#[tauri::command]
+pub async fn sync_notes() -> Result<SyncReport, String> {
+  run_sync(SyncDirection::Both).await
+}
+
+// Send notes created, edited, moved or deleted here since the last sync
+#[tauri::command]
+pub async fn push_notes() -> Result<SyncReport, String> {
+  run_sync(SyncDirection::Push).await
+}
+
+// Apply the remote changes made since the last sync token
+#[tauri::command]
+pub async fn pull_notes() -> Result<SyncReport, String> {
+  run_sync(SyncDirection::Pull).await
+}
+
+// When the notes were last synced and which local changes have not been sent yet
+#[tauri::command]
+pub fn sync_status() -> Result<SyncStatus, String> {
+  let remote = get_sync_remote()?;
+  let mut state = load_state()?;
+  let description = remote.as_ref().map(|remote| remote.describe());
+  if description.as_deref() != Some(state.remote.as_str()) {
+    state = SyncState::default();
+  }
+
+  let local = local_notes()?;
+  let pending = local
+    .keys()
+    .chain(state.notes.keys())
+    .collect::<BTreeSet<_>>()
+    .into_iter()
+    .filter(|id| match (local.get(*id), state.notes.get(*id)) {
+      (Some(note), Some(synced)) => note.hash != synced.hash || note.relative != synced.path,
+      _ => true,
+    })
+    .cloned()
+    .collect();
+
+  Ok(SyncStatus {
+    remote: description,
+    last_synced_at: state.last_synced_at,
+    pending,
+  })
+}
+
+// Notes whose remote version was kept as a conflict copy during a sync
+#[tauri::command]
+pub fn list_sync_conflicts() -> Result<Vec<SyncConflict>, String> {
+  load_conflicts()
+}
+
+// Settle a conflict by keeping the local version, the remote version or both as separate notes
+#[tauri::command]
+pub async fn resolve_sync_conflict(
+  copy_id: String,
+  keep: ConflictResolution,
+) -> Result<(), String> {
+  let mut conflicts = load_conflicts()?;
+  let index = conflicts
+    .iter()
+    .position(|conflict| conflict.copy_id == copy_id)
+    .ok_or_else(|| format!("No sync conflict for note {}", copy_id))?;
+  let conflict = conflicts[index].clone();
+
+  match keep {
+    ConflictResolution::Local => remove_conflict_copy(&conflict.copy_id).await?,
+    ConflictResolution::Remote => {
+      let copy_path = note_path(&conflict.copy_id)?;
+      let mut note = read_note(&copy_path)?;
+      note["id"] = Value::String(conflict.id.clone());
+      note["title"] = Value::String(conflict.remote_title.clone());
+      let path = note_path(&conflict.id)?;
+      write_note(&path, &note)?;
+      if let Err(e) = index_note_file(Path::new(&path)).await {
+        eprintln!("Failed to index {}: {}", path, e);
+      }
+      remove_conflict_copy(&conflict.copy_id).await?;
+    }
+    ConflictResolution::Both => {}
+  }
+
+  conflicts.remove(index);
+  save_conflicts(&conflicts)
+}
+
+async fn remove_conflict_copy(copy_id: &str) -> Result<(), String> {
+  // Already deleted by hand
+  if note_path(copy_id).is_err() {
+    return Ok(());
+  }
+  crate::fs::delete_note(copy_id)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fs::TEST_DATA_DIR;
+  use serde_json::json;
+
+  // A device with its own data directory, syncing through a shared remote
+  struct Device {
+    dir: PathBuf,
+  }
+
+  impl Device {
+    fn new(root: &Path, name: &str) -> Self {
+      let dir = root.join(name);
+      fs::create_dir_all(dir.join("notes")).unwrap();
+      Device { dir }
+    }
+
+    // Point the data directory of the current thread at this device
+    fn enter(&self) {
+      TEST_DATA_DIR.with(|dir| *dir.borrow_mut() = Some(self.dir.clone()));
+    }
+
+    async fn sync(&self, remote: &dyn SyncRemote) -> SyncReport {
+      self.enter();
+      sync_with(remote, SyncDirection::Both).await.unwrap()
+    }
+
+    fn path(&self, relative: &str) -> PathBuf {
+      self.dir.join("notes").join(relative)
+    }
+
+    fn write(&self, relative: &str, note: &Value) {
+      let path = self.path(relative);
+      fs::create_dir_all(path.parent().unwrap()).unwrap();
+      fs::write(path, serde_json::to_string_pretty(note).unwrap()).unwrap();
+    }
+
+    fn read(&self, relative: &str) -> Option<Value> {
+      let content = fs::read_to_string(self.path(relative)).ok()?;
+      Some(serde_json::from_str(&content).unwrap())
+    }
+  }
+
+  fn temp_root() -> PathBuf {
+    std::env::temp_dir().join(format!("sync-test-{}", Uuid::new_v4()))
+  }
+
+  fn note(id: &str, title: &str, pages: &[(&str, &str)]) -> Value {
+    json!({
+      "id": id,
+      "title": title,
+      "metadata": {
+        "note_type": "notebook",
+        "tags": [],
+        "created_at": "2025-01-01T00:00:00Z",
+        "last_accessed": "2025-01-01T00:00:00Z",
+      },
+      "pages": pages
+        .iter()
+        .map(|(id, content)| json!({"id": id, "content": content, "drawings": []}))
+        .collect::<Vec<_>>(),
+    })
+  }
+
+  // Two devices that both have `note` synced at `relative`
+  async fn synced_pair(
+    root: &Path,
+    remote: &dyn SyncRemote,
+    relative: &str,
+    note: &Value,
+  ) -> (Device, Device) {
+    let (a, b) = (Device::new(root, "a"), Device::new(root, "b"));
+    a.write(relative, note);
+    a.sync(remote).await;
+    b.sync(remote).await;
+    (a, b)
+  }
+
+  #[tokio::test]
+  async fn pushes_and_pulls_new_notes() {
+    let root = temp_root();
+    let remote = FolderRemote::new(root.join("remote"));
+    let (a, b) = (Device::new(&root, "a"), Device::new(&root, "b"));
+    a.write(
+      "first.json",
+      &note("n1", "First", &[("p1", "<p>hello</p>")]),
+    );
+
+    let report = a.sync(&remote).await;
+    assert_eq!(report.pushed, vec!["n1"]);
+    assert!(report.errors.is_empty());
+    let report = b.sync(&remote).await;
+    assert_eq!(report.pulled, vec!["n1"]);
+    assert_eq!(b.read("first.json"), a.read("first.json"));
+
+    // Neither side has anything left to send or receive
+    for device in [&a, &b] {
+      let report = device.sync(&remote).await;
+      assert!(report.pushed.is_empty() && report.pulled.is_empty());
+    }
+    let _ = fs::remove_dir_all(root);
+  }
+
+  #[tokio::test]
+  async fn deletes_notes_deleted_on_another_device() {
+    let root = temp_root();
+    let remote = FolderRemote::new(root.join("remote"));
+    let original = note("n1", "First", &[("p1", "<p>hello</p>")]);
+    let (a, b) = synced_pair(&root, &remote, "first.json", &original).await;
+    assert!(b.read("first.json").is_some());
+
+    fs::remove_file(a.path("first.json")).unwrap();
+    assert_eq!(a.sync(&remote).await.pushed, vec!["n1"]);
+    assert_eq!(b.sync(&remote).await.deleted, vec!["n1"]);
+    assert!(b.read("first.json").is_none());
+    let _ = fs::remove_dir_all(root);
+  }
+
+  #[tokio::test]
+  async fn moves_notes_moved_on_another_device() {
+    let root = temp_root();
+    let remote = FolderRemote::new(root.join("remote"));
+    let original = note("n1", "First", &[("p1", "<p>hello</p>")]);
+    let (a, b) = synced_pair(&root, &remote, "first.json", &original).await;
+
+    fs::create_dir_all(a.path("folder")).unwrap();
+    fs::rename(a.path("first.json"), a.path("folder/first.json")).unwrap();
+    assert_eq!(a.sync(&remote).await.pushed, vec!["n1"]);
+    assert_eq!(b.sync(&remote).await.pulled, vec!["n1"]);
+    assert!(b.read("first.json").is_none());
+    assert_eq!(b.read("folder/first.json"), Some(original));
+    let _ = fs::remove_dir_all(root);
+  }
+
+  #[tokio::test]
+  async fn refuses_pushes_based_on_an_old_revision() {
+    let root = temp_root();
+    let remote = FolderRemote::new(root.join("remote"));
+    let content = note("n1", "First", &[]);
+    let entry = RemoteEntry {
+      id: "n1".to_string(),
+      path: "first.json".to_string(),
+      hash: note_hash(&content),
+      revision: 0,
+      deleted: false,
+      modified_at: Utc::now(),
+    };
+
+    assert_eq!(
+      remote.push(&entry, Some(&content), 0).await.unwrap(),
+      Some(1)
+    );
+    // Another device already pushed revision 1
+    assert_eq!(remote.push(&entry, Some(&content), 0).await.unwrap(), None);
+    assert_eq!(
+      remote.push(&entry, Some(&content), 1).await.unwrap(),
+      Some(2)
+    );
+    let changes = remote.changes(1).await.unwrap();
+    assert_eq!(changes.cursor, 2);
+    assert_eq!(changes.entries.len(), 1);
+    let _ = fs::remove_dir_all(root);
+  }
+
+  #[tokio::test]
+  async fn merges_edits_to_different_pages() {
+    let root = temp_root();
+    let remote = FolderRemote::new(root.join("remote"));
+    let original = note("n1", "First", &[("p1", "<p>one</p>"), ("p2", "<p>two</p>")]);
+    let (a, b) = synced_pair(&root, &remote, "first.json", &original).await;
+
+    a.write(
+      "first.json",
+      &note(
+        "n1",
+        "First",
+        &[("p1", "<p>one from a</p>"), ("p2", "<p>two</p>")],
+      ),
+    );
+    b.write(
+      "first.json",
+      &note(
+        "n1",
+        "First",
+        &[("p1", "<p>one</p>"), ("p2", "<p>two from b</p>")],
+      ),
+    );
+    assert_eq!(a.sync(&remote).await.pushed, vec!["n1"]);
+    let report = b.sync(&remote).await;
+    assert_eq!(report.merged, vec!["n1"]);
+    let merged = note(
+      "n1",
+      "First",
+      &[("p1", "<p>one from a</p>"), ("p2", "<p>two from b</p>")],
+    );
+    assert_eq!(b.read("first.json"), Some(merged.clone()));
+
+    assert_eq!(a.sync(&remote).await.pulled, vec!["n1"]);
+    assert_eq!(a.read("first.json"), Some(merged));
+    let _ = fs::remove_dir_all(root);
+  }
+
+  #[tokio::test]
+  async fn keeps_a_conflict_copy_of_clashing_edits() {
+    let root = temp_root();
+    let remote = FolderRemote::new(root.join("remote"));
+    let original = note("n1", "First", &[("p1", "<p>one</p>")]);
+    let (a, b) = synced_pair(&root, &remote, "first.json", &original).await;
+
+    let from_a = note("n1", "First", &[("p1", "<p>one from a</p>")]);
+    let from_b = note("n1", "First", &[("p1", "<p>one from b</p>")]);
+    a.write("first.json", &from_a);
+    b.write("first.json", &from_b);
+    a.sync(&remote).await;
+    let report = b.sync(&remote).await;
+    assert_eq!(report.conflicts, vec!["n1"]);
+
+    // The local edits stay in the note and the remote version in the copy
+    assert_eq!(b.read("first.json"), Some(from_b));
+    let conflicts = load_conflicts().unwrap();
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].id, "n1");
+    assert_eq!(conflicts[0].pages, vec!["p1"]);
+    let copy = b
+      .read(&format!("{}.json", conflicts[0].copy_id))
+      .expect("conflict copy");
+    assert_eq!(copy["pages"], from_a["pages"]);
+    let _ = fs::remove_dir_all(root);
+  }
+
+  #[tokio::test]
+  async fn keeps_plaintext_notes_when_another_device_turns_on_encryption() {
+    let root = temp_root();
+    let remote: Arc<dyn SyncRemote> = Arc::new(FolderRemote::new(root.join("remote")));
+    let (a, b) = (Device::new(&root, "a"), Device::new(&root, "b"));
+    let content = note("n1", "First", &[("p1", "<p>hello</p>")]);
+    a.write("first.json", &content);
+    a.sync(remote.as_ref()).await;
+
+    // B unlocks encryption; nothing encrypted covers A's note yet, so nothing is purged
+    let encrypted = EncryptedRemote::with_new_keys(remote.clone());
+    assert_eq!(encrypted.purge_plaintext().await.unwrap(), 0);
+    b.sync(&encrypted).await;
+    let report = a.sync(remote.as_ref()).await;
+    assert!(report.errors.is_empty() && report.pulled.is_empty());
+    assert_eq!(a.read("first.json"), Some(content.clone()));
+
+    // Once A syncs with encryption too, the plaintext copy is no longer needed
+    a.sync(&encrypted).await;
+    assert_eq!(encrypted.purge_plaintext().await.unwrap(), 1);
+    assert_eq!(remote.fetch("n1").await.unwrap(), None);
+    b.sync(&encrypted).await;
+    assert_eq!(b.read("first.json"), Some(content));
+    let _ = fs::remove_dir_all(root);
+  }
+}
@@ -0,0 +1,270 @@
+use crate::fs::{collect_note_paths, note_page_texts, note_path, read_note, write_note};
+use crate::ollama::chat_json;
+use crate::qdrant::NOTES_COLLECTION;
+use crate::related::{group_by_note, note_vector, Pooling};
+use crate::vector_store::get_vector_store;
+use ollama_rs::generation::chat::ChatMessage;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+
+// Similar notes whose tags are considered
+const NEIGHBOURS: usize = 8;
+// Characters of note text sent to the model
+const MAX_TAGGING_INPUT: usize = 6000;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TagSuggestion {
+  pub tag: String,
+  // Between 0 and 1
+  pub confidence: f32,
+  // Confidence contributed by tags of similar notes
+  pub neighbour_score: f32,
+  // Confidence the model assigned, if it picked the tag
+  pub model_score: Option<f32>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct NoteTagSuggestions {
+  pub id: String,
+  pub path: String,
+  pub title: String,
+  pub suggestions: Vec<TagSuggestion>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct UntaggedSuggestions {
+  pub notes: Vec<NoteTagSuggestions>,
+  // Paths of the notes suggestions couldn't be made for
+  pub failed: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ModelTags {
+  tags: Vec<ModelTag>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ModelTag {
+  tag: String,
+  confidence: f32,
+}
+
+// Tags in a note's metadata
+pub fn note_tags(note: &Value) -> Vec<String> {
+  note["metadata"]["tags"]
+    .as_array()
+    .map(|tags| {
+      tags
+        .iter()
+        .filter_map(|tag| tag.as_str().map(str::to_string))
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+// Every tag used in the vault with the number of notes carrying it
+pub fn vault_tags() -> Result<BTreeMap<String, usize>, String> {
+  let mut tags = BTreeMap::new();
+  for path in collect_note_paths()? {
+    let Some(path_str) = path.to_str() else {
+      continue;
+    };
+    let Ok(note) = read_note(path_str) else {
+      continue;
+    };
+    for tag in note_tags(&note) {
+      *tags.entry(tag).or_insert(0) += 1;
+    }
+  }
+  Ok(tags)
+}
+
+// Tags of the notes most similar to the note `note_id`, weighted by their similarity
+async fn neighbour_tag_scores(note_id: &str) -> Result<HashMap<String, f32>, String> {
+  let mut scores = HashMap::new();
+  let Some(vector) = note_vector(note_id, None, Pooling::Mean).await? else {
+    return Ok(scores);
+  };
+  let hits = get_vector_store()?
+    .search(NOTES_COLLECTION, &vector, NEIGHBOURS * 3, Some(note_id))
+    .await?;
+  let neighbours = group_by_note(hits, NEIGHBOURS);
+
+  let total: f32 = neighbours.iter().map(|note| note.score.max(0.0)).sum();
+  if total <= 0.0 {
+    return Ok(scores);
+  }
+  for neighbour in neighbours {
+    let Ok(note) = read_note(&neighbour.path) else {
+      continue;
+    };
+    for tag in note_tags(&note) {
+      *scores.entry(tag).or_insert(0.0) += neighbour.score.max(0.0) / total;
+    }
+  }
+  Ok(scores)
+}
+
+// Ask the chat model to pick tags for the note from the vault vocabulary only
+async fn model_tag_scores(
+  text: &str,
+  vocabulary: &BTreeMap<String, usize>,
+) -> Result<HashMap<String, f32>, String> {
+  let tag_list = vocabulary.keys().cloned().collect::<Vec<_>>().join(", ");
+  let prompt = format!(
+    "You tag notes. Choose the tags that fit the note from this list only: {}. \
+Reply with a JSON object {{\"tags\": [{{\"tag\": string, \"confidence\": number between 0 and 1}}]}}.",
+    tag_list
+  );
+  let reply: ModelTags = chat_json(vec![
+    ChatMessage::system(prompt),
+    ChatMessage::user(text.chars().take(MAX_TAGGING_INPUT).collect()),
+  ])
+  .await?;
+
+  Ok(
+    reply
+      .tags
+      .into_iter()
+      .filter(|tag| vocabulary.contains_key(&tag.tag))
+      .map(|tag| (tag.tag, tag.confidence.clamp(0.0, 1.0)))
+      .collect(),
+  )
+}
+
+async fn suggest_for_note(
+  path: &str,
+  vocabulary: &BTreeMap<String, usize>,
+  limit: usize,
+) -> Result<Vec<TagSuggestion>, String> {
+  let note = read_note(path)?;
+  let existing = note_tags(&note);
+  let text = note_page_texts(&note)
+    .into_iter()
+    .map(|(_, text)| text)
+    .collect::<Vec<_>>()
+    .join("\n\n");
+
+  let note_id = note["id"].as_str().unwrap_or("");
+  let neighbour_scores = neighbour_tag_scores(note_id).await.unwrap_or_else(|e| {
+    eprintln!("Skipping neighbour tags for {}: {}", path, e);
+    HashMap::new()
+  });
+  let model_scores = if text.trim().is_empty() || vocabulary.is_empty() {
+    None
+  } else {
+    match model_tag_scores(&text, vocabulary).await {
+      Ok(scores) => Some(scores),
+      Err(e) => {
+        eprintln!("Skipping model tags for {}: {}", path, e);
+        None
+      }
+    }
+  };
+
+  let mut candidates: Vec<&String> = neighbour_scores.keys().collect();
+  if let Some(model_scores) = &model_scores {
+    candidates.extend(model_scores.keys());
+  }
+  candidates.sort();
+  candidates.dedup();
+
+  let mut suggestions: Vec<TagSuggestion> = candidates
+    .into_iter()
+    .filter(|tag| !existing.contains(*tag))
+    .map(|tag| {
+      let neighbour_score = neighbour_scores.get(tag).copied().unwrap_or(0.0);
+      let model_score = model_scores
+        .as_ref()
+        .and_then(|scores| scores.get(tag).copied());
+      // Without a model answer the neighbours are the only signal
+      let confidence = match &model_scores {
+        Some(_) => (neighbour_score + model_score.unwrap_or(0.0)) / 2.0,
+        None => neighbour_score,
+      };
+      TagSuggestion {
+        tag: tag.clone(),
+        confidence,
+        neighbour_score,
+        model_score,
+      }
+    })
+    .filter(|suggestion| suggestion.confidence > 0.0)
+    .collect();
+  suggestions.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+  suggestions.truncate(limit);
+  Ok(suggestions)
+}
+
+// Propose tags from the vault's existing vocabulary for the note `id`
+#[tauri::command]
+pub async fn suggest_tags(id: String, limit: Option<usize>) -> Result<Vec<TagSuggestion>, String> {
+  let vocabulary = vault_tags()?;
+  suggest_for_note(&note_path(&id)?, &vocabulary, limit.unwrap_or(5)).await
+}
+
+// Propose tags for every note that has none yet
+#[tauri::command]
+pub async fn suggest_tags_for_untagged(
+  limit: Option<usize>,
+) -> Result<UntaggedSuggestions, String> {
+  let vocabulary = vault_tags()?;
+  let mut results = UntaggedSuggestions {
+    notes: Vec::new(),
+    failed: Vec::new(),
+  };
+  for path in collect_note_paths()? {
+    let Some(path_str) = path.to_str() else {
+      continue;
+    };
+    let Ok(note) = read_note(path_str) else {
+      continue;
+    };
+    if !note_tags(&note).is_empty() {
+      continue;
+    }
+    let suggestions = match suggest_for_note(path_str, &vocabulary, limit.unwrap_or(5)).await {
+      Ok(suggestions) => suggestions,
+      Err(e) => {
+        eprintln!("Failed to suggest tags for {}: {}", path_str, e);
+        results.failed.push(path_str.to_string());
+        continue;
+      }
+    };
+    if suggestions.is_empty() {
+      continue;
+    }
+    results.notes.push(NoteTagSuggestions {
+      id: note["id"].as_str().unwrap_or("").to_string(),
+      path: path_str.to_string(),
+      title: note["title"].as_str().unwrap_or("").to_string(),
+      suggestions,
+    });
+  }
+  Ok(results)
+}
+
+// Add approved tags to the note `id`
+#[tauri::command]
+pub fn apply_tags(id: &str, tags: Vec<String>) -> Result<Vec<String>, String> {
+  let path = &note_path(id)?;
+  let mut note = read_note(path)?;
+  let mut current = note_tags(&note);
+  for tag in tags {
+    if !current.contains(&tag) {
+      current.push(tag);
+    }
+  }
+  if let Some(metadata) = note["metadata"].as_object_mut() {
+    metadata.insert("tags".to_string(), json!(current));
+  }
+  write_note(path, &note)?;
+  Ok(current)
+}
+
+// Every tag in the vault with how many notes use it
+#[tauri::command]
+pub fn list_vault_tags() -> Result<BTreeMap<String, usize>, String> {
+  vault_tags()
+}
@@ -0,0 +1,149 @@
+use crate::config::{get_config, VectorDistance, VectorStoreKind};
+use crate::fs::get_app_data_dir;
+use crate::local_store::LocalStore;
+use crate::qdrant::QdrantStore;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+
+// What we store alongside every embedded chunk of a note
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChunkPayload {
+  // Id of the note, stable across moves unlike the path
+  #[serde(default)]
+  pub note_id: String,
+  pub path: String,
+  pub title: String,
+  pub page_id: String,
+  pub chunk: usize,
+  pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorPoint {
+  pub id: String,
+  pub vector: Vec<f32>,
+  pub payload: ChunkPayload,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScoredChunk {
+  pub id: String,
+  pub score: f32,
+  pub payload: ChunkPayload,
+}
+
+// Storage for note embeddings, implemented by the Qdrant client and by the embedded local index
+#[async_trait]
+pub trait VectorStore: Send + Sync {
+  async fn collection_exists(&self, collection: &str) -> Result<bool, String>;
+
+  // Vector size of an existing collection
+  async fn collection_dimension(&self, collection: &str) -> Result<Option<u64>, String>;
+
+  async fn create_collection(
+    &self,
+    collection: &str,
+    dimension: u64,
+    distance: VectorDistance,
+  ) -> Result<(), String>;
+
+  async fn delete_collection(&self, collection: &str) -> Result<(), String>;
+
+  // Insert points, replacing any existing point with the same id
+  async fn upsert(&self, collection: &str, points: Vec<VectorPoint>) -> Result<(), String>;
+
+  // The `limit` points most similar to `vector`, best first, skipping the note `exclude_note`
+  async fn search(
+    &self,
+    collection: &str,
+    vector: &[f32],
+    limit: usize,
+    exclude_note: Option<&str>,
+  ) -> Result<Vec<ScoredChunk>, String>;
+
+  // Every point stored for the note `note_id`, vectors included
+  async fn get_by_note(&self, collection: &str, note_id: &str) -> Result<Vec<VectorPoint>, String>;
+
+  // Remove every point whose payload belongs to the note `note_id`
+  async fn delete_by_note(&self, collection: &str, note_id: &str) -> Result<(), String>;
+}
+
+static VECTOR_STORE: RwLock<Option<Arc<dyn VectorStore>>> = RwLock::new(None);
+
+// The vector store selected in the user config, created on first use
+pub fn get_vector_store() -> Result<Arc<dyn VectorStore>, String> {
+  if let Some(store) = VECTOR_STORE.read().unwrap().as_ref() {
+    return Ok(store.clone());
+  }
+
+  let mut guard = VECTOR_STORE.write().unwrap();
+  if let Some(store) = guard.as_ref() {
+    return Ok(store.clone()); // Someone else initialized it first
+  }
+  let store: Arc<dyn VectorStore> = match get_config()?.vector_store {
+    VectorStoreKind::Qdrant => Arc::new(QdrantStore),
+    VectorStoreKind::Local => Arc::new(LocalStore::new(get_app_data_dir()?.join("vectors"))),
+  };
+  *guard = Some(store.clone());
+  Ok(store)
+}
+
+// Drop the cached store so the next access re-reads the config
+pub fn reset_vector_store() {
+  *VECTOR_STORE.write().unwrap() = None;
+}
+
+// Make sure `collection` exists with vectors of `dimension`, dropping it first when `recreate` is
+// set. An existing collection with a different dimension is left alone and reported as an error,
+// so nothing is ever written into a collection the embedder doesn't match.
+pub async fn ensure_collection(
+  store: &dyn VectorStore,
+  collection: &str,
+  dimension: u64,
+  distance: VectorDistance,
+  recreate: bool,
+) -> Result<(), String> {
+  let mut exists = store.collection_exists(collection).await?;
+
+  if exists && recreate {
+    store.delete_collection(collection).await?;
+    exists = false;
+  }
+
+  if exists {
+    return match store.collection_dimension(collection).await? {
+      Some(existing) if existing != dimension => Err(format!(
+        "Collection '{}' stores {}-dimensional vectors but the embedding model produces {}; re-index with recreate to rebuild it",
+        collection, existing, dimension
+      )),
+      _ => Ok(()),
+    };
+  }
+
+  store
+    .create_collection(collection, dimension, distance)
+    .await
+}
+
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+  a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+  let norms = dot(a, a).sqrt() * dot(b, b).sqrt();
+  if norms == 0.0 {
+    0.0
+  } else {
+    dot(a, b) / norms
+  }
+}
+
+// Scale a vector to unit length so dot products between vectors are cosine similarities
+pub fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+  let norm = dot(&vector, &vector).sqrt();
+  if norm > 0.0 {
+    vector.iter_mut().for_each(|value| *value /= norm);
+  }
+  vector
+}
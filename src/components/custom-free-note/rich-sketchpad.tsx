@@ -74,6 +74,7 @@ const parseAndValidateLines = (
               ), // Ensure points are numbers
               color: typeof line.color === "string" ? line.color : undefined,
               width: typeof line.width === "number" ? line.width : 5,
+              id: typeof line.id === "string" ? line.id : undefined,
             };
           })
           .filter((line): line is LineType => line !== null);
@@ -107,6 +108,7 @@ const parseAndValidateLines = (
           ),
           color: typeof line.color === "string" ? line.color : undefined,
           width: typeof line.width === "number" ? line.width : 5,
+          id: typeof line.id === "string" ? line.id : undefined,
         };
       })
       .filter((line): line is LineType => line !== null);
@@ -299,7 +301,7 @@ export const RichSketchpadImpl = forwardRef<any, StandaloneRichSketchpadProps>(
       // Invoke Rust backend to save the updated pages array
       // Send lines as an array (which backend should handle)
       invoke("update_freenote_content", {
-        path: note.id,
+        id: note.id,
         pageId: newPage.id,
         content: newPage.content,
         lines: newPage.lines, // Send as empty array
@@ -349,7 +351,7 @@ export const RichSketchpadImpl = forwardRef<any, StandaloneRichSketchpadProps>(
         // 4. --- Backend Call ---
         // Always send lines as stringified JSON for consistency
         invoke("update_freenote_content", {
-          path: note.id,
+          id: note.id,
           pageId: currentPageForBackend.id,
           content: currentPageForBackend.content,
           lines: JSON.stringify(currentPageForBackend.lines || []), // Ensure array before stringify
@@ -482,6 +484,8 @@ export const RichSketchpadImpl = forwardRef<any, StandaloneRichSketchpadProps>(
 
       if (pos) {
         const newLine: LineType = {
+          // Lets sync merge strokes drawn on different devices
+          id: crypto.randomUUID(),
           tool,
           points: [pos.x, pos.y],
           color: tool === "brush" ? color : undefined, // Eraser doesn't need color
@@ -654,7 +658,7 @@ export const RichSketchpadImpl = forwardRef<any, StandaloneRichSketchpadProps>(
                     if (localTitle !== note?.title) {
                       updateNote(note.id, { title: localTitle });
                       invoke("update_title", {
-                        path: note.id,
+                        id: note.id,
                         newTitle: localTitle,
                       }).catch((err) =>
                         console.error("Failed to update title:", err),
@@ -670,7 +674,7 @@ export const RichSketchpadImpl = forwardRef<any, StandaloneRichSketchpadProps>(
                   if (note && localTitle !== note.title) {
                     updateNote(note.id, { title: localTitle });
                     invoke("update_title", {
-                      path: note.id,
+                      id: note.id,
                       newTitle: localTitle,
                     }).catch((err) =>
                       console.error("Failed to update title:", err),
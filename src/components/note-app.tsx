@@ -96,12 +96,12 @@ export default function NoteApp() {
 
   const createNote = useCallback(async (note_type: string) => {
     try {
-      const filePath = await invoke("create_new_note", {
+      const noteId = await invoke("create_new_note", {
         title: "Untitled",
         noteType: note_type,
       });
 
-      console.log("Created note at path:", filePath);
+      console.log("Created note:", noteId);
 
       // Refresh notes list to include the new note
       const updatedNotes: string[] = await invoke("gather_notes");
@@ -112,9 +112,9 @@ export default function NoteApp() {
 
       // Set the new note as active - This assumes the last note in the array is the new one
       // You might need a better way to identify the new note
-      if (formattedNotes.length > 0 && typeof filePath === "string") {
-        // Find the note with the matching path
-        const newNote = formattedNotes.find((note) => note.id === filePath);
+      if (formattedNotes.length > 0 && typeof noteId === "string") {
+        // Find the note with the matching id
+        const newNote = formattedNotes.find((note) => note.id === noteId);
         if (newNote) {
           setActiveNoteId(newNote.id);
         }
@@ -144,9 +144,8 @@ export default function NoteApp() {
         }
 
         // Delete from filesystem
-        await invoke("delete_path", {
-          path: path,
-          recursive: false,
+        await invoke("delete_note", {
+          id: path,
         });
 
         // Refresh notes list
@@ -238,7 +237,7 @@ export default function NoteApp() {
           if (note?.metadata.note_type === "notebook") {
             const htmlContent = editorRef.current.getHTML();
             invoke("update_notebook_content", {
-              path: note.id,
+              id: note.id,
               pageId: note.pages[0].id,
               content: htmlContent,
             });
@@ -249,7 +248,7 @@ export default function NoteApp() {
           } else if (note?.metadata.note_type === "freenote") {
             const htmlContent = editorRef.current.getHTML();
             invoke("update_freenote_content", {
-              path: note.id,
+              id: note.id,
               pageId: note.pages[0].id,
               content: htmlContent.content,
               lines: JSON.stringify(htmlContent.lines),
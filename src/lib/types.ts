@@ -55,6 +55,8 @@ export interface StandaloneRichSketchpadProps {
 }
 
 export interface Line {
+  // Missing on strokes drawn before strokes had ids
+  id?: string;
   tool: "brush" | "eraser";
   points: number[];
   color: string | undefined;